@@ -5,70 +5,39 @@
 //! Advent of Code Day 7.  
 //! Bart Massey 2019
 
-use aoc::Intcode;
+use aoc::{Intcode, NetStop, Network, Topology};
 
-/// Run an amplifier chain in feedforward mode. Each
+/// Set up an amplifier chain with the given topology. Each
 /// amplifier runs the given program and uses its given
-/// setting.
-fn chain_output(prog: &Intcode, settings: &[i64]) -> i64 {
-    // This "input" will be set by previous outputs.
-    let mut input = 0;
-    for s in settings.as_ref().iter() {
-        let mut prog = prog.clone().with_inputs(vec![*s, input]);
-        let outputs = prog.collect_outputs();
-        assert_eq!(1, outputs.len());
-        input = outputs[0];
-    }
-    input
-}
-
-/// Run an amplifier chain in feedback mode. Each amplifier
-/// runs the given program and uses its given setting.
-fn chain_output_feedback(prog: &Intcode, settings: &[i64]) -> i64 {
-    // Set up the programs with their parameters.
-    let mut progs: Vec<Intcode> = settings
-        .as_ref()
+/// setting. The first amplifier gets an initial input of 0.
+fn chain(prog: &Intcode, settings: &[i64], topology: Topology) -> Network {
+    let progs = settings
         .iter()
-        .map(|s| {
-            let mut prog = prog.clone();
-            prog.add_input(*s);
-            prog
-        })
+        .map(|s| prog.clone().with_inputs(vec![*s]))
         .collect();
+    let mut net = Network::new(progs, topology);
+    net.add_input(0, 0);
+    net
+}
 
-    use aoc::Terminus::*;
-    // This "input" will be set by previous outputs.
-    let mut input = 0;
-
-    // Run the chain until completion. We assume that the
-    // program is chainable: takes one input, produces one
-    // output. We assume that the first amplifier will halt
-    // first and the others follow: we will check this.
-    'finished: loop {
-        for prog in &mut progs {
-            loop {
-                match prog.run() {
-                    Halted => break 'finished,
-                    NeedInput => prog.add_input(input),
-                    HaveOutput(out) => {
-                        input = out;
-                        break;
-                    }
-                }
-            }
-        }
-    }
-
-    // Run the other amplifiers to make sure they're about
-    // to stop.
-    for prog in &mut progs[1..] {
-        match prog.run() {
-            Halted => (),
-            _ => panic!("subsequent amplifier failed to halt"),
-        }
-    }
+/// Run an amplifier chain in feedforward mode.
+fn chain_output(prog: &Intcode, settings: &[i64]) -> i64 {
+    let report = chain(prog, settings, Topology::Pipeline).run();
+    assert_eq!(NetStop::Halted, report.stop);
+    assert_eq!(1, report.outputs.len());
+    report.outputs[0]
+}
 
-    input
+/// Run an amplifier chain in feedback mode.
+fn chain_output_feedback(prog: &Intcode, settings: &[i64]) -> i64 {
+    // We assume that the program is chainable: takes one
+    // input, produces one output. We assume that the first
+    // amplifier will halt first and the others follow: we
+    // check this.
+    let report = chain(prog, settings, Topology::Ring).run();
+    assert_eq!(NetStop::Halted, report.stop);
+    assert_eq!(Some(0), report.first_halt);
+    *report.outputs.last().expect("no amplifier output")
 }
 
 /// Try all possible parameter settings, running the given
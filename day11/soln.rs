@@ -23,12 +23,14 @@ fn paint(mut prog: Intcode, mut default_color: i64) -> Map {
             HaveOutput(q) => q,
             Halted => break,
             NeedInput => panic!("unexpected input request"),
+            OutOfFuel => unreachable!("no fuel limit"),
         };
         map.insert(posn, new_color);
         let rot = match prog.run() {
             HaveOutput(q) => q,
             Halted => panic!("unexpected halt during output"),
             NeedInput => panic!("unexpected input request"),
+            OutOfFuel => unreachable!("no fuel limit"),
         };
         let rot = match rot {
             0 => Rot::CCW,
@@ -39,6 +39,7 @@ fn get_output(prog: &mut Intcode) -> i64 {
         HaveOutput(q) => q,
         Halted => panic!("unexpected halt"),
         NeedInput => panic!("unexpected input request"),
+        OutOfFuel => unreachable!("no fuel limit"),
     }
 }
 
@@ -50,6 +51,7 @@ fn paint(mut prog: Intcode) -> Map {
             HaveOutput(q) => q,
             Halted => return map,
             NeedInput => panic!("unexpected ask for input"),
+            OutOfFuel => unreachable!("no fuel limit"),
         };
         let y = get_output(&mut prog);
         let t = get_output(&mut prog);
@@ -84,6 +86,7 @@ fn play(mut prog: Intcode, delay: Option<Duration>) -> i64 {
                 prog.add_input(dirn);
                 continue;
             }
+            OutOfFuel => unreachable!("no fuel limit"),
         };
         let y = get_output(&mut prog);
         let t = get_output(&mut prog);
@@ -45,6 +45,7 @@ fn move_robot(prog: &mut Intcode, dirn: Dirn, goal: bool) -> Status {
     match prog.run() {
         Halted => panic!("unexpected robot halt"),
         NeedInput => panic!("unexpected extra input"),
+        OutOfFuel => unreachable!("no fuel limit"),
         HaveOutput(q) => match q {
             0 => Blocked,
             1 => Moved,
@@ -0,0 +1,276 @@
+// This program is licensed under the "MIT License".
+// Please see the file LICENSE in this distribution
+// for license terms.
+
+//! Intcode engine benchmarks.
+//!
+//! Usage: `intcode-bench [reps]`. Runs each workload `reps`
+//! times (default 5) on the original interpreter and with
+//! each execution engine, and reports the best time for
+//! each, along with each engine's time relative to the
+//! original. The original is taken from git when building:
+//! see `build.rs`. Built outside a git checkout, the
+//! benchmark goes without it. Then times loads and stores
+//! through flat and paged `Memory` against the same through
+//! a plain `Vec`. Build with `--release` for meaningful
+//! numbers.
+
+use std::time::{Duration, Instant};
+
+use aoc::{Engine, Intcode, Memory, Terminus};
+
+/// The original interpreter, taken from git by `build.rs`,
+/// as a yardstick for the engines.
+#[cfg(baseline)]
+#[allow(dead_code, clippy::all)]
+mod baseline {
+    include!(concat!(env!("OUT_DIR"), "/baseline.rs"));
+}
+
+/// What runs a workload: the baseline interpreter, or an
+/// `Intcode` with the given engine.
+#[derive(Clone, Copy)]
+enum Runner {
+    #[cfg(baseline)]
+    Baseline,
+    Engine(Engine),
+}
+
+/// A loop counting down from a million.
+fn countdown(runner: Runner) -> i64 {
+    let prog = aoc::assemble(
+        "
+        loop:   add [n], #-1, [n]
+                add [total], [n], [total]
+                jt [n], #loop
+                out [total]
+                halt
+        n:      .data 1000000
+        total:  .data 0
+        ",
+    )
+    .unwrap();
+    match runner {
+        #[cfg(baseline)]
+        Runner::Baseline => {
+            baseline::Intcode::new(prog).collect_outputs()[0]
+        }
+        Runner::Engine(engine) => {
+            let mut ic = Intcode::new(prog).with_engine(engine);
+            ic.collect_outputs()[0]
+        }
+    }
+}
+
+/// A Day 2 style search: try every noun and verb on a
+/// straight-line program, counting the results divisible
+/// by 7.
+fn search(runner: Runner) -> i64 {
+    // A straight-line program of adds from pseudo-random
+    // addresses, accumulating in address 3.
+    let mut prog = vec![1, 0, 0, 3];
+    let mut seed: u64 = 12345;
+    for _ in 0..40 {
+        seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1);
+        let src = (seed >> 33) as i64 % 160;
+        prog.extend_from_slice(&[1, 3, src, 3]);
+    }
+    prog.push(99);
+    // Run the program on the given noun and verb, returning
+    // the result.
+    let mut try_pair: Box<dyn FnMut(i64, i64) -> i64> = match runner {
+        #[cfg(baseline)]
+        Runner::Baseline => {
+            let base = baseline::Intcode::new(prog);
+            Box::new(move |noun, verb| {
+                let mut ic = base.clone();
+                ic.poke(1, noun);
+                ic.poke(2, verb);
+                ic.run();
+                ic.peek(3)
+            })
+        }
+        Runner::Engine(engine) => {
+            let mut base = Intcode::new(prog).with_engine(engine);
+            base.warm_cache();
+            Box::new(move |noun, verb| {
+                let mut ic = base.clone();
+                ic.poke(1, noun);
+                ic.poke(2, verb);
+                assert_eq!(ic.run(), Terminus::Halted);
+                ic.peek(3)
+            })
+        }
+    };
+    let mut hits = 0;
+    for noun in 0..100 {
+        for verb in 0..100 {
+            if try_pair(noun, verb) % 7 == 0 {
+                hits += 1;
+            }
+        }
+    }
+    hits
+}
+
+/// Best of `reps` runs of the given workload.
+fn time(
+    reps: usize,
+    f: fn(Runner) -> i64,
+    runner: Runner,
+) -> (i64, Duration) {
+    best_of(reps, || f(runner))
+}
+
+/// Best of `reps` runs of the given workload on the
+/// original interpreter, if this was built with it.
+#[cfg(baseline)]
+fn time_baseline(
+    reps: usize,
+    f: fn(Runner) -> i64,
+) -> Option<(i64, Duration)> {
+    Some(time(reps, f, Runner::Baseline))
+}
+
+#[cfg(not(baseline))]
+fn time_baseline(
+    _reps: usize,
+    _f: fn(Runner) -> i64,
+) -> Option<(i64, Duration)> {
+    None
+}
+
+/// Words of memory for the memory benchmark: a few pages,
+/// as for a typical program.
+const MEMORY_WORDS: usize = 4096;
+
+/// Increment words of memory at pseudo-random addresses, as
+/// an interpreter loads and stores them, through the given
+/// accessors. Returns a checksum.
+fn bump<M>(
+    mem: &mut M,
+    get: fn(&M, usize) -> i64,
+    set: fn(&mut M, usize, i64),
+) -> i64 {
+    let mut seed: u64 = 12345;
+    for _ in 0..3_000_000 {
+        seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1);
+        let addr = (seed >> 33) as usize % MEMORY_WORDS;
+        let val = get(mem, addr);
+        set(mem, addr, val + 1);
+    }
+    (0..MEMORY_WORDS)
+        .map(|addr| get(mem, addr) * addr as i64)
+        .sum()
+}
+
+/// Best time of `reps` runs of `bump()` on a `Vec`.
+fn bump_vec(reps: usize) -> (i64, Duration) {
+    let get = |v: &Vec<i64>, addr| v[addr];
+    let set = |v: &mut Vec<i64>, addr, val| v[addr] = val;
+    best_of(reps, || bump(&mut vec![0; MEMORY_WORDS], get, set))
+}
+
+/// Best time of `reps` runs of `bump()` on a `Memory`,
+/// flat or paged.
+fn bump_memory(reps: usize, paged: bool) -> (i64, Duration) {
+    let get = |m: &Memory, addr| m.get(addr).unwrap();
+    let set = |m: &mut Memory, addr, val| m.set(addr, val);
+    best_of(reps, || {
+        let mut m = Memory::from(vec![0; MEMORY_WORDS]);
+        if paged {
+            m.make_paged();
+        }
+        // Share the pages, as a snapshot or clone would, so
+        // that the first store to each must copy it.
+        let _shared = m.clone();
+        bump(&mut m, get, set)
+    })
+}
+
+/// Best time of `reps` runs of the given function.
+fn best_of<F: FnMut() -> i64>(
+    reps: usize,
+    mut f: F,
+) -> (i64, Duration) {
+    let mut best = Duration::from_secs(u64::MAX);
+    let mut result = 0;
+    for _ in 0..reps {
+        let start = Instant::now();
+        result = f();
+        best = best.min(start.elapsed());
+    }
+    (result, best)
+}
+
+fn main() {
+    let args = aoc::get_args();
+    let reps = match args.first() {
+        Some(n) => n.parse().expect("bad rep count"),
+        None => 5,
+    };
+    type Workload = (&'static str, fn(Runner) -> i64);
+    let workloads: [Workload; 2] =
+        [("countdown", countdown), ("search", search)];
+    println!(
+        "{:12} {:>12} {:>12} {:>8} {:>12} {:>8}",
+        "workload",
+        "baseline",
+        "interp",
+        "vs base",
+        "predecoded",
+        "vs base"
+    );
+    let ms = |t: Duration| t.as_secs_f64() * 1000.0;
+    for &(name, f) in &workloads {
+        let base = time_baseline(reps, f);
+        let (r1, t1) =
+            time(reps, f, Runner::Engine(Engine::Interpreter));
+        let (r2, t2) =
+            time(reps, f, Runner::Engine(Engine::Predecoded));
+        assert_eq!(r1, r2, "{}: engines disagree", name);
+        if let Some((r0, _)) = base {
+            assert_eq!(r0, r1, "{}: baseline disagrees", name);
+        }
+        let t0 = base.map_or("-".to_string(), |(_, t0)| {
+            format!("{:.2}ms", ms(t0))
+        });
+        let vs = |t: Duration| {
+            base.map_or("-".to_string(), |(_, t0)| {
+                format!("{:.2}x", ms(t) / ms(t0))
+            })
+        };
+        println!(
+            "{:12} {:>12} {:>10.2}ms {:>8} {:>10.2}ms {:>8}",
+            name,
+            t0,
+            ms(t1),
+            vs(t1),
+            ms(t2),
+            vs(t2),
+        );
+    }
+
+    let (r0, t0) = bump_vec(reps);
+    let (r1, t1) = bump_memory(reps, false);
+    let (r2, t2) = bump_memory(reps, true);
+    assert_eq!(r1, r0, "memory: flat Memory and Vec disagree");
+    assert_eq!(r2, r0, "memory: paged Memory and Vec disagree");
+    println!(
+        "\n{:12} {:>12} {:>12} {:>8} {:>12} {:>8}",
+        "workload", "Vec", "flat", "vs Vec", "paged", "vs Vec"
+    );
+    println!(
+        "{:12} {:>10.2}ms {:>10.2}ms {:>7.2}x {:>10.2}ms {:>7.2}x",
+        "memory",
+        ms(t0),
+        ms(t1),
+        ms(t1) / ms(t0),
+        ms(t2),
+        ms(t2) / ms(t0),
+    );
+}
@@ -0,0 +1,173 @@
+// This program is licensed under the "MIT License".
+// Please see the file LICENSE in this distribution
+// for license terms.
+
+//! Interactive Intcode debugger.
+//!
+//! Usage: `intcode-debug prog.txt`. Type `h` at the prompt
+//! for a list of commands.
+
+use std::io::{stdin, stdout, BufRead, Write};
+
+use aoc::{Debugger, Intcode, Stop};
+
+const HELP: &str = "\
+s [n]         step n instructions (default 1)
+c             continue until suspension, breakpoint or watch
+bs [n]        step back n instructions (default 1)
+bc            step back to the previous suspension
+b addr        set breakpoint
+d addr        delete breakpoint
+w addr        watch address for stores
+u addr        unwatch address
+i val ...     queue input values
+r             show registers and input queue
+x addr [n]    examine n words of memory (default 1)
+l [addr] [n]  list n instructions (default ip, 10)
+p             show breakpoints and watches
+q             quit";
+
+/// Load a program file, as text or a binary image.
+fn load(path: &str) -> Intcode {
+    Intcode::from_path(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    })
+}
+
+/// Parse command arguments as numbers, or return `None`.
+fn numbers<T: std::str::FromStr>(args: &[&str]) -> Option<Vec<T>> {
+    args.iter().map(|a| a.parse().ok()).collect()
+}
+
+/// Print `n` instructions of disassembly starting at
+/// `addr`.
+fn list(db: &Debugger, mut addr: usize, n: usize) {
+    let machine = db.machine();
+    for _ in 0..n {
+        if addr >= machine.memory().len() {
+            break;
+        }
+        let mark = if addr == machine.ip() { '>' } else { ' ' };
+        match machine.decode(addr) {
+            Some(insn) => {
+                println!("{}{:6}  {}", mark, addr, insn);
+                addr += insn.size();
+            }
+            None => {
+                println!(
+                    "{}{:6}  .data {}",
+                    mark,
+                    addr,
+                    db.value(addr)
+                );
+                addr += 1;
+            }
+        }
+    }
+}
+
+/// Report why the machine stopped and where it is.
+fn report(db: &Debugger, stop: &Stop) {
+    println!("{}", stop);
+    println!("{}", db.current_insn());
+}
+
+fn main() {
+    let args = aoc::get_args();
+    if args.len() != 1 {
+        eprintln!("usage: intcode-debug prog.txt");
+        std::process::exit(1);
+    }
+    let mut db = Debugger::new(load(&args[0]).with_journal());
+    println!("{}", db.current_insn());
+
+    let stdin = stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(icdb) ");
+        stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(line) => line.expect("could not read command"),
+            None => break,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let (cmd, rest) = (words[0], &words[1..]);
+        let nums: Option<Vec<usize>> = numbers(rest);
+        match (cmd, nums.as_deref()) {
+            ("s", Some(n)) if n.len() <= 1 => {
+                let n = n.first().cloned().unwrap_or(1);
+                let mut stop = Stop::Stepped;
+                for _ in 0..n {
+                    stop = db.step();
+                    if stop != Stop::Stepped {
+                        break;
+                    }
+                }
+                report(&db, &stop);
+            }
+            ("c", Some([])) => {
+                let stop = db.cont();
+                report(&db, &stop);
+            }
+            ("bs", Some(n)) if n.len() <= 1 => {
+                let n = n.first().cloned().unwrap_or(1);
+                let undone = db.machine_mut().rewind(n);
+                println!("stepped back {}", undone);
+                println!("{}", db.current_insn());
+            }
+            ("bc", Some([])) => {
+                let undone = db.machine_mut().rewind_to_suspension();
+                println!("stepped back {}", undone);
+                println!("{}", db.current_insn());
+            }
+            ("b", Some(&[addr])) => db.add_breakpoint(addr),
+            ("d", Some(&[addr])) => {
+                if !db.remove_breakpoint(addr) {
+                    println!("no breakpoint at {}", addr);
+                }
+            }
+            ("w", Some(&[addr])) => db.add_watch(addr),
+            ("u", Some(&[addr])) => {
+                if !db.remove_watch(addr) {
+                    println!("no watch on {}", addr);
+                }
+            }
+            ("i", _) => match numbers::<i64>(rest) {
+                Some(vals) if !vals.is_empty() => {
+                    for v in vals {
+                        db.machine_mut().add_input(v);
+                    }
+                }
+                _ => println!("i: expected input values"),
+            },
+            ("r", Some([])) => println!("{}", db.registers()),
+            ("x", Some(n)) if !n.is_empty() && n.len() <= 2 => {
+                let count = n.get(1).cloned().unwrap_or(1);
+                for addr in n[0]..n[0].saturating_add(count) {
+                    println!("{:6}  {}", addr, db.value(addr));
+                }
+            }
+            ("l", Some(n)) if n.len() <= 2 => {
+                let addr = n
+                    .first()
+                    .cloned()
+                    .unwrap_or_else(|| db.machine().ip());
+                let count = n.get(1).cloned().unwrap_or(10);
+                list(&db, addr, count);
+            }
+            ("p", Some([])) => {
+                let bs: Vec<usize> = db.breakpoints().collect();
+                let ws: Vec<usize> = db.watches().collect();
+                println!("breakpoints: {:?}", bs);
+                println!("watches: {:?}", ws);
+            }
+            ("q", Some([])) => break,
+            ("h", _) | ("?", _) => println!("{}", HELP),
+            _ => println!("bad command: {} (h for help)", line.trim()),
+        }
+    }
+}
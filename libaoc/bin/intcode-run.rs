@@ -0,0 +1,220 @@
+// This program is licensed under the "MIT License".
+// Please see the file LICENSE in this distribution
+// for license terms.
+
+//! Run an Intcode program by hand.
+//!
+//! Usage: `intcode-run [-a] [-s script] [-t transcript]
+//! [-p profile] prog.txt`. Outputs are printed as they
+//! arrive. When the program needs input, a line is read
+//! from the script file if one was given and not yet used
+//! up, and from `stdin` otherwise.
+//!
+//! In numeric mode (the default) each output is printed on
+//! a line of its own and input lines are numbers separated
+//! by spaces or commas. In ASCII mode (`-a`) outputs are
+//! printed as characters, except that non-ASCII outputs
+//! are printed in decimal on a line of their own, and each
+//! input line is supplied as characters followed by a
+//! newline. Input lines starting with `:` are commands:
+//! `:ascii` and `:numeric` switch modes and `:quit` quits.
+//!
+//! With `-t`, everything printed and typed is also written
+//! to the transcript file. With `-p`, the run is profiled
+//! and an annotated listing is written to the profile file
+//! when it ends.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, Write};
+
+use aoc::{Intcode, Terminus};
+
+const USAGE: &str = "usage: intcode-run [-a] [-s script] \
+                     [-t transcript] [-p profile] prog.txt";
+
+/// Load a program file, as text or a binary image.
+fn load(path: &str) -> Intcode {
+    Intcode::from_path(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    })
+}
+
+/// Terminal state: output mode, where input comes from and
+/// where the transcript goes.
+struct Session {
+    ascii: bool,
+    // The last text printed ended a line.
+    line_start: bool,
+    script: VecDeque<String>,
+    transcript: Option<File>,
+}
+
+impl Session {
+    /// Print the given text, recording it in the
+    /// transcript.
+    fn emit(&mut self, text: &str) {
+        print!("{}", text);
+        stdout().flush().unwrap();
+        self.record(text);
+        if !text.is_empty() {
+            self.line_start = text.ends_with('\n');
+        }
+    }
+
+    /// Record the given text in the transcript only.
+    fn record(&mut self, text: &str) {
+        if let Some(ref mut t) = self.transcript {
+            t.write_all(text.as_bytes())
+                .expect("could not write transcript");
+        }
+    }
+
+    /// Print an output in the current mode.
+    fn output(&mut self, val: i64) {
+        if self.ascii && aoc::is_ascii_output(val) {
+            self.emit(&(val as u8 as char).to_string());
+        } else if self.ascii && !self.line_start {
+            self.emit(&format!("\n{}\n", val));
+        } else {
+            self.emit(&format!("{}\n", val));
+        }
+    }
+
+    /// Get the next input line, without its newline. Script
+    /// lines are echoed as if typed.
+    fn next_line(&mut self) -> Option<String> {
+        if let Some(line) = self.script.pop_front() {
+            self.emit(&format!("{}\n", line));
+            return Some(line);
+        }
+        let mut line = String::new();
+        let nread =
+            stdin().read_line(&mut line).expect("could not read input");
+        if nread == 0 {
+            return None;
+        }
+        self.record(&line);
+        Some(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+    }
+
+    /// Read lines until one supplies some input, and give
+    /// it to the machine. Return false if the user is done.
+    fn input(&mut self, ic: &mut Intcode) -> bool {
+        loop {
+            if !self.ascii {
+                self.emit("? ");
+            }
+            let line = match self.next_line() {
+                Some(line) => line,
+                None => return false,
+            };
+            match line.trim() {
+                ":ascii" => self.ascii = true,
+                ":numeric" => self.ascii = false,
+                ":quit" => return false,
+                cmd if cmd.starts_with(':') => {
+                    self.emit(&format!("unknown command {}\n", cmd))
+                }
+                _ if self.ascii => {
+                    ic.add_ascii_input(&line);
+                    ic.add_ascii_input("\n");
+                    return true;
+                }
+                words => {
+                    let vals: Result<Vec<i64>, _> = words
+                        .split(|c: char| c == ',' || c.is_whitespace())
+                        .filter(|w| !w.is_empty())
+                        .map(|w| w.parse())
+                        .collect();
+                    match vals {
+                        Ok(ref vals) if !vals.is_empty() => {
+                            for &v in vals {
+                                ic.add_input(v);
+                            }
+                            return true;
+                        }
+                        _ => self.emit("expected numbers\n"),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut session = Session {
+        ascii: false,
+        line_start: true,
+        script: VecDeque::new(),
+        transcript: None,
+    };
+    let mut prog = None;
+    let mut profile = None;
+    let mut args = aoc::get_args().into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-a" => session.ascii = true,
+            "-s" => {
+                let path = args.next().expect(USAGE);
+                let file =
+                    File::open(path).expect("could not open script");
+                session.script = std::io::BufReader::new(file)
+                    .lines()
+                    .collect::<Result<_, _>>()
+                    .expect("could not read script");
+            }
+            "-t" => {
+                let path = args.next().expect(USAGE);
+                let file = File::create(path)
+                    .expect("could not create transcript");
+                session.transcript = Some(file);
+            }
+            "-p" => profile = Some(args.next().expect(USAGE)),
+            _ if prog.is_none() && !arg.starts_with('-') => {
+                prog = Some(load(&arg))
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(1);
+            }
+        }
+    }
+    let mut ic = match prog {
+        Some(ic) => ic,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+    if profile.is_some() {
+        ic.start_profile();
+    }
+
+    let status = loop {
+        match ic.try_run() {
+            Ok(Terminus::HaveOutput(val)) => session.output(val),
+            Ok(Terminus::NeedInput) => {
+                if !session.input(&mut ic) {
+                    break 0;
+                }
+            }
+            Ok(Terminus::Halted) => {
+                session.emit("[halted]\n");
+                break 0;
+            }
+            Ok(Terminus::OutOfFuel) => unreachable!("no fuel limit"),
+            Err(e) => {
+                session.emit(&format!("[fault: {}]\n", e));
+                break 1;
+            }
+        }
+    };
+
+    if let Some(path) = profile {
+        let listing = ic.profile_listing().expect("not profiling");
+        std::fs::write(path, listing).expect("could not write profile");
+    }
+    std::process::exit(status);
+}
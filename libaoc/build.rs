@@ -0,0 +1,43 @@
+// This program is licensed under the "MIT License".
+// Please see the file LICENSE in this distribution
+// for license terms.
+
+//! Build script. Extracts the original Intcode interpreter
+//! from git for `intcode-bench` to measure the engines
+//! against. Outside a git checkout the benchmark goes
+//! without it.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// The original interpreter, as of the commit before the
+/// engines were added.
+const BASELINE: &str =
+    "f89672e4fabb783203024ee7dea037da0c7cc463:libaoc/intcode.rs";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rustc-check-cfg=cfg(baseline)");
+    let source = Command::new("git")
+        .args(["show", BASELINE])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok());
+    let source = match source {
+        Some(source) => source,
+        None => return,
+    };
+    // Inner doc comments can't be included.
+    let source: String = source
+        .lines()
+        .filter(|line| !line.starts_with("//!"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let out = env::var("OUT_DIR").expect("no OUT_DIR");
+    fs::write(Path::new(&out).join("baseline.rs"), source)
+        .expect("could not write baseline");
+    println!("cargo:rustc-cfg=baseline");
+}
@@ -4,10 +4,122 @@
 //! of being highly self-modifying code. Use
 //! `Intcode::read()` to load a program from `stdin`;
 //! `input()` to load input values; `run()` to run until
-//! halted; `output()` to get the output value.
+//! halted; `output()` to get the output value. Use
+//! `try_run()` instead of `run()` to get an `IntcodeError`
+//! rather than a panic when the program faults.
+//!
+//! Submodules provide supporting machinery:
+//!
+//! * `asm`: assembler for writing Intcode programs by
+//!   mnemonic rather than by number.
+//! * `disasm`: disassembler turning programs back into
+//!   text.
+//! * `debug`: breakpoints and watchpoints.
+//! * `tracer`: per-instruction execution logs.
+//! * `memory`: machine memory, flat or copy-on-write
+//!   paged, which makes clones cheap and far-flung
+//!   addresses affordable.
+//! * `snapshot`: saving and restoring machine state.
+//! * `io`: pluggable input sources and output sinks for
+//!   running without suspending on every I/O operation.
+//! * `engine`: a faster execution engine that caches
+//!   decoded instructions.
+//! * `ascii`: text interfaces for programs that talk
+//!   ASCII.
+//! * `network`: many machines connected in pipelines,
+//!   rings or packet networks.
+//! * `arith`: arithmetic overflow policies and big
+//!   integers.
+//! * `profile`: execution counts and coverage listings.
+//! * `cfg`: static control-flow graphs, with Graphviz
+//!   export.
+//! * `journal`: rewinding and replaying execution.
+//! * `lang`: compiler for a small structured language.
+//! * `optimize`: peephole optimization, checked by running
+//!   programs side by side.
+//! * `symbolic`: symbolic execution, solving for the inputs
+//!   that give a wanted result.
+//! * `load`: loading programs from text, with error
+//!   positions, or from binary images.
+//! * `spawn`: running machines on threads of their own,
+//!   with channel I/O and cancellation.
+//! * `isa`: instruction sets of earlier days, and
+//!   user-defined opcodes.
+//! * `diff`: differences between machine states.
+
+pub mod asm;
+pub use self::asm::*;
+
+pub mod disasm;
+pub use self::disasm::*;
+
+pub mod debug;
+pub use self::debug::*;
+
+pub mod tracer;
+pub use self::tracer::*;
+
+pub mod memory;
+pub use self::memory::*;
+
+pub mod snapshot;
+pub use self::snapshot::*;
+
+pub mod io;
+pub use self::io::*;
+
+pub mod engine;
+pub use self::engine::*;
+
+pub mod ascii;
+pub use self::ascii::*;
+
+pub mod network;
+pub use self::network::*;
+
+pub mod arith;
+pub use self::arith::*;
+
+pub mod profile;
+pub use self::profile::*;
+
+pub mod cfg;
+pub use self::cfg::*;
+
+pub mod journal;
+pub use self::journal::*;
+
+pub mod lang;
+pub use self::lang::*;
+
+pub mod optimize;
+pub use self::optimize::*;
+
+pub mod symbolic;
+pub use self::symbolic::*;
+
+pub mod load;
+pub use self::load::*;
+
+pub mod spawn;
+pub use self::spawn::*;
+
+pub mod isa;
+pub use self::isa::*;
+
+pub mod diff;
+pub use self::diff::*;
+
+#[cfg(test)]
+mod fuzz;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
 
 // Possible opcodes.
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Opcode {
     Add = 0,
     Mul = 1,
@@ -22,28 +134,93 @@ enum Opcode {
 }
 
 impl Opcode {
-    // Make an opcode from a numeric code, checking for
-    // validity.
-    fn new(code: usize) -> Self {
+    // Make an opcode from a numeric code, or `None` if the
+    // code is not a legal opcode.
+    fn try_new(code: usize) -> Option<Self> {
         use Opcode::*;
-        if code == 99 {
-            return Halt;
-        }
-        let codes = [
-            Add,
-            Mul,
-            Input,
-            Output,
-            JumpIfTrue,
-            JumpIfFalse,
-            LessThan,
-            Equals,
-            RBO,
-        ];
-        if code == 0 || code > codes.len() {
-            panic!("illegal opcode {}", code);
-        }
-        codes[code - 1]
+        match code {
+            1 => Some(Add),
+            2 => Some(Mul),
+            3 => Some(Input),
+            4 => Some(Output),
+            5 => Some(JumpIfTrue),
+            6 => Some(JumpIfFalse),
+            7 => Some(LessThan),
+            8 => Some(Equals),
+            9 => Some(RBO),
+            99 => Some(Halt),
+            _ => None,
+        }
+    }
+
+    // All the opcodes, in numeric order.
+    const ALL: [Opcode; 10] = [
+        Opcode::Add,
+        Opcode::Mul,
+        Opcode::Input,
+        Opcode::Output,
+        Opcode::JumpIfTrue,
+        Opcode::JumpIfFalse,
+        Opcode::LessThan,
+        Opcode::Equals,
+        Opcode::RBO,
+        Opcode::Halt,
+    ];
+
+    // The numeric code for this opcode, as it appears in
+    // the low two digits of an instruction.
+    fn code(self) -> i64 {
+        match self {
+            Opcode::Halt => 99,
+            Opcode::RBO => 9,
+            op => op as i64 + 1,
+        }
+    }
+
+    // The assembler mnemonic for this opcode.
+    fn mnemonic(self) -> &'static str {
+        use Opcode::*;
+        match self {
+            Add => "add",
+            Mul => "mul",
+            Input => "in",
+            Output => "out",
+            JumpIfTrue => "jt",
+            JumpIfFalse => "jf",
+            LessThan => "lt",
+            Equals => "eq",
+            Halt => "halt",
+            RBO => "rbo",
+        }
+    }
+
+    // Find the opcode with the given assembler mnemonic.
+    fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|op| op.mnemonic() == mnemonic)
+    }
+
+    // Number of operands taken by this opcode.
+    fn nopnds(self) -> usize {
+        use Opcode::*;
+        match self {
+            Add | Mul | LessThan | Equals => 3,
+            JumpIfTrue | JumpIfFalse => 2,
+            Input | Output | RBO => 1,
+            Halt => 0,
+        }
+    }
+
+    // Index of the operand this opcode stores to, if any.
+    fn store_opnd(self) -> Option<usize> {
+        use Opcode::*;
+        match self {
+            Add | Mul | LessThan | Equals => Some(2),
+            Input => Some(0),
+            _ => None,
+        }
     }
 }
 
@@ -56,21 +233,27 @@ enum OpndMode {
 }
 
 impl OpndMode {
-    // Make a new operand mode from a number, checking for
-    // validity.
-    fn new(mode: usize) -> Self {
-        let modes = [Self::Pos, Self::Imm, Self::Rel];
-        if mode > modes.len() {
-            panic!("illegal opnd mode {}", mode);
+    // Make a new operand mode from a number, or `None` if
+    // the number is not a legal mode.
+    fn try_new(mode: usize) -> Option<Self> {
+        [Self::Pos, Self::Imm, Self::Rel].get(mode).cloned()
+    }
+
+    // The numeric code for this mode, as it appears in the
+    // mode digits of an instruction.
+    fn code(self) -> i64 {
+        match self {
+            OpndMode::Pos => 0,
+            OpndMode::Imm => 1,
+            OpndMode::Rel => 2,
         }
-        modes[mode]
     }
 }
 
 // Iterator-like object for fetching / storing successive
 // arguments of an instruction.
 struct Decode<'a> {
-    prog: &'a mut Vec<i64>,
+    prog: &'a mut Memory,
     index: usize,
     modebits: usize,
     rel_base: i64,
@@ -81,14 +264,22 @@ impl<'a> Decode<'a> {
     // itself as an iterator that will be used on successive
     // operands.
     fn new(
-        prog: &'a mut Vec<i64>,
+        prog: &'a mut Memory,
         index: usize,
         rel_base: i64,
-    ) -> (Opcode, Self) {
-        let opcode = prog[index] as usize;
-        let op = Opcode::new(opcode % 100);
+    ) -> Result<(Opcode, Self), Fault> {
+        let word = prog.get(index).expect("decode past end of memory");
+        if prog.is_big(index) {
+            return Err(Fault::Overflow);
+        }
+        if word < 0 {
+            return Err(Fault::IllegalOpcode(word));
+        }
+        let opcode = word as usize;
+        let op = Opcode::try_new(opcode % 100)
+            .ok_or(Fault::IllegalOpcode(word % 100))?;
         let modebits = opcode / 100;
-        (
+        Ok((
             op,
             Self {
                 prog,
@@ -96,75 +287,121 @@ impl<'a> Decode<'a> {
                 index: index + 1,
                 rel_base,
             },
-        )
+        ))
     }
 
-    // Treat the current instruction operand as a fetch and
-    // get the value.
-    //
-    // XXX Ugly copy-paste of this code below that is
-    // awkward to get rid of.
-    fn fetch(&mut self) -> i64 {
-        let nprog = self.prog.len();
+    // Fetch the raw operand word and decode its mode,
+    // extending memory if needed.
+    #[inline]
+    fn operand(&mut self) -> Result<(OpndMode, i64), Fault> {
+        self.prog.resize(self.index + 1);
         let mode = self.modebits % 10;
-        if self.index >= nprog {
-            self.prog.resize(self.index + 1, 0);
+        let mode =
+            OpndMode::try_new(mode).ok_or(Fault::IllegalMode(mode))?;
+        if self.prog.is_big(self.index) {
+            return Err(Fault::Overflow);
+        }
+        Ok((mode, self.get(self.index)))
+    }
+
+    // Turn a positional or relative operand into an
+    // address, extending memory if needed.
+    #[inline]
+    fn address(
+        &mut self,
+        mode: OpndMode,
+        opnd: i64,
+    ) -> Result<usize, Fault> {
+        let mut opnd = opnd;
+        if mode == OpndMode::Rel {
+            opnd = opnd
+                .checked_add(self.rel_base)
+                .ok_or(Fault::Overflow)?;
         }
-        let mut opnd = self.prog[self.index];
-        let mode = OpndMode::new(mode);
+        if opnd < 0 {
+            return Err(Fault::NegativeAddress(opnd));
+        }
+        let addr = opnd as usize;
+        self.prog.resize(addr + 1);
+        Ok(addr)
+    }
+
+    // Treat the current instruction operand as a fetch and
+    // get the value.
+    #[inline]
+    fn fetch(&mut self) -> Result<i64, Fault> {
+        let (mode, opnd) = self.operand()?;
         let val = match mode {
             OpndMode::Imm => opnd,
             OpndMode::Pos | OpndMode::Rel => {
-                if mode == OpndMode::Rel {
-                    opnd += self.rel_base;
-                }
-                if opnd < 0 {
-                    panic!("fetch position out of range");
-                }
-                if opnd as usize >= nprog {
-                    self.prog.resize(opnd as usize + 1, 0);
+                let addr = self.address(mode, opnd)?;
+                if self.prog.is_big(addr) {
+                    return Err(Fault::Overflow);
                 }
-                self.prog[opnd as usize]
+                self.get(addr)
             }
         };
-        self.modebits /= 10;
-        self.index += 1;
-        val
+        self.skip();
+        Ok(val)
     }
 
-    // Treat the current instruction operand as a store and
-    // store the value.
-    fn store(&mut self, val: i64) {
-        let nprog = self.prog.len();
-        let mode = self.modebits % 10;
-        if self.index >= nprog {
-            self.prog.resize(self.index + 1, 0);
-        }
-        let mut opnd = self.prog[self.index];
-        let mode = OpndMode::new(mode);
-        match mode {
-            OpndMode::Imm => {
-                panic!("store to immediate");
-            }
+    // As with `fetch()`, but allowing values too large for
+    // an `i64`.
+    fn fetch_big(&mut self) -> Result<BigInt, Fault> {
+        let imm = OpndMode::try_new(self.modebits % 10);
+        if imm == Some(OpndMode::Imm) && self.prog.is_big(self.index) {
+            let val = self.prog.get_big(self.index);
+            self.skip();
+            return Ok(val.expect("operand past end"));
+        }
+        let (mode, opnd) = self.operand()?;
+        let val = match mode {
+            OpndMode::Imm => BigInt::from(opnd),
             OpndMode::Pos | OpndMode::Rel => {
-                if mode == OpndMode::Rel {
-                    opnd += self.rel_base;
-                }
-                if opnd < 0 {
-                    panic!("store position out of range");
-                }
-                if opnd as usize >= nprog {
-                    self.prog.resize(opnd as usize + 1, 0);
-                }
-                self.prog[opnd as usize] = val;
+                let addr = self.address(mode, opnd)?;
+                self.prog.get_big(addr).expect("operand past end")
             }
+        };
+        self.skip();
+        Ok(val)
+    }
+
+    // Treat the current instruction operand as a store and
+    // store the value. Returns the address stored to.
+    #[inline]
+    fn store(&mut self, val: i64) -> Result<usize, Fault> {
+        let (mode, opnd) = self.operand()?;
+        if mode == OpndMode::Imm {
+            return Err(Fault::StoreToImmediate);
         }
-        self.modebits /= 10;
-        self.index += 1;
+        let addr = self.address(mode, opnd)?;
+        self.prog.set(addr, val);
+        self.skip();
+        Ok(addr)
+    }
+
+    // As with `store()`, but allowing values too large for
+    // an `i64`.
+    fn store_big(&mut self, val: BigInt) -> Result<usize, Fault> {
+        let (mode, opnd) = self.operand()?;
+        if mode == OpndMode::Imm {
+            return Err(Fault::StoreToImmediate);
+        }
+        let addr = self.address(mode, opnd)?;
+        self.prog.set_big(addr, val);
+        self.skip();
+        Ok(addr)
+    }
+
+    // Value at an address known to be in memory.
+    #[inline]
+    fn get(&self, addr: usize) -> i64 {
+        self.prog.get(addr).expect("operand past end of memory")
     }
 
     // Skip the current operand. This is used, for example,
     // for jumps not taken.
+    #[inline]
     fn skip(&mut self) {
         self.modebits /= 10;
         self.index += 1;
@@ -174,17 +411,102 @@ impl<'a> Decode<'a> {
     // (would probably indicate a number-of-arguments error)
     // and then return the index one past the end of this
     // instruction.
-    fn finish(self) -> usize {
+    #[inline]
+    fn finish(self) -> Result<usize, Fault> {
         if self.modebits != 0 {
-            panic!("unused mode bits");
+            return Err(Fault::UnusedModeBits);
+        }
+        Ok(self.index)
+    }
+}
+
+/// Reason for an Intcode execution fault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// The instruction has an unknown opcode.
+    IllegalOpcode(i64),
+    /// An operand has an unknown mode digit.
+    IllegalMode(usize),
+    /// An instruction tried to store to an immediate
+    /// operand.
+    StoreToImmediate,
+    /// An operand referred to a negative address.
+    NegativeAddress(i64),
+    /// The instruction has more mode digits than operands.
+    UnusedModeBits,
+    /// A jump went to a negative address.
+    NegativeJump(i64),
+    /// The instruction pointer is past the end of memory.
+    RanOffEnd,
+    /// `peek()` or `poke()` of an address past the end of
+    /// memory.
+    AddressOutOfRange(usize),
+    /// Arithmetic overflowed, or a value too large for an
+    /// `i64` was used where one is needed.
+    Overflow,
+    /// The handler of a user-defined instruction failed
+    /// with the given message.
+    UserOp(String),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::IllegalOpcode(op) => {
+                write!(f, "illegal opcode {}", op)
+            }
+            Fault::IllegalMode(m) => {
+                write!(f, "illegal opnd mode {}", m)
+            }
+            Fault::StoreToImmediate => write!(f, "store to immediate"),
+            Fault::NegativeAddress(a) => {
+                write!(f, "position {} out of range", a)
+            }
+            Fault::UnusedModeBits => write!(f, "unused mode bits"),
+            Fault::NegativeJump(t) => {
+                write!(f, "jump target {} out of range", t)
+            }
+            Fault::RanOffEnd => write!(f, "program ran off end"),
+            Fault::AddressOutOfRange(a) => {
+                write!(f, "address {} out of range", a)
+            }
+            Fault::Overflow => write!(f, "arithmetic overflow"),
+            Fault::UserOp(msg) => {
+                write!(f, "user-defined instruction failed: {}", msg)
+            }
         }
-        self.index
     }
 }
 
+/// An Intcode execution fault. Carries the instruction
+/// pointer at the time of the fault and the instruction
+/// word there, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntcodeError {
+    pub ip: usize,
+    pub insn: Option<i64>,
+    pub fault: Fault,
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ip {}", self.ip)?;
+        if let Some(insn) = self.insn {
+            write!(f, " (insn {})", insn)?;
+        }
+        write!(f, ": {}", self.fault)
+    }
+}
+
+impl std::error::Error for IntcodeError {}
+
+/// When a deadline is set, `Intcode::run()` checks the
+/// clock only once per this many instructions.
+pub const DEADLINE_INTERVAL: u64 = 1024;
+
 /// This is returned by `Intcode::run()` to indicate why it
 /// stopped.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Terminus {
     /// Program executed a `Halt` instruction.
     Halted,
@@ -194,6 +516,10 @@ pub enum Terminus {
     /// Program executed an `Output` instruction with the
     /// given value.
     HaveOutput(i64),
+    /// Program used up its fuel or passed its deadline
+    /// before suspending. It can be resumed after adding
+    /// fuel or extending the deadline.
+    OutOfFuel,
 }
 
 /// An Intcode program. It has an input buffer for
@@ -202,10 +528,21 @@ pub enum Terminus {
 /// halted, although this is less useful.)
 #[derive(Debug, Clone)]
 pub struct Intcode {
-    prog: Vec<i64>,
-    inputs: Vec<i64>,
+    prog: Memory,
+    inputs: VecDeque<i64>,
     ip: usize,
     rel_base: i64,
+    last_store: Option<usize>,
+    tracer: Option<Tracer>,
+    executed: u64,
+    fuel: Option<u64>,
+    deadline: Option<Instant>,
+    cache: Option<DecodeCache>,
+    arith: Arith,
+    big_output: Option<BigInt>,
+    profile: Option<Profile>,
+    journal: Option<Journal>,
+    isa: Option<Arc<Isa>>,
 }
 
 impl Intcode {
@@ -213,123 +550,359 @@ impl Intcode {
     /// instructions.
     pub fn new(prog: Vec<i64>) -> Self {
         Self {
-            prog,
-            inputs: Vec::new(),
+            prog: Memory::from(prog),
+            inputs: VecDeque::new(),
             ip: 0,
             rel_base: 0,
+            last_store: None,
+            tracer: None,
+            executed: 0,
+            fuel: None,
+            deadline: None,
+            cache: None,
+            arith: Arith::Checked,
+            big_output: None,
+            profile: None,
+            journal: None,
+            isa: None,
         }
     }
 
     /// Builder for adding user inputs to the `Intcode`
     /// program before running.
     pub fn with_inputs(mut self, inputs: Vec<i64>) -> Self {
-        self.inputs.reverse();
-        self.inputs.extend_from_slice(&inputs);
-        self.inputs.reverse();
+        self.inputs.extend(inputs);
         self
     }
 
     /// Add an input to the Intcode program while running.
     pub fn add_input(&mut self, input: i64) {
-        self.inputs.reverse();
-        self.inputs.push(input);
-        self.inputs.reverse();
+        self.inputs.push_back(input);
     }
 
-    /// Read and parse the program from stdin in the
-    /// specified format.
-    pub fn read() -> Self {
-        use std::io::{stdin, BufReader, Read};
+    /// Builder for limiting the number of instructions the
+    /// `Intcode` program may execute.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Set the number of instructions the program may
+    /// execute before `run()` returns `Terminus::OutOfFuel`,
+    /// or `None` for no limit.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
 
-        let mut text = String::new();
-        BufReader::new(stdin()).read_to_string(&mut text).unwrap();
-        let prog = text
-            .trim_end()
-            .split(',')
-            .map(|w| w.parse().unwrap())
-            .collect();
-        Self::new(prog)
+    /// Fuel remaining, if limited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Set a wall-clock time after which `run()` returns
+    /// `Terminus::OutOfFuel`, or `None` for no deadline.
+    /// The deadline is checked when `run()` starts and
+    /// every `DEADLINE_INTERVAL` instructions thereafter;
+    /// `step()` ignores it.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Total number of instructions executed so far, not
+    /// counting `Halt`.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Read and parse the program from stdin, as text or a
+    /// binary image: see `from_reader()`.
+    ///
+    /// # Panics
+    /// Will panic if the program cannot be read or parsed.
+    pub fn read() -> Self {
+        Self::from_reader(std::io::stdin())
+            .unwrap_or_else(|e| panic!("could not load program: {}", e))
     }
 
     /// Run this Intcode program until it suspends. Returns
     /// the cause of suspension.
+    ///
+    /// # Panics
+    /// Will panic on an execution fault: see `try_run()`.
     pub fn run(&mut self) -> Terminus {
-        let prog = &mut self.prog;
-        let nprog = prog.len();
-
-        // The actual emulator loop tries to be careful in
-        // its checking.
-        let mut ip: usize = self.ip;
-        while ip < nprog {
-            let (op, mut opnds) = Decode::new(prog, ip, self.rel_base);
-            use Opcode::*;
-            ip = match op {
-                Halt => {
-                    self.ip = ip;
-                    return Terminus::Halted;
-                }
-                Add | Mul | LessThan | Equals => {
-                    let src1 = opnds.fetch();
-                    let src2 = opnds.fetch();
-                    let a = match op {
-                        Add => src1 + src2,
-                        Mul => src1 * src2,
-                        LessThan => (src1 < src2) as i64,
-                        Equals => (src1 == src2) as i64,
-                        _ => unreachable!("wrong insn for ALU"),
-                    };
-                    opnds.store(a);
-                    opnds.finish()
+        self.try_run().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Run this Intcode program until it suspends or
+    /// faults. On a fault, the instruction pointer is left
+    /// at the faulting instruction, although that
+    /// instruction may already have stored its result.
+    pub fn try_run(&mut self) -> Result<Terminus, IntcodeError> {
+        if self.tracer.is_none()
+            && self.profile.is_none()
+            && self.journal.is_none()
+        {
+            return self.run_plain();
+        }
+        // Instructions until the next deadline check.
+        let mut countdown = 0;
+        loop {
+            if self.past_deadline(&mut countdown) {
+                return Ok(Terminus::OutOfFuel);
+            }
+            if let Some(t) = self.try_step()? {
+                return Ok(t);
+            }
+        }
+    }
+
+    // `try_run()` for a machine with no tracer, profile or
+    // journal to tell about each instruction.
+    fn run_plain(&mut self) -> Result<Terminus, IntcodeError> {
+        let mut countdown = 0;
+        loop {
+            if self.past_deadline(&mut countdown)
+                || self.fuel == Some(0)
+            {
+                return Ok(Terminus::OutOfFuel);
+            }
+            // Run what can run on flat memory, within the fuel
+            // and the instructions left before the next
+            // deadline check, counting the one just checked.
+            let mut limit = self.fuel.unwrap_or(u64::MAX);
+            if self.deadline.is_some() {
+                limit = limit.min(countdown + 1);
+            }
+            let ran = self.run_flat(limit);
+            if ran > 0 {
+                self.executed += ran;
+                if let Some(ref mut fuel) = self.fuel {
+                    *fuel -= ran;
                 }
-                Input => {
-                    if self.inputs.is_empty() {
-                        self.ip = ip;
-                        return Terminus::NeedInput;
-                    }
-                    let input =
-                        self.inputs.pop().expect("input without value");
-                    opnds.store(input);
-                    opnds.finish()
+                if self.deadline.is_some() {
+                    countdown -= ran - 1;
                 }
-                Output => {
-                    let output = opnds.fetch();
-                    ip = opnds.finish();
-                    self.ip = ip;
-                    return Terminus::HaveOutput(output);
+                continue;
+            }
+            let ip = self.ip;
+            let insn = self.prog.get(ip);
+            let t = match self.dispatch() {
+                Ok(None) => None,
+                Ok(Some(t @ Terminus::HaveOutput(_))) => Some(t),
+                Ok(Some(t)) => return Ok(t),
+                Err(fault) => {
+                    return Err(IntcodeError { ip, insn, fault })
                 }
-                JumpIfTrue | JumpIfFalse => {
-                    let test = opnds.fetch();
-                    let test = match op {
-                        JumpIfTrue => test != 0,
-                        JumpIfFalse => test == 0,
-                        _ => unreachable!("wrong insn for jump"),
-                    };
-                    if test {
-                        let target = opnds.fetch();
-                        if target < 0 {
-                            panic!("jump target out of range");
+            };
+            self.executed += 1;
+            if let Some(ref mut fuel) = self.fuel {
+                *fuel -= 1;
+            }
+            if let Some(t) = t {
+                return Ok(t);
+            }
+        }
+    }
+
+    // True if the deadline has passed. The clock is read
+    // only when the given countdown of instructions since
+    // the last reading runs out.
+    fn past_deadline(&self, countdown: &mut u64) -> bool {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return false,
+        };
+        if *countdown == 0 {
+            if Instant::now() >= deadline {
+                return true;
+            }
+            *countdown = DEADLINE_INTERVAL;
+        }
+        *countdown -= 1;
+        false
+    }
+
+    /// Execute a single instruction. Returns the cause of
+    /// suspension if the instruction suspended the program,
+    /// and `None` otherwise. As with `run()`, an `Input`
+    /// with no inputs buffered is not executed.
+    ///
+    /// # Panics
+    /// Will panic on an execution fault: see `try_step()`.
+    pub fn step(&mut self) -> Option<Terminus> {
+        self.try_step().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Execute a single instruction, returning an error on
+    /// an execution fault.
+    pub fn try_step(
+        &mut self,
+    ) -> Result<Option<Terminus>, IntcodeError> {
+        if self.fuel == Some(0) {
+            return Ok(Some(Terminus::OutOfFuel));
+        }
+        let undo = self.journal.as_ref().map(|_| self.journal_entry());
+        let ninputs = self.inputs.len();
+        let result = if self.tracer.is_some() {
+            self.traced_step()
+        } else {
+            self.untraced_step()
+        };
+        if let Ok(None) | Ok(Some(Terminus::HaveOutput(_))) = result {
+            self.executed += 1;
+            if let Some(ref mut fuel) = self.fuel {
+                *fuel -= 1;
+            }
+        }
+        if let Some(undo) = undo {
+            self.update_journal(undo, ninputs, &result);
+        }
+        result
+    }
+
+    // Execute a single instruction, converting faults to
+    // errors.
+    fn untraced_step(
+        &mut self,
+    ) -> Result<Option<Terminus>, IntcodeError> {
+        let ip = self.ip;
+        let insn = self.prog.get(ip);
+        let rel_base = self.rel_base;
+        let decoded =
+            self.profile.as_ref().and_then(|_| self.decode(ip));
+        let result = self.dispatch().map_err(|fault| IntcodeError {
+            ip,
+            insn,
+            fault,
+        });
+        self.update_profile(ip, decoded, rel_base, &result);
+        result
+    }
+
+    // Execute a single instruction with the chosen engine.
+    #[inline]
+    fn dispatch(&mut self) -> Result<Option<Terminus>, Fault> {
+        if self.cache.is_none() {
+            return self.execute();
+        }
+        if self.run_flat(1) == 1 {
+            return Ok(None);
+        }
+        let result = self.execute();
+        if let (Some(cache), Some(addr)) =
+            (self.cache.as_mut(), self.last_store)
+        {
+            cache.invalidate(addr);
+        }
+        result
+    }
+
+    // Execute a single instruction. This is the guts of the
+    // emulator, and tries to be careful in its checking.
+    fn execute(&mut self) -> Result<Option<Terminus>, Fault> {
+        self.last_store = None;
+        if self.isa.is_some() {
+            let isa = self.isa.clone().expect("no isa");
+            if let Some(result) = self.execute_isa(&isa) {
+                return result;
+            }
+        }
+        let prog = &mut self.prog;
+
+        let ip: usize = self.ip;
+        if ip >= prog.len() {
+            return Err(Fault::RanOffEnd);
+        }
+        let (op, mut opnds) = Decode::new(prog, ip, self.rel_base)?;
+        use Opcode::*;
+        self.ip = match op {
+            Halt => {
+                return Ok(Some(Terminus::Halted));
+            }
+            Add | Mul | LessThan | Equals
+                if self.arith == Arith::Big =>
+            {
+                let src1 = opnds.fetch_big()?;
+                let src2 = opnds.fetch_big()?;
+                let a = match op {
+                    Add => &src1 + &src2,
+                    Mul => &src1 * &src2,
+                    LessThan => BigInt::from((src1 < src2) as i64),
+                    Equals => BigInt::from((src1 == src2) as i64),
+                    _ => unreachable!("wrong insn for ALU"),
+                };
+                self.last_store = Some(opnds.store_big(a)?);
+                opnds.finish()?
+            }
+            Add | Mul | LessThan | Equals => {
+                let src1 = opnds.fetch()?;
+                let src2 = opnds.fetch()?;
+                let a = self.arith.apply(op, src1, src2)?;
+                self.last_store = Some(opnds.store(a)?);
+                opnds.finish()?
+            }
+            Input => {
+                let input = match self.inputs.front() {
+                    Some(&input) => input,
+                    None => return Ok(Some(Terminus::NeedInput)),
+                };
+                self.last_store = Some(opnds.store(input)?);
+                let next = opnds.finish()?;
+                self.inputs.pop_front();
+                next
+            }
+            Output => {
+                self.big_output = None;
+                let output = if self.arith == Arith::Big {
+                    let output = opnds.fetch_big()?;
+                    match output.to_i64() {
+                        Some(output) => output,
+                        None => {
+                            let low = output.low_i64();
+                            self.big_output = Some(output);
+                            low
                         }
-                        target as usize
-                    } else {
-                        opnds.skip();
-                        opnds.finish()
                     }
-                }
-                RBO => {
-                    let offset = opnds.fetch();
-                    self.rel_base += offset;
-                    opnds.finish()
+                } else {
+                    opnds.fetch()?
+                };
+                self.ip = opnds.finish()?;
+                return Ok(Some(Terminus::HaveOutput(output)));
+            }
+            JumpIfTrue | JumpIfFalse => {
+                let test = opnds.fetch()?;
+                let test = match op {
+                    JumpIfTrue => test != 0,
+                    JumpIfFalse => test == 0,
+                    _ => unreachable!("wrong insn for jump"),
+                };
+                if test {
+                    let target = opnds.fetch()?;
+                    if target < 0 {
+                        return Err(Fault::NegativeJump(target));
+                    }
+                    target as usize
+                } else {
+                    opnds.skip();
+                    opnds.finish()?
                 }
             }
-        }
-        panic!("program ran off end");
+            RBO => {
+                let offset = opnds.fetch()?;
+                self.rel_base =
+                    self.arith.add(self.rel_base, offset)?;
+                opnds.finish()?
+            }
+        };
+        Ok(None)
     }
 
     /// Keep running the program until it halts, collecting
     /// any outputs produced along the way. Return them all.
     ///
     /// # Panics
-    /// Will panic if program stops to request input.
+    /// Will panic if program stops to request input or runs
+    /// out of fuel.
     pub fn collect_outputs(&mut self) -> Vec<i64> {
         let mut outputs = Vec::new();
         loop {
@@ -339,16 +912,48 @@ impl Intcode {
                 Terminus::NeedInput => {
                     panic!("output collection stopped for input")
                 }
+                Terminus::OutOfFuel => {
+                    panic!("output collection ran out of fuel")
+                }
             }
         }
     }
 
+    /// The current contents of memory, including any
+    /// locations extended by stores past the end of the
+    /// original program.
+    pub fn memory(&self) -> &Memory {
+        &self.prog
+    }
+
+    /// Address of the next instruction to be executed.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// Current relative base.
+    pub fn rel_base(&self) -> i64 {
+        self.rel_base
+    }
+
+    /// Inputs buffered but not yet consumed, in the order
+    /// they will be consumed.
+    pub fn pending_inputs(&self) -> Vec<i64> {
+        self.inputs.iter().cloned().collect()
+    }
+
+    /// Address stored to by the most recent `step()`, if
+    /// any.
+    pub fn last_store(&self) -> Option<usize> {
+        self.last_store
+    }
+
     /// Retrieve the value at the given address.
     ///
     /// # Panics
     /// Will panic if address is out of range.
     pub fn peek(&mut self, addr: usize) -> i64 {
-        self.prog[addr]
+        self.try_peek(addr).unwrap_or_else(|e| panic!("{}", e))
     }
 
     /// Poke the given value into the given address.
@@ -356,7 +961,45 @@ impl Intcode {
     /// # Panics
     /// Will panic if address is out of range.
     pub fn poke(&mut self, addr: usize, val: i64) {
-        self.prog[addr] = val;
+        self.try_poke(addr, val).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Retrieve the value at the given address, or an
+    /// error if the address is out of range.
+    pub fn try_peek(&self, addr: usize) -> Result<i64, IntcodeError> {
+        match self.prog.get(addr) {
+            Some(val) => Ok(val),
+            None => Err(self.error(Fault::AddressOutOfRange(addr))),
+        }
+    }
+
+    /// Poke the given value into the given address, or
+    /// return an error if the address is out of range. This
+    /// restarts any journal.
+    pub fn try_poke(
+        &mut self,
+        addr: usize,
+        val: i64,
+    ) -> Result<(), IntcodeError> {
+        if addr >= self.prog.len() {
+            return Err(self.error(Fault::AddressOutOfRange(addr)));
+        }
+        self.prog.set(addr, val);
+        if let Some(ref mut cache) = self.cache {
+            cache.invalidate(addr);
+        }
+        self.restart_journal();
+        Ok(())
+    }
+
+    // Make an error for the given fault at the current
+    // instruction pointer.
+    fn error(&self, fault: Fault) -> IntcodeError {
+        IntcodeError {
+            ip: self.ip,
+            insn: self.prog.get(self.ip),
+            fault,
+        }
     }
 }
 
@@ -393,7 +1036,7 @@ fn test_day02() {
         let mut init = Intcode::new(init.to_vec());
         let fin = fin.to_vec();
         init.run();
-        assert_eq!(init.prog, fin);
+        assert_eq!(init.memory().to_vec(), fin);
     }
 }
 
@@ -472,3 +1115,144 @@ fn test_day09() {
         _ => panic!("test failed with no output"),
     }
 }
+
+// Faulting programs should report where and why.
+#[test]
+fn test_faults() {
+    #[rustfmt::skip]
+    let testcases: &[(&[i64], usize, Fault)] = &[
+        (&[98], 0, Fault::IllegalOpcode(98)),
+        (&[-1], 0, Fault::IllegalOpcode(-1)),
+        (&[1, 0, 0, 0, 301, 0, 0, 0], 4, Fault::IllegalMode(3)),
+        (&[1101, 1, 2, 3, 11101, 1, 2, 3], 4, Fault::StoreToImmediate),
+        (&[1, -1, 0, 0], 0, Fault::NegativeAddress(-1)),
+        (&[109, -5, 22201, 0, 0, 0], 2, Fault::NegativeAddress(-5)),
+        (&[10104, 0], 0, Fault::UnusedModeBits),
+        (&[1105, 1, -3], 0, Fault::NegativeJump(-3)),
+        (&[1105, 1, 7], 7, Fault::RanOffEnd),
+    ];
+    for &(prog, ip, ref fault) in testcases {
+        let mut ic = Intcode::new(prog.to_vec());
+        let err = ic.try_run().unwrap_err();
+        assert_eq!((err.ip, &err.fault), (ip, fault), "{}", err);
+        assert_eq!(err.insn, prog.get(ip).cloned());
+        assert_eq!(ic.ip(), ip);
+    }
+
+    let mut ic = Intcode::new(vec![99]);
+    assert_eq!(ic.try_peek(0), Ok(99));
+    let err = ic.try_poke(1, 0).unwrap_err();
+    assert_eq!(err.fault, Fault::AddressOutOfRange(1));
+}
+
+// Fuel should stop a runaway program and allow resuming.
+#[test]
+fn test_fuel() {
+    // Loop forever.
+    let mut ic = Intcode::new(vec![1105, 1, 0]).with_fuel(10);
+    assert_eq!(ic.run(), Terminus::OutOfFuel);
+    assert_eq!(ic.executed(), 10);
+    assert_eq!(ic.run(), Terminus::OutOfFuel);
+    ic.set_fuel(Some(5));
+    assert_eq!(ic.run(), Terminus::OutOfFuel);
+    assert_eq!(ic.executed(), 15);
+
+    ic.set_fuel(None);
+    ic.set_deadline(Some(Instant::now()));
+    assert_eq!(ic.run(), Terminus::OutOfFuel);
+
+    // A deadline not yet reached leaves the fuel to stop
+    // the program, over several clock readings.
+    let later = Instant::now() + std::time::Duration::from_secs(60);
+    ic.set_deadline(Some(later));
+    ic.set_fuel(Some(3 * DEADLINE_INTERVAL + 1));
+    assert_eq!(ic.run(), Terminus::OutOfFuel);
+    assert_eq!(ic.executed(), 15 + 3 * DEADLINE_INTERVAL + 1);
+
+    // Resuming with more fuel gives the same results as
+    // never running out.
+    let prog = vec![
+        109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006,
+        101, 0, 99,
+    ];
+    let mut ic = Intcode::new(prog.clone()).with_fuel(7);
+    let mut outputs = Vec::new();
+    loop {
+        match ic.run() {
+            Terminus::HaveOutput(v) => outputs.push(v),
+            Terminus::OutOfFuel => ic.set_fuel(Some(7)),
+            Terminus::Halted => break,
+            Terminus::NeedInput => panic!("unexpected input request"),
+        }
+    }
+    assert_eq!(outputs, prog);
+}
+
+#[test]
+fn test_arith() {
+    // Square a value three times, outputting each square.
+    let prog = vec![
+        2,
+        14,
+        14,
+        14,
+        4,
+        14,
+        1001,
+        15,
+        -1,
+        15,
+        1005,
+        15,
+        0,
+        99,
+        1 << 20,
+        3,
+    ];
+    let run = |arith| {
+        let mut ic = Intcode::new(prog.clone()).with_arith(arith);
+        let mut outputs = Vec::new();
+        let result = loop {
+            match ic.try_run() {
+                Ok(Terminus::HaveOutput(v)) => {
+                    let big = ic.big_output().map(|b| b.to_string());
+                    outputs.push((v, big));
+                }
+                r => break r,
+            }
+        };
+        (result, outputs)
+    };
+
+    let (result, outputs) = run(Arith::Checked);
+    let err = result.unwrap_err();
+    assert_eq!((err.ip, err.fault), (0, Fault::Overflow));
+    assert_eq!(outputs, vec![(1 << 40, None)]);
+
+    let (result, outputs) = run(Arith::Wrapping);
+    assert_eq!(result, Ok(Terminus::Halted));
+    assert_eq!(outputs, vec![(1 << 40, None), (0, None), (0, None)]);
+
+    let (result, outputs) = run(Arith::Big);
+    assert_eq!(result, Ok(Terminus::Halted));
+    assert_eq!(outputs[1].0, 0);
+    assert_eq!(
+        outputs[2].1.as_deref(),
+        Some("1461501637330902918203684832716283019655932542976")
+    );
+
+    // Big values can't be used as addresses.
+    let mut ic = Intcode::new(vec![2, 7, 7, 7, 1005, 7, 0, 1 << 40])
+        .with_arith(Arith::Big);
+    let err = ic.try_run().unwrap_err();
+    assert_eq!((err.ip, err.fault), (4, Fault::Overflow));
+
+    // But big immediates can be used in arithmetic.
+    let prog = vec![2, 5, 5, 5, 1102, 1 << 40, 2, 9, 99, 0];
+    let mut ic = Intcode::new(prog).with_arith(Arith::Big);
+    assert_eq!(ic.run(), Terminus::Halted);
+    assert_eq!(
+        ic.try_peek_big(9).unwrap().to_string(),
+        "2417851639229258349412352"
+    );
+}
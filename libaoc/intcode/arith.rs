@@ -0,0 +1,365 @@
+//! Intcode arithmetic policies.
+//!
+//! Plain `i64` arithmetic panics on overflow in debug
+//! builds and wraps in release builds. An `Intcode` machine
+//! instead follows an explicit `Arith` policy, the same in
+//! every build: overflow can fault, wrap, or produce an
+//! arbitrary-precision `BigInt` value.
+//!
+//! With `Arith::Big`, memory words that don't fit in an
+//! `i64` are held alongside memory as `BigInt`s. Such values
+//! may be used by arithmetic and comparison instructions
+//! and output; using one as an address, jump test, jump
+//! target or relative base offset faults with
+//! `Fault::Overflow`. `Terminus::HaveOutput` carries only
+//! the low 64 bits of a big output: the whole value is
+//! available from `Intcode::big_output()`.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::{Arith, Fault, Intcode};
+//!
+//! // Square 2^40 and output it.
+//! let prog = vec![1002, 7, 1099511627776, 7, 4, 7, 99, 1099511627776];
+//!
+//! let mut ic = Intcode::new(prog.clone());
+//! assert_eq!(ic.try_run().unwrap_err().fault, Fault::Overflow);
+//!
+//! let mut ic = Intcode::new(prog.clone()).with_arith(Arith::Wrapping);
+//! ic.run();
+//! assert_eq!(ic.peek(7), 0);
+//!
+//! let mut ic = Intcode::new(prog).with_arith(Arith::Big);
+//! ic.run();
+//! assert_eq!(ic.big_output().unwrap().to_string(), "1208925819614629174706176");
+//! ```
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Mul};
+
+use super::{Fault, Intcode, IntcodeError, Opcode};
+
+/// Arithmetic policy for an Intcode machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arith {
+    /// Fault with `Fault::Overflow` on overflow. This is
+    /// the default.
+    Checked,
+    /// Wrap around on overflow.
+    Wrapping,
+    /// Compute exactly with arbitrary-precision values.
+    Big,
+}
+
+impl Arith {
+    // Compute the result of an ALU instruction on `i64`
+    // values.
+    #[inline]
+    pub(super) fn apply(
+        self,
+        op: Opcode,
+        a: i64,
+        b: i64,
+    ) -> Result<i64, Fault> {
+        match op {
+            Opcode::Add => self.add(a, b),
+            Opcode::Mul => match self {
+                Arith::Wrapping => Ok(a.wrapping_mul(b)),
+                _ => a.checked_mul(b).ok_or(Fault::Overflow),
+            },
+            Opcode::LessThan => Ok((a < b) as i64),
+            Opcode::Equals => Ok((a == b) as i64),
+            _ => unreachable!("wrong insn for ALU"),
+        }
+    }
+
+    // Add two `i64` values.
+    #[inline]
+    pub(super) fn add(self, a: i64, b: i64) -> Result<i64, Fault> {
+        match self {
+            Arith::Wrapping => Ok(a.wrapping_add(b)),
+            _ => a.checked_add(b).ok_or(Fault::Overflow),
+        }
+    }
+}
+
+/// An arbitrary-precision integer, as used by `Arith::Big`.
+/// Supports just what Intcode needs: addition,
+/// multiplication, comparison and printing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct BigInt {
+    negative: bool,
+    // Little-endian base 2^32 digits, with no high zero
+    // digits. Zero has no digits and is not negative.
+    digits: Vec<u32>,
+}
+
+impl BigInt {
+    /// True if this is zero.
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    /// This value as an `i64`, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+        let mag = self.low_u64();
+        if self.negative {
+            if mag <= 1 << 63 {
+                Some((mag as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else {
+            i64::try_from(mag).ok()
+        }
+    }
+
+    /// The low 64 bits of this value in two's complement,
+    /// as an `i64`.
+    pub fn low_i64(&self) -> i64 {
+        let low = self.low_u64() as i64;
+        if self.negative {
+            low.wrapping_neg()
+        } else {
+            low
+        }
+    }
+
+    // The low 64 bits of the magnitude.
+    fn low_u64(&self) -> u64 {
+        let digit =
+            |i| u64::from(self.digits.get(i).cloned().unwrap_or(0));
+        digit(0) | digit(1) << 32
+    }
+
+    // The sign and little-endian base 2^32 digits of this
+    // value.
+    pub(super) fn parts(&self) -> (bool, &[u32]) {
+        (self.negative, &self.digits)
+    }
+
+    // Make a value from a sign and digits, normalizing.
+    pub(super) fn from_parts(
+        negative: bool,
+        mut digits: Vec<u32>,
+    ) -> Self {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        let negative = negative && !digits.is_empty();
+        Self { negative, digits }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(v: i64) -> Self {
+        let mag = v.unsigned_abs();
+        Self::from_parts(v < 0, vec![mag as u32, (mag >> 32) as u32])
+    }
+}
+
+// Compare magnitudes.
+fn cmp_digits(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+// Add magnitudes.
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..a.len().max(b.len()) {
+        let d = |x: &[u32]| u64::from(x.get(i).cloned().unwrap_or(0));
+        let s = d(a) + d(b) + carry;
+        sum.push(s as u32);
+        carry = s >> 32;
+    }
+    sum.push(carry as u32);
+    sum
+}
+
+// Subtract magnitudes, the first no smaller than the
+// second.
+fn sub_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut diff = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &x) in a.iter().enumerate() {
+        let y = i64::from(b.get(i).cloned().unwrap_or(0));
+        let mut d = i64::from(x) - y - borrow;
+        borrow = (d < 0) as i64;
+        if d < 0 {
+            d += 1 << 32;
+        }
+        diff.push(d as u32);
+    }
+    diff
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            let digits = add_digits(&self.digits, &other.digits);
+            return BigInt::from_parts(self.negative, digits);
+        }
+        match cmp_digits(&self.digits, &other.digits) {
+            Ordering::Less => BigInt::from_parts(
+                other.negative,
+                sub_digits(&other.digits, &self.digits),
+            ),
+            _ => BigInt::from_parts(
+                self.negative,
+                sub_digits(&self.digits, &other.digits),
+            ),
+        }
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut prod =
+            vec![0u32; self.digits.len() + other.digits.len()];
+        for (i, &x) in self.digits.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &y) in other.digits.iter().enumerate() {
+                let p = u64::from(x) * u64::from(y)
+                    + u64::from(prod[i + j])
+                    + carry;
+                prod[i + j] = p as u32;
+                carry = p >> 32;
+            }
+            prod[i + other.digits.len()] = carry as u32;
+        }
+        BigInt::from_parts(self.negative != other.negative, prod)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_digits(&self.digits, &other.digits),
+            (true, true) => cmp_digits(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Peel off base 10^9 digits from the low end.
+        let mut digits = self.digits.clone();
+        let mut chunks = Vec::new();
+        while !digits.is_empty() {
+            let mut rem = 0u64;
+            for d in digits.iter_mut().rev() {
+                let cur = rem << 32 | u64::from(*d);
+                *d = (cur / 1_000_000_000) as u32;
+                rem = cur % 1_000_000_000;
+            }
+            chunks.push(rem);
+            while digits.last() == Some(&0) {
+                digits.pop();
+            }
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        for chunk in chunks {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Intcode {
+    /// Builder for choosing the arithmetic policy.
+    pub fn with_arith(mut self, arith: Arith) -> Self {
+        self.arith = arith;
+        self
+    }
+
+    /// Choose the arithmetic policy.
+    pub fn set_arith(&mut self, arith: Arith) {
+        self.arith = arith;
+    }
+
+    /// The current arithmetic policy.
+    pub fn arith(&self) -> Arith {
+        self.arith
+    }
+
+    /// The full value of the most recent output, if it was
+    /// too large for an `i64`.
+    pub fn big_output(&self) -> Option<&BigInt> {
+        self.big_output.as_ref()
+    }
+
+    /// Retrieve the full value at the given address, or an
+    /// error if the address is out of range.
+    pub fn try_peek_big(
+        &self,
+        addr: usize,
+    ) -> Result<BigInt, IntcodeError> {
+        self.try_peek(addr)?;
+        Ok(self.prog.get_big(addr).expect("big peek past end"))
+    }
+}
+
+#[test]
+fn test_bigint() {
+    let vals: Vec<i64> = vec![
+        0,
+        1,
+        -1,
+        7,
+        -1000,
+        u32::MAX as i64,
+        1 << 32,
+        -(1 << 40) + 3,
+        i64::MAX,
+        i64::MIN,
+    ];
+    for &a in &vals {
+        let big_a = BigInt::from(a);
+        assert_eq!(big_a.to_i64(), Some(a));
+        assert_eq!(big_a.to_string(), a.to_string());
+        for &b in &vals {
+            let big_b = BigInt::from(b);
+            let (a, b) = (a as i128, b as i128);
+            let sum = &big_a + &big_b;
+            assert_eq!(sum.to_string(), (a + b).to_string());
+            assert_eq!(sum.low_i64(), (a + b) as i64);
+            let prod = &big_a * &big_b;
+            assert_eq!(prod.to_string(), (a * b).to_string());
+            assert_eq!(big_a.cmp(&big_b), a.cmp(&b));
+        }
+    }
+    let mut x = BigInt::from(1);
+    for _ in 0..30 {
+        x = &x * &BigInt::from(-1000);
+    }
+    assert_eq!(x.to_string(), format!("1{}", "0".repeat(90)));
+    assert_eq!(x.to_i64(), None);
+}
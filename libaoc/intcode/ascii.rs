@@ -0,0 +1,295 @@
+//! ASCII text interfaces to Intcode programs.
+//!
+//! Many Intcode programs talk to the user in ASCII, one
+//! character per input or output, often finishing with a
+//! single large non-ASCII output carrying the answer. The
+//! helpers here feed text to a machine and read text back,
+//! and `AsciiStream` wraps a machine as a `BufRead` and
+//! `Write` for use with standard text tooling.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::AsciiEnd;
+//!
+//! // Prompt, echo a line, then report 1000.
+//! let prog = aoc::assemble("
+//!         out #62
+//!         out #32
+//! loop:   in [c]
+//!         out [c]
+//!         eq [c], #10, [t]
+//!         jf [t], #loop
+//!         out #1000
+//!         halt
+//! c:      .data 0
+//! t:      .data 0
+//! ").unwrap();
+//! let mut ic = aoc::Intcode::new(prog);
+//! assert_eq!(ic.read_ascii_until("> ").text, "> ");
+//! ic.add_ascii_input("hello\n");
+//! assert_eq!(ic.read_ascii_line().text, "hello\n");
+//! assert_eq!(ic.read_ascii_line().end, AsciiEnd::Value(1000));
+//! ```
+
+use std::io::{self, BufRead, Read, Write};
+
+use super::{Intcode, Terminus};
+
+/// How reading ASCII output from a machine ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsciiEnd {
+    /// The requested newline or prompt was output.
+    Matched,
+    /// The machine output the given non-ASCII value.
+    Value(i64),
+    /// The machine suspended for some reason other than
+    /// output.
+    Suspended(Terminus),
+}
+
+/// Text read from a machine, and how the read ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsciiText {
+    /// ASCII output read, including any newline or prompt
+    /// matched.
+    pub text: String,
+    /// Why reading stopped.
+    pub end: AsciiEnd,
+}
+
+/// True if the given Intcode output is an ASCII character.
+pub fn is_ascii_output(val: i64) -> bool {
+    (0..128).contains(&val)
+}
+
+// Append the given output to a byte buffer: ASCII as a
+// character, anything else in decimal on a line of its own.
+pub(super) fn encode_ascii(val: i64, buf: &mut Vec<u8>) {
+    if is_ascii_output(val) {
+        buf.push(val as u8);
+    } else {
+        buf.extend(format!("{}\n", val).bytes());
+    }
+}
+
+/// Split the given outputs into their text and a trailing
+/// non-ASCII result, if there is one. Any other non-ASCII
+/// values appear in the text in decimal on a line of their
+/// own.
+pub fn split_ascii(outputs: &[i64]) -> (String, Option<i64>) {
+    let (text, result) = match outputs.split_last() {
+        Some((&last, rest)) if !is_ascii_output(last) => {
+            (rest, Some(last))
+        }
+        _ => (outputs, None),
+    };
+    let mut buf = Vec::new();
+    for &val in text {
+        encode_ascii(val, &mut buf);
+    }
+    (String::from_utf8(buf).expect("non-UTF-8 ASCII"), result)
+}
+
+impl Intcode {
+    /// Builder for adding the bytes of the given text as
+    /// inputs before running, as with `add_ascii_input()`.
+    pub fn with_ascii_input(mut self, text: &str) -> Self {
+        self.add_ascii_input(text);
+        self
+    }
+
+    /// Add the bytes of the given text as inputs. Text that
+    /// is not ASCII goes in as its UTF-8 encoding, as it
+    /// would through `AsciiStream`.
+    pub fn add_ascii_input(&mut self, text: &str) {
+        self.inputs.extend(text.bytes().map(i64::from));
+    }
+
+    /// Run until the machine outputs a newline, returning
+    /// the line read.
+    ///
+    /// # Panics
+    /// Will panic on an execution fault.
+    pub fn read_ascii_line(&mut self) -> AsciiText {
+        self.read_ascii_until("\n")
+    }
+
+    /// Run until the text output ends with the given
+    /// prompt, returning the text read.
+    ///
+    /// # Panics
+    /// Will panic on an execution fault.
+    pub fn read_ascii_until(&mut self, prompt: &str) -> AsciiText {
+        let mut text = String::new();
+        while !text.ends_with(prompt) {
+            let end = match self.run() {
+                Terminus::HaveOutput(val) if is_ascii_output(val) => {
+                    text.push(val as u8 as char);
+                    continue;
+                }
+                Terminus::HaveOutput(val) => AsciiEnd::Value(val),
+                t => AsciiEnd::Suspended(t),
+            };
+            return AsciiText { text, end };
+        }
+        AsciiText {
+            text,
+            end: AsciiEnd::Matched,
+        }
+    }
+
+    /// Run until the machine halts, returning its text
+    /// output and any trailing non-ASCII result as with
+    /// `split_ascii()`.
+    ///
+    /// # Panics
+    /// Will panic as with `collect_outputs()`.
+    pub fn collect_ascii(&mut self) -> (String, Option<i64>) {
+        split_ascii(&self.collect_outputs())
+    }
+
+    /// Wrap this machine as a text stream.
+    pub fn ascii_stream(self) -> AsciiStream {
+        AsciiStream {
+            machine: self,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+/// An Intcode machine as a text stream. Reading runs the
+/// machine for output, with non-ASCII values read in
+/// decimal on a line of their own. Reading returns end of
+/// file whenever the machine halts or needs input, so more
+/// can be read after writing. Writing supplies input.
+#[derive(Debug, Clone)]
+pub struct AsciiStream {
+    machine: Intcode,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl AsciiStream {
+    /// The underlying machine.
+    pub fn machine(&self) -> &Intcode {
+        &self.machine
+    }
+
+    /// Mutable access to the underlying machine.
+    pub fn machine_mut(&mut self) -> &mut Intcode {
+        &mut self.machine
+    }
+
+    /// Give back the underlying machine. Any output read
+    /// from it but not yet consumed is lost.
+    pub fn into_inner(self) -> Intcode {
+        self.machine
+    }
+}
+
+/// Execution faults are reported as `ErrorKind::Other`
+/// errors, and running out of fuel as
+/// `ErrorKind::TimedOut`.
+impl BufRead for AsciiStream {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+            let t = self.machine.try_run().map_err(io::Error::other)?;
+            match t {
+                Terminus::HaveOutput(val) => {
+                    encode_ascii(val, &mut self.buf)
+                }
+                Terminus::Halted | Terminus::NeedInput => (),
+                Terminus::OutOfFuel => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Intcode machine out of fuel",
+                    ));
+                }
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+impl Read for AsciiStream {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let avail = self.fill_buf()?;
+        let n = avail.len().min(out.len());
+        out[..n].copy_from_slice(&avail[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl Write for AsciiStream {
+    fn write(&mut self, text: &[u8]) -> io::Result<usize> {
+        self.machine
+            .inputs
+            .extend(text.iter().map(|&b| i64::from(b)));
+        Ok(text.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_ascii() {
+    let prog = super::assemble(
+        "
+                out #62
+                out #32
+        loop:   in [c]
+                out [c]
+                eq [c], #10, [t]
+                jf [t], #loop
+                out #1000
+                halt
+        c:      .data 0
+        t:      .data 0
+        ",
+    )
+    .unwrap();
+
+    let mut ic = Intcode::new(prog.clone());
+    let prompt = ic.read_ascii_until("> ");
+    assert_eq!(prompt.end, AsciiEnd::Matched);
+    let line = ic.read_ascii_line();
+    assert_eq!(line.text, "");
+    assert_eq!(line.end, AsciiEnd::Suspended(Terminus::NeedInput));
+    ic.add_ascii_input("hi");
+    let line = ic.read_ascii_line();
+    assert_eq!(line.text, "hi");
+    assert_eq!(line.end, AsciiEnd::Suspended(Terminus::NeedInput));
+
+    let ic = Intcode::new(prog.clone()).with_ascii_input("ok\n");
+    let (text, result) = ic.clone().collect_ascii();
+    assert_eq!(text, "> ok\n");
+    assert_eq!(result, Some(1000));
+    assert_eq!(split_ascii(&[200, 65]), ("200\nA".to_string(), None));
+
+    let mut stream = Intcode::new(prog).ascii_stream();
+    let mut line = String::new();
+    stream.read_line(&mut line).unwrap();
+    assert_eq!(line, "> ");
+    writeln!(stream, "yo").unwrap();
+    let lines: Vec<String> =
+        stream.lines().collect::<io::Result<_>>().unwrap();
+    assert_eq!(lines, vec!["yo", "1000"]);
+
+    // Both ways of giving input encode text as bytes.
+    let ic = Intcode::new(vec![99]).with_ascii_input("é");
+    let mut stream = Intcode::new(vec![99]).ascii_stream();
+    write!(stream, "é").unwrap();
+    assert_eq!(ic.pending_inputs(), vec![0xc3, 0xa9]);
+    assert_eq!(ic.pending_inputs(), stream.machine().pending_inputs());
+}
@@ -0,0 +1,548 @@
+//! Intcode assembler.
+//!
+//! Turns a small textual mnemonic language into a program
+//! vector suitable for `Intcode::new()`. Each line has the
+//! form
+//!
+//! ```text
+//! [label:]... [mnemonic opnd, opnd, ...] [; comment]
+//! ```
+//!
+//! The mnemonics are `add`, `mul`, `in`, `out`, `jt`, `jf`,
+//! `lt`, `eq`, `rbo` and `halt`. Operands are written
+//! `[addr]` for position mode, `#value` for immediate mode
+//! and `rb+offset` (or `rb-offset`, or just `rb`) for
+//! relative mode. Addresses, values and offsets are
+//! integers, labels, or sums and differences of these; a
+//! label stands for the address at which it is defined.
+//!
+//! The directive `.data v, v, ...` places the given values
+//! directly in memory, and `.zero n` places `n` zeros, for
+//! `n` up to 2^24.
+//!
+//! # Examples
+//!
+//! ```rust
+//! let prog = aoc::assemble("
+//!     in [x]          ; read x
+//!     mul [x], #2, [x]
+//!     out [x]
+//!     halt
+//! x:  .data 0
+//! ").unwrap();
+//! assert_eq!(prog, vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0]);
+//! let mut ic = aoc::Intcode::new(prog).with_inputs(vec![21]);
+//! assert_eq!(ic.collect_outputs(), vec![42]);
+//! ```
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::{Opcode, OpndMode};
+
+// Largest count allowed for `.zero`.
+const MAX_ZERO: u64 = 1 << 24;
+
+/// Error produced when assembly fails. Line and column
+/// numbers start at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+impl AsmError {
+    fn new(line: usize, col: usize, msg: impl Into<String>) -> Self {
+        Self {
+            line,
+            col,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.msg)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// Tokens of the assembly language, without positions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Num(u64),
+    Punct(char),
+}
+
+// Split a line into tokens, each with its column. Comments
+// have already been removed.
+fn tokenize(
+    text: &str,
+    line: usize,
+) -> Result<Vec<(Token, usize)>, AsmError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let col = i + 1;
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let digits: String = chars[start..i].iter().collect();
+            let n = digits.parse().map_err(|_| {
+                AsmError::new(line, col, "number out of range")
+            })?;
+            tokens.push((Token::Num(n), col));
+        } else if c.is_alphabetic() || c == '_' || c == '.' {
+            let start = i;
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_')
+            {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().collect();
+            tokens.push((Token::Ident(ident), col));
+        } else if ":,#[]+-".contains(c) {
+            tokens.push((Token::Punct(c), col));
+            i += 1;
+        } else {
+            let msg = format!("unexpected character {:?}", c);
+            return Err(AsmError::new(line, col, msg));
+        }
+    }
+    Ok(tokens)
+}
+
+// An expression: a signed sum of numbers and labels, each
+// with its column for error reporting. Numbers are kept as
+// magnitudes, so that `-9223372036854775808` can be
+// written.
+type Expr = Vec<(i64, Atom, usize)>;
+
+#[derive(Debug, Clone)]
+enum Atom {
+    Num(u64),
+    Label(String),
+}
+
+// A parsed operand, not yet resolved.
+#[derive(Debug, Clone)]
+struct Opnd {
+    mode: OpndMode,
+    expr: Expr,
+    col: usize,
+}
+
+// A parsed statement.
+#[derive(Debug, Clone)]
+enum Stmt {
+    Insn(Opcode, Vec<Opnd>),
+    Data(Vec<Expr>),
+    Zero(usize),
+}
+
+// Recursive-descent parser over the tokens of one line.
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    posn: usize,
+    line: usize,
+    // Column just past the end of the line, for errors at
+    // end of input.
+    eol: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.posn).map(|(t, _)| t)
+    }
+
+    fn col(&self) -> usize {
+        self.tokens
+            .get(self.posn)
+            .map(|&(_, c)| c)
+            .unwrap_or(self.eol)
+    }
+
+    fn error<T>(&self, msg: impl Into<String>) -> Result<T, AsmError> {
+        Err(AsmError::new(self.line, self.col(), msg))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.posn).map(|(t, _)| t.clone());
+        self.posn += 1;
+        t
+    }
+
+    fn at_end(&self) -> bool {
+        self.posn >= self.tokens.len()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.posn += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), AsmError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}'", c))
+        }
+    }
+
+    // Parse a signed sum of numbers and labels.
+    fn expr(&mut self) -> Result<Expr, AsmError> {
+        let mut expr = Vec::new();
+        let mut sign = if self.eat('-') {
+            -1
+        } else {
+            self.eat('+');
+            1
+        };
+        loop {
+            let col = self.col();
+            let atom = match self.next() {
+                Some(Token::Num(n)) => Atom::Num(n),
+                Some(Token::Ident(ref l)) if is_label(l) => {
+                    Atom::Label(l.clone())
+                }
+                _ => {
+                    self.posn -= 1;
+                    return self.error("expected number or label");
+                }
+            };
+            expr.push((sign, atom, col));
+            sign = if self.eat('+') {
+                1
+            } else if self.eat('-') {
+                -1
+            } else {
+                return Ok(expr);
+            };
+        }
+    }
+
+    // Parse an instruction operand.
+    fn opnd(&mut self) -> Result<Opnd, AsmError> {
+        let col = self.col();
+        if self.eat('#') {
+            let expr = self.expr()?;
+            return Ok(Opnd {
+                mode: OpndMode::Imm,
+                expr,
+                col,
+            });
+        }
+        if self.eat('[') {
+            let expr = self.expr()?;
+            self.expect(']')?;
+            return Ok(Opnd {
+                mode: OpndMode::Pos,
+                expr,
+                col,
+            });
+        }
+        if self.peek() == Some(&Token::Ident("rb".to_string())) {
+            self.posn += 1;
+            let expr = match self.peek() {
+                Some(Token::Punct('+')) | Some(Token::Punct('-')) => {
+                    self.expr()?
+                }
+                _ => vec![(1, Atom::Num(0), col)],
+            };
+            return Ok(Opnd {
+                mode: OpndMode::Rel,
+                expr,
+                col,
+            });
+        }
+        self.error("expected operand")
+    }
+
+    // Parse a comma-separated list of at least one item.
+    fn list<T, F>(&mut self, mut item: F) -> Result<Vec<T>, AsmError>
+    where
+        F: FnMut(&mut Self) -> Result<T, AsmError>,
+    {
+        let mut items = vec![item(self)?];
+        while self.eat(',') {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    // Parse the statement following any labels.
+    fn stmt(&mut self) -> Result<Stmt, AsmError> {
+        let col = self.col();
+        let name = match self.next() {
+            Some(Token::Ident(name)) => name,
+            _ => {
+                self.posn -= 1;
+                return self.error("expected mnemonic or directive");
+            }
+        };
+        let stmt = match name.as_str() {
+            ".data" => Stmt::Data(self.list(Self::expr)?),
+            ".zero" => match self.next() {
+                Some(Token::Num(n)) if n <= MAX_ZERO => {
+                    Stmt::Zero(n as usize)
+                }
+                Some(Token::Num(_)) => {
+                    self.posn -= 1;
+                    return self.error("count too large");
+                }
+                _ => {
+                    self.posn -= 1;
+                    return self.error("expected count");
+                }
+            },
+            _ => {
+                let op =
+                    Opcode::from_mnemonic(&name).ok_or_else(|| {
+                        let msg = format!("unknown mnemonic {}", name);
+                        AsmError::new(self.line, col, msg)
+                    })?;
+                let opnds = if self.at_end() {
+                    Vec::new()
+                } else {
+                    self.list(Self::opnd)?
+                };
+                if opnds.len() != op.nopnds() {
+                    let msg = format!(
+                        "{} takes {} operands, got {}",
+                        name,
+                        op.nopnds(),
+                        opnds.len(),
+                    );
+                    return Err(AsmError::new(self.line, col, msg));
+                }
+                if let Some(i) = op.store_opnd() {
+                    if opnds[i].mode == OpndMode::Imm {
+                        let msg = "store to immediate operand";
+                        return Err(AsmError::new(
+                            self.line,
+                            opnds[i].col,
+                            msg,
+                        ));
+                    }
+                }
+                Stmt::Insn(op, opnds)
+            }
+        };
+        if !self.at_end() {
+            return self.error("junk at end of line");
+        }
+        Ok(stmt)
+    }
+}
+
+// Is this identifier usable as a label?
+fn is_label(ident: &str) -> bool {
+    !ident.starts_with('.') && ident != "rb"
+}
+
+// Compute the value of an expression given the label
+// addresses.
+fn resolve(
+    expr: &[(i64, Atom, usize)],
+    labels: &HashMap<String, usize>,
+    line: usize,
+) -> Result<i64, AsmError> {
+    // Summed wider than a word, so that only the final
+    // value need fit.
+    let mut total: i128 = 0;
+    let mut last_col = 0;
+    for (sign, atom, col) in expr {
+        let val = match atom {
+            Atom::Num(n) => *n as i128,
+            Atom::Label(l) => match labels.get(l) {
+                Some(&addr) => addr as i128,
+                None => {
+                    let msg = format!("undefined label {}", l);
+                    return Err(AsmError::new(line, *col, msg));
+                }
+            },
+        };
+        total += *sign as i128 * val;
+        last_col = *col;
+    }
+    i64::try_from(total)
+        .map_err(|_| AsmError::new(line, last_col, "value overflow"))
+}
+
+/// Assemble the given source text into an Intcode program
+/// vector. Returns an error with the line and column of the
+/// first problem encountered.
+pub fn assemble(src: &str) -> Result<Vec<i64>, AsmError> {
+    // First pass: parse, and find label addresses.
+    let mut labels = HashMap::new();
+    let mut stmts = Vec::new();
+    let mut addr = 0;
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        let text = match text.find(';') {
+            Some(c) => &text[..c],
+            None => text,
+        };
+        let tokens = tokenize(text, line)?;
+        let eol = text.chars().count() + 1;
+        let mut parser = Parser {
+            tokens,
+            posn: 0,
+            line,
+            eol,
+        };
+        while let (
+            Some((Token::Ident(l), col)),
+            Some((Token::Punct(':'), _)),
+        ) = (
+            parser.tokens.get(parser.posn),
+            parser.tokens.get(parser.posn + 1),
+        ) {
+            if !is_label(l) {
+                return Err(AsmError::new(
+                    line,
+                    *col,
+                    "reserved label",
+                ));
+            }
+            if labels.insert(l.clone(), addr).is_some() {
+                let msg = format!("duplicate label {}", l);
+                return Err(AsmError::new(line, *col, msg));
+            }
+            parser.posn += 2;
+        }
+        if parser.at_end() {
+            continue;
+        }
+        let stmt = parser.stmt()?;
+        addr += match &stmt {
+            Stmt::Insn(op, _) => 1 + op.nopnds(),
+            Stmt::Data(vals) => vals.len(),
+            Stmt::Zero(n) => *n,
+        };
+        stmts.push((line, stmt));
+    }
+
+    // Second pass: emit code.
+    let mut prog = Vec::with_capacity(addr);
+    for (line, stmt) in stmts {
+        match stmt {
+            Stmt::Insn(op, opnds) => {
+                let mut insn = op.code();
+                let mut scale = 100;
+                for opnd in &opnds {
+                    insn += scale * opnd.mode.code();
+                    scale *= 10;
+                }
+                prog.push(insn);
+                for opnd in &opnds {
+                    prog.push(resolve(&opnd.expr, &labels, line)?);
+                }
+            }
+            Stmt::Data(vals) => {
+                for val in &vals {
+                    prog.push(resolve(val, &labels, line)?);
+                }
+            }
+            Stmt::Zero(n) => prog.resize(prog.len() + n, 0),
+        }
+    }
+    Ok(prog)
+}
+
+#[test]
+fn test_assemble() {
+    // The Day 5 "compare to 8" examples.
+    let prog = assemble(
+        "
+        in [9]
+        eq [9], [10], [9]
+        out [9]
+        halt
+        .data -1, 8
+        ",
+    )
+    .unwrap();
+    assert_eq!(prog, vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+
+    let prog = assemble(
+        "
+        in [3]
+        eq #-1, #8, [3]   ; input overwrites the -1
+        out [3]
+        halt
+        ",
+    )
+    .unwrap();
+    assert_eq!(prog, vec![3, 3, 1108, -1, 8, 3, 4, 3, 99]);
+
+    // Labels, relative mode and jumps: count down from the
+    // input, printing each value.
+    let prog = assemble(
+        "
+            rbo #n
+            in rb
+        loop:
+            out rb+0
+            add rb, #-1, rb
+            jt [n], #loop
+        done: halt
+        n:  .zero 1
+        ",
+    )
+    .unwrap();
+    let mut ic = super::Intcode::new(prog).with_inputs(vec![3]);
+    assert_eq!(ic.collect_outputs(), vec![3, 2, 1]);
+
+    // The full range of words, including sums that pass
+    // through values out of range.
+    let prog = assemble(
+        "
+        .data -9223372036854775808, 9223372036854775807
+        .data 9223372036854775808 - 1, -1 - 9223372036854775807
+        out rb-9223372036854775808
+        ",
+    )
+    .unwrap();
+    let (min, max) = (i64::MIN, i64::MAX);
+    assert_eq!(prog, vec![min, max, max, min, 204, min]);
+}
+
+#[test]
+fn test_assemble_errors() {
+    let cases: &[(&str, (usize, usize))] = &[
+        ("halt\nfoo [1]", (2, 1)),
+        ("add [1], [2]", (1, 1)),
+        ("add [1], [2], #3", (1, 15)),
+        ("  jt #1, #nowhere", (1, 11)),
+        ("x: halt\nx: halt", (2, 1)),
+        ("out [1", (1, 7)),
+        ("out @", (1, 5)),
+        (".data 1,", (1, 9)),
+        ("halt 3", (1, 6)),
+        (".data 9223372036854775808", (1, 7)),
+        (".data -9223372036854775807 - 2", (1, 30)),
+        (".data 99999999999999999999", (1, 7)),
+        (".zero 99999999999999", (1, 7)),
+    ];
+    for &(src, (line, col)) in cases {
+        let err = assemble(src).unwrap_err();
+        assert_eq!((err.line, err.col), (line, col), "{}", err);
+    }
+}
@@ -0,0 +1,557 @@
+//! Static control-flow analysis of Intcode programs.
+//!
+//! `analyze()` finds the code reachable from address 0 by
+//! following jumps with immediate targets, splits it into
+//! basic blocks, and builds a control-flow graph. Unlike
+//! the linear sweep of `disassemble()`, this does not
+//! mistake data for code, although it cannot follow jumps
+//! whose targets are computed.
+//!
+//! Programs compiled for Advent of Code keep a stack of
+//! frames addressed through the relative base. A call
+//! stores the return address at `rb+0` and jumps
+//! unconditionally to the subroutine, which moves the
+//! relative base up past its frame with `rbo #n` and
+//! returns by moving it back and jumping to `rb+0`. These
+//! idioms are recognized, so that a call is followed by its
+//! return address as well as its target, and subroutines
+//! are collected along with their frame sizes.
+//!
+//! Stores with position-mode destinations inside the code
+//! found are reported as self-modifying.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::BlockEnd;
+//!
+//! let prog = aoc::assemble("
+//!         in [x]
+//!         jt [x], #skip
+//!         out #1
+//! skip:   halt
+//! x:      .data 0
+//! ").unwrap();
+//! let cfg = aoc::analyze(&prog);
+//! let entry = cfg.block(0).unwrap();
+//! assert_eq!(entry.end, BlockEnd::Branch { taken: 7, next: 5 });
+//! assert_eq!(cfg.blocks().count(), 3);
+//! assert!(cfg.to_dot().contains("b0 -> b7"));
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::{decode, Insn, Opcode, Operand};
+
+/// How a basic block ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockEnd {
+    /// Falls through to the block at the given address.
+    Next(usize),
+    /// Jumps unconditionally to the given address.
+    Jump(usize),
+    /// Jumps conditionally to `taken`, or falls through to
+    /// `next`.
+    Branch { taken: usize, next: usize },
+    /// Calls the subroutine at `target`, which returns to
+    /// `ret`.
+    Call { target: usize, ret: usize },
+    /// Returns from a subroutine.
+    Return,
+    /// Jumps to a computed address.
+    Indirect,
+    /// Halts.
+    Halt,
+    /// Runs into a word that is not a legal instruction, or
+    /// off the end of the program.
+    Invalid,
+}
+
+/// Kind of control-flow edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Falling through, or a branch not taken.
+    Next,
+    /// A jump or branch taken.
+    Taken,
+    /// A subroutine call.
+    Call,
+    /// The return from a subroutine call to the
+    /// instruction after it.
+    AfterCall,
+}
+
+/// A control-flow edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    /// Address of the block the edge leaves.
+    pub from: usize,
+    /// Address of the block the edge enters.
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// A basic block: a run of instructions entered only at
+/// the top and left only at the bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// Address of the first instruction.
+    pub start: usize,
+    /// The instructions, with their addresses. Empty if a
+    /// jump targets a word that is not a legal
+    /// instruction.
+    pub insns: Vec<(usize, Insn)>,
+    pub end: BlockEnd,
+}
+
+impl Block {
+    /// The edges leaving this block.
+    pub fn succs(&self) -> Vec<Edge> {
+        let edge = |to, kind| Edge {
+            from: self.start,
+            to,
+            kind,
+        };
+        match self.end {
+            BlockEnd::Next(next) => vec![edge(next, EdgeKind::Next)],
+            BlockEnd::Jump(target) => {
+                vec![edge(target, EdgeKind::Taken)]
+            }
+            BlockEnd::Branch { taken, next } => vec![
+                edge(taken, EdgeKind::Taken),
+                edge(next, EdgeKind::Next),
+            ],
+            BlockEnd::Call { target, ret } => vec![
+                edge(target, EdgeKind::Call),
+                edge(ret, EdgeKind::AfterCall),
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A subroutine found through a recognized call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subroutine {
+    /// Address of the first instruction.
+    pub entry: usize,
+    /// Frame size, from an `rbo` with an immediate operand
+    /// in the entry block.
+    pub frame: Option<i64>,
+    /// Addresses of the blocks reachable from the entry
+    /// without following calls.
+    pub blocks: Vec<usize>,
+    /// Addresses of the return instructions among them.
+    pub returns: Vec<usize>,
+}
+
+/// A store from code into code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfModify {
+    /// Address of the storing instruction.
+    pub ip: usize,
+    /// Address stored to.
+    pub target: usize,
+}
+
+/// Control-flow graph of a program, as built by
+/// `analyze()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    blocks: BTreeMap<usize, Block>,
+    subroutines: Vec<Subroutine>,
+    self_modifying: Vec<SelfModify>,
+}
+
+// How a single instruction transfers control, if it does.
+fn control(addr: usize, insn: &Insn) -> Option<BlockEnd> {
+    let next = addr + insn.size();
+    let jt = match insn.op {
+        Opcode::Halt => return Some(BlockEnd::Halt),
+        Opcode::JumpIfTrue => true,
+        Opcode::JumpIfFalse => false,
+        _ => return None,
+    };
+    let always = match insn.opnds[0] {
+        Operand::Imm(v) if (v != 0) != jt => {
+            return Some(BlockEnd::Next(next))
+        }
+        Operand::Imm(_) => true,
+        _ => false,
+    };
+    Some(match insn.opnds[1] {
+        Operand::Imm(t) if t >= 0 && always => {
+            BlockEnd::Jump(t as usize)
+        }
+        Operand::Imm(t) if t >= 0 => BlockEnd::Branch {
+            taken: t as usize,
+            next,
+        },
+        Operand::Rel(_) if always => BlockEnd::Return,
+        _ => BlockEnd::Indirect,
+    })
+}
+
+// True if the given instruction stores the given constant
+// at `rb+0`, where calls keep the return address.
+fn stores_const(insn: &Insn, val: i64) -> bool {
+    match (insn.op, &insn.opnds[..]) {
+        (
+            Opcode::Add,
+            &[Operand::Imm(a), Operand::Imm(b), Operand::Rel(0)],
+        ) => a.checked_add(b) == Some(val),
+        (
+            Opcode::Mul,
+            &[Operand::Imm(a), Operand::Imm(b), Operand::Rel(0)],
+        ) => a.checked_mul(b) == Some(val),
+        _ => false,
+    }
+}
+
+/// Build the control-flow graph of the code reachable from
+/// address 0 of the given program.
+pub fn analyze(prog: &[i64]) -> Cfg {
+    let mut insns: BTreeMap<usize, Insn> = BTreeMap::new();
+    let mut ends: BTreeMap<usize, BlockEnd> = BTreeMap::new();
+    let mut leaders: BTreeSet<usize> = BTreeSet::new();
+    let mut entries: BTreeSet<usize> = BTreeSet::new();
+    let mut work = vec![0];
+
+    // Walk the code, one straight-line run at a time.
+    while let Some(start) = work.pop() {
+        if !leaders.insert(start) || insns.contains_key(&start) {
+            continue;
+        }
+        let mut run: Vec<Insn> = Vec::new();
+        let mut addr = start;
+        while let Some(insn) = decode(prog, addr) {
+            if insns.contains_key(&addr) && addr != start {
+                leaders.insert(addr);
+                break;
+            }
+            let mut end = control(addr, &insn);
+            let next = addr + insn.size();
+            if let Some(BlockEnd::Jump(target)) = end {
+                let ret = next as i64;
+                if run.iter().any(|i| stores_const(i, ret)) {
+                    end = Some(BlockEnd::Call { target, ret: next });
+                    entries.insert(target);
+                }
+            }
+            insns.insert(addr, insn.clone());
+            if let Some(end) = end {
+                ends.insert(addr, end);
+                let succs = Block {
+                    start,
+                    insns: Vec::new(),
+                    end,
+                }
+                .succs();
+                work.extend(succs.iter().map(|e| e.to));
+                break;
+            }
+            run.push(insn);
+            addr = next;
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in &leaders {
+        let mut block = Block {
+            start,
+            insns: Vec::new(),
+            end: BlockEnd::Invalid,
+        };
+        let mut addr = start;
+        while let Some(insn) = insns.get(&addr) {
+            block.insns.push((addr, insn.clone()));
+            if let Some(&end) = ends.get(&addr) {
+                block.end = end;
+                break;
+            }
+            addr += insn.size();
+            if leaders.contains(&addr) {
+                block.end = BlockEnd::Next(addr);
+                break;
+            }
+        }
+        blocks.insert(start, block);
+    }
+
+    let mut cfg = Cfg {
+        blocks,
+        subroutines: Vec::new(),
+        self_modifying: Vec::new(),
+    };
+    cfg.subroutines =
+        entries.iter().map(|&entry| cfg.subroutine(entry)).collect();
+    cfg.self_modifying = cfg.find_self_modifying();
+    cfg
+}
+
+impl Cfg {
+    /// The basic blocks, in address order.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    /// The block starting at the given address.
+    pub fn block(&self, start: usize) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    /// The block containing an instruction starting at the
+    /// given address.
+    pub fn block_containing(&self, addr: usize) -> Option<&Block> {
+        let (_, block) = self.blocks.range(..=addr).next_back()?;
+        if block.insns.iter().any(|&(a, _)| a == addr) {
+            Some(block)
+        } else {
+            None
+        }
+    }
+
+    /// All the edges of the graph.
+    pub fn edges(&self) -> Vec<Edge> {
+        self.blocks().flat_map(|b| b.succs()).collect()
+    }
+
+    /// The subroutines found, in address order.
+    pub fn subroutines(&self) -> &[Subroutine] {
+        &self.subroutines
+    }
+
+    /// The stores into code found, in address order.
+    pub fn self_modifying(&self) -> &[SelfModify] {
+        &self.self_modifying
+    }
+
+    /// True if the given address is part of an instruction
+    /// found.
+    pub fn is_code(&self, addr: usize) -> bool {
+        self.containing_block(addr).is_some()
+    }
+
+    // Gather the subroutine with the given entry point.
+    fn subroutine(&self, entry: usize) -> Subroutine {
+        let frame = self.block(entry).and_then(|block| {
+            block.insns.iter().find_map(|(_, insn)| {
+                match (insn.op, &insn.opnds[..]) {
+                    (Opcode::RBO, &[Operand::Imm(n)]) => Some(n),
+                    _ => None,
+                }
+            })
+        });
+        let mut seen = BTreeSet::new();
+        let mut work = vec![entry];
+        while let Some(start) = work.pop() {
+            let block = match self.block(start) {
+                Some(block) if seen.insert(start) => block,
+                _ => continue,
+            };
+            for edge in block.succs() {
+                if edge.kind != EdgeKind::Call {
+                    work.push(edge.to);
+                }
+            }
+        }
+        let returns = seen
+            .iter()
+            .filter_map(|start| {
+                let block = &self.blocks[start];
+                match (block.end, block.insns.last()) {
+                    (BlockEnd::Return, Some(&(addr, _))) => Some(addr),
+                    _ => None,
+                }
+            })
+            .collect();
+        Subroutine {
+            entry,
+            frame,
+            blocks: seen.into_iter().collect(),
+            returns,
+        }
+    }
+
+    // Find the position-mode stores into code.
+    fn find_self_modifying(&self) -> Vec<SelfModify> {
+        let mut found = Vec::new();
+        for block in self.blocks() {
+            for (ip, insn) in &block.insns {
+                let store = insn.op.store_opnd().map(|i| insn.opnds[i]);
+                if let Some(Operand::Pos(target)) = store {
+                    if target >= 0 && self.is_code(target as usize) {
+                        found.push(SelfModify {
+                            ip: *ip,
+                            target: target as usize,
+                        });
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Render the graph in Graphviz DOT format. Each block
+    /// is a node listing its instructions. Subroutine
+    /// entries are drawn in bold, calls dashed, returns to
+    /// after a call dotted, and self-modified blocks
+    /// filled.
+    pub fn to_dot(&self) -> String {
+        let entries: BTreeSet<usize> =
+            self.subroutines.iter().map(|s| s.entry).collect();
+        let modified: BTreeSet<usize> = self
+            .self_modifying
+            .iter()
+            .filter_map(|m| self.containing_block(m.target))
+            .collect();
+        let mut dot = String::from("digraph intcode {\n");
+        dot += "    node [shape=box, fontname=\"monospace\"];\n";
+        for block in self.blocks() {
+            let mut label = String::new();
+            for (addr, insn) in &block.insns {
+                let _ = write!(label, "{}: {}\\l", addr, insn);
+            }
+            if block.insns.is_empty() {
+                let _ = write!(label, "{}: ?\\l", block.start);
+            }
+            match block.end {
+                BlockEnd::Return => label += "return\\l",
+                BlockEnd::Indirect => label += "indirect jump\\l",
+                BlockEnd::Invalid => label += "invalid\\l",
+                _ => (),
+            }
+            let mut attrs = format!("label=\"{}\"", label);
+            match (
+                entries.contains(&block.start),
+                modified.contains(&block.start),
+            ) {
+                (true, true) => {
+                    attrs +=
+                        ", style=\"bold,filled\", fillcolor=lightgrey"
+                }
+                (true, false) => attrs += ", style=bold",
+                (false, true) => {
+                    attrs += ", style=filled, fillcolor=lightgrey"
+                }
+                (false, false) => (),
+            }
+            let _ = writeln!(dot, "    b{} [{}];", block.start, attrs);
+        }
+        for edge in self.edges() {
+            let attrs = match edge.kind {
+                EdgeKind::Next => "",
+                EdgeKind::Taken => " [label=\"taken\"]",
+                EdgeKind::Call => " [label=\"call\", style=dashed]",
+                EdgeKind::AfterCall => " [style=dotted]",
+            };
+            let _ = writeln!(
+                dot,
+                "    b{} -> b{}{};",
+                edge.from, edge.to, attrs
+            );
+        }
+        dot += "}\n";
+        dot
+    }
+
+    // Start of the block with an instruction covering the
+    // given address.
+    fn containing_block(&self, addr: usize) -> Option<usize> {
+        let (&start, block) = self.blocks.range(..=addr).next_back()?;
+        block
+            .insns
+            .iter()
+            .any(|(a, insn)| (*a..a + insn.size()).contains(&addr))
+            .then_some(start)
+    }
+}
+
+#[test]
+fn test_cfg() {
+    let prog = super::assemble(
+        "
+                rbo #100
+                in [x]
+                add #ret1, #0, rb
+                jt #1, #double
+        ret1:   jf [x], #done
+                out [x]
+        done:   halt
+
+        double: rbo #2
+                mul [x], #2, [x]
+                add #2, #0, [patch+1]
+        patch:  add #0, #0, [y]
+                rbo #-2
+                jf #0, rb
+        x:      .data 0
+        y:      .data 0
+        ",
+    )
+    .unwrap();
+    let cfg = analyze(&prog);
+
+    let starts: Vec<usize> = cfg.blocks().map(|b| b.start).collect();
+    assert_eq!(starts, vec![0, 11, 14, 16, 17]);
+    assert_eq!(
+        cfg.block(0).unwrap().end,
+        BlockEnd::Call {
+            target: 17,
+            ret: 11
+        }
+    );
+    assert_eq!(
+        cfg.block(11).unwrap().end,
+        BlockEnd::Branch {
+            taken: 16,
+            next: 14
+        }
+    );
+    assert_eq!(cfg.block(14).unwrap().end, BlockEnd::Next(16));
+    assert_eq!(cfg.block(16).unwrap().end, BlockEnd::Halt);
+    assert_eq!(cfg.block(17).unwrap().end, BlockEnd::Return);
+    assert_eq!(cfg.block_containing(19).unwrap().start, 17);
+    assert!(cfg.block_containing(20).is_none());
+    assert!(cfg.is_code(28));
+    assert!(!cfg.is_code(36));
+
+    assert_eq!(
+        cfg.subroutines(),
+        &[Subroutine {
+            entry: 17,
+            frame: Some(2),
+            blocks: vec![17],
+            returns: vec![33],
+        }]
+    );
+    assert_eq!(
+        cfg.self_modifying(),
+        &[SelfModify { ip: 23, target: 28 }]
+    );
+
+    let dot = cfg.to_dot();
+    assert!(dot.starts_with("digraph intcode {\n"));
+    assert!(dot.contains("b0 -> b17 [label=\"call\", style=dashed];"));
+    assert!(dot.contains("b0 -> b11 [style=dotted];"));
+    assert!(dot.contains("b11 -> b16 [label=\"taken\"];"));
+    assert!(dot.contains("b14 -> b16;"));
+    assert!(dot.contains("33: jf #0, rb+0\\lreturn\\l"));
+    assert!(dot.contains("style=\"bold,filled\""));
+
+    // Data after an unconditional jump is not code, and a
+    // computed jump can't be followed.
+    let prog = vec![1105, 1, 4, 12345, 106, 0, 3, 99];
+    let cfg = analyze(&prog);
+    assert_eq!(cfg.block(0).unwrap().end, BlockEnd::Jump(4));
+    assert_eq!(cfg.block(4).unwrap().end, BlockEnd::Indirect);
+    assert_eq!(cfg.blocks().count(), 2);
+    assert!(!cfg.is_code(3));
+
+    // Only a return address stored at `rb+0` makes a call.
+    let prog = vec![21101, 7, 0, 1, 1105, 1, 7, 99];
+    let cfg = analyze(&prog);
+    assert_eq!(cfg.block(0).unwrap().end, BlockEnd::Jump(7));
+    assert!(cfg.subroutines().is_empty());
+}
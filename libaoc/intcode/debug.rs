@@ -0,0 +1,252 @@
+//! Intcode debugger.
+//!
+//! A `Debugger` wraps an `Intcode` machine and runs it with
+//! breakpoints on instruction addresses and watchpoints on
+//! memory addresses. See `bin/intcode-debug.rs` for a small
+//! interactive front end.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::{Debugger, Stop, Terminus};
+//!
+//! // Count down from 3.
+//! let prog = aoc::assemble("
+//!         in [n]
+//! loop:   out [n]
+//!         add [n], #-1, [n]
+//!         jt [n], #loop
+//!         halt
+//! n:      .data 0
+//! ").unwrap();
+//! let ic = aoc::Intcode::new(prog).with_inputs(vec![3]);
+//! let mut db = Debugger::new(ic);
+//! db.add_watch(12);
+//! assert_eq!(db.cont(), Stop::Watch { addr: 12, old: 0, new: 3 });
+//! db.add_breakpoint(11);
+//! db.remove_watch(12);
+//! assert_eq!(db.cont(), Stop::Terminus(Terminus::HaveOutput(3)));
+//! assert_eq!(db.cont(), Stop::Terminus(Terminus::HaveOutput(2)));
+//! assert_eq!(db.cont(), Stop::Terminus(Terminus::HaveOutput(1)));
+//! assert_eq!(db.cont(), Stop::Breakpoint(11));
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::{Intcode, IntcodeError, Terminus};
+
+/// Reason the debugger stopped the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// The machine suspended on its own.
+    Terminus(Terminus),
+    /// The machine reached a breakpoint at the given
+    /// address. The instruction there has not been
+    /// executed.
+    Breakpoint(usize),
+    /// The last instruction stored to a watched address.
+    Watch { addr: usize, old: i64, new: i64 },
+    /// The machine faulted. It is left at the faulting
+    /// instruction.
+    Fault(IntcodeError),
+    /// A single step completed without any other reason to
+    /// stop.
+    Stepped,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Terminus(Terminus::Halted) => write!(f, "halted"),
+            Stop::Terminus(Terminus::NeedInput) => {
+                write!(f, "need input")
+            }
+            Stop::Terminus(Terminus::HaveOutput(v)) => {
+                write!(f, "output {}", v)
+            }
+            Stop::Terminus(Terminus::OutOfFuel) => {
+                write!(f, "out of fuel")
+            }
+            Stop::Breakpoint(addr) => {
+                write!(f, "breakpoint at {}", addr)
+            }
+            Stop::Watch { addr, old, new } => {
+                write!(f, "watch [{}]: {} -> {}", addr, old, new)
+            }
+            Stop::Fault(e) => write!(f, "fault: {}", e),
+            Stop::Stepped => write!(f, "stepped"),
+        }
+    }
+}
+
+/// An Intcode machine under debugger control.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    machine: Intcode,
+    breakpoints: BTreeSet<usize>,
+    watches: BTreeSet<usize>,
+    // Breakpoint most recently reported, so that
+    // continuing does not immediately stop there again.
+    stopped_at: Option<usize>,
+}
+
+impl Debugger {
+    /// Take control of the given machine.
+    pub fn new(machine: Intcode) -> Self {
+        Self {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watches: BTreeSet::new(),
+            stopped_at: None,
+        }
+    }
+
+    /// The machine being debugged.
+    pub fn machine(&self) -> &Intcode {
+        &self.machine
+    }
+
+    /// The machine being debugged, for adding inputs or
+    /// poking memory.
+    pub fn machine_mut(&mut self) -> &mut Intcode {
+        &mut self.machine
+    }
+
+    /// Give up control of the machine.
+    pub fn into_machine(self) -> Intcode {
+        self.machine
+    }
+
+    /// Stop before executing the instruction at the given
+    /// address.
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Remove a breakpoint. Returns false if there was no
+    /// breakpoint at the given address.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Current breakpoints, in address order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().cloned()
+    }
+
+    /// Stop after any instruction that stores to the given
+    /// address.
+    pub fn add_watch(&mut self, addr: usize) {
+        self.watches.insert(addr);
+    }
+
+    /// Remove a watchpoint. Returns false if there was no
+    /// watchpoint at the given address.
+    pub fn remove_watch(&mut self, addr: usize) -> bool {
+        self.watches.remove(&addr)
+    }
+
+    /// Current watchpoints, in address order.
+    pub fn watches(&self) -> impl Iterator<Item = usize> + '_ {
+        self.watches.iter().cloned()
+    }
+
+    /// Execute a single instruction, ignoring breakpoints.
+    pub fn step(&mut self) -> Stop {
+        let old: Vec<(usize, i64)> =
+            self.watches.iter().map(|&a| (a, self.value(a))).collect();
+        let executed = self.machine.executed();
+        let result = self.machine.try_step();
+        // Only an instruction that did not execute, such as
+        // an `in` waiting for input, leaves the breakpoint
+        // reported.
+        if self.machine.executed() != executed {
+            self.stopped_at = None;
+        }
+        match result {
+            Ok(Some(t)) => return Stop::Terminus(t),
+            Ok(None) => (),
+            Err(e) => return Stop::Fault(e),
+        }
+        if let Some(addr) = self.machine.last_store() {
+            if let Some(&(_, old)) =
+                old.iter().find(|&&(a, _)| a == addr)
+            {
+                let new = self.value(addr);
+                return Stop::Watch { addr, old, new };
+            }
+        }
+        Stop::Stepped
+    }
+
+    /// Run until the machine suspends, reaches a
+    /// breakpoint or stores to a watched address. When
+    /// continuing from a breakpoint, the instruction there
+    /// is executed rather than stopping again.
+    pub fn cont(&mut self) -> Stop {
+        loop {
+            let ip = self.machine.ip();
+            if self.breakpoints.contains(&ip)
+                && self.stopped_at != Some(ip)
+            {
+                self.stopped_at = Some(ip);
+                return Stop::Breakpoint(ip);
+            }
+            match self.step() {
+                Stop::Stepped => (),
+                stop => return stop,
+            }
+        }
+    }
+
+    /// Value at the given address. Addresses past the end
+    /// of memory read as zero, as they would for the
+    /// running program.
+    pub fn value(&self, addr: usize) -> i64 {
+        self.machine.memory().get(addr).unwrap_or(0)
+    }
+
+    /// Describe the machine registers and input queue.
+    pub fn registers(&self) -> String {
+        let m = &self.machine;
+        format!(
+            "ip={} rel_base={} inputs={:?}",
+            m.ip(),
+            m.rel_base(),
+            m.pending_inputs(),
+        )
+    }
+
+    /// Disassemble the instruction at the current `ip`.
+    pub fn current_insn(&self) -> String {
+        let ip = self.machine.ip();
+        match self.machine.decode(ip) {
+            Some(insn) => format!("{:6}  {}", ip, insn),
+            None => format!("{:6}  .data {}", ip, self.value(ip)),
+        }
+    }
+}
+
+#[test]
+fn test_debug_self_loop() {
+    // A breakpoint on a jump to itself is hit every time
+    // round.
+    let ic = Intcode::new(vec![1105, 1, 0]).with_fuel(10);
+    let mut db = Debugger::new(ic);
+    db.add_breakpoint(0);
+    for _ in 0..3 {
+        assert_eq!(db.cont(), Stop::Breakpoint(0));
+    }
+    assert_eq!(db.machine().executed(), 2);
+
+    // Waiting for input at a breakpoint does not pass it.
+    let ic = Intcode::new(vec![3, 0, 99]);
+    let mut db = Debugger::new(ic);
+    db.add_breakpoint(0);
+    assert_eq!(db.cont(), Stop::Breakpoint(0));
+    let need_input = Stop::Terminus(Terminus::NeedInput);
+    assert_eq!(db.cont(), need_input);
+    db.machine_mut().add_input(1);
+    assert_eq!(db.cont(), Stop::Terminus(Terminus::Halted));
+}
@@ -0,0 +1,317 @@
+//! Differences between Intcode machine states.
+//!
+//! `Intcode::diff()` and `Snapshot::diff()` compare two
+//! machine states and return a `StateDiff`: the runs of
+//! memory words that changed, and the instruction
+//! pointers, relative bases, memory lengths and pending
+//! inputs, where those differ. Its `Display` is a readable
+//! report, one difference per line; tests can instead look
+//! at the runs, or at single words with
+//! `StateDiff::changed()`.
+//!
+//! Memory past the end of the shorter state counts as zero,
+//! as it would read if that machine grew, so growth shows
+//! up as a length change plus the nonzero words beyond.
+//! With paged memory, pages shared by the two states are
+//! skipped, so comparing a machine with a clone or snapshot
+//! of itself is cheap however much memory it has. Values
+//! too large for an `i64` are compared in full but shown by
+//! their low 64 bits.
+//!
+//! # Examples
+//!
+//! ```rust
+//! // Store the input at 7 and again at 8.
+//! let prog = vec![3, 7, 1001, 7, 0, 8, 99, 0, 0];
+//! let mut a = aoc::Intcode::new(prog.clone()).with_inputs(vec![4]);
+//! let mut b = aoc::Intcode::new(prog).with_inputs(vec![5, 6]);
+//! a.run();
+//! b.run();
+//! let diff = a.diff(&b);
+//! assert_eq!(diff.changed(7), Some((4, 5)));
+//! assert_eq!(diff.changed(0), None);
+//! assert_eq!(
+//!     diff.to_string(),
+//!     "inputs: [] -> [6]\nmemory 7..9: 4 4 -> 5 5\n",
+//! );
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::{Intcode, Memory, Snapshot};
+
+// Words of a run shown in a report before eliding the
+// rest.
+const SHOW_WORDS: usize = 8;
+
+/// A run of consecutive memory words that differ between
+/// two states.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeDiff {
+    /// Address of the first word.
+    pub start: usize,
+    /// The words in the first state.
+    pub old: Vec<i64>,
+    /// The words in the second state.
+    pub new: Vec<i64>,
+}
+
+impl RangeDiff {
+    /// Address one past the last word.
+    pub fn end(&self) -> usize {
+        self.start + self.old.len()
+    }
+}
+
+impl fmt::Display for RangeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.old.len() == 1 {
+            write!(f, "memory {}:", self.start)?;
+        } else {
+            write!(f, "memory {}..{}:", self.start, self.end())?;
+        }
+        write_words(f, &self.old)?;
+        write!(f, " ->")?;
+        write_words(f, &self.new)
+    }
+}
+
+// Write words separated by spaces, eliding all but the
+// first few.
+fn write_words(f: &mut fmt::Formatter, words: &[i64]) -> fmt::Result {
+    for w in words.iter().take(SHOW_WORDS) {
+        write!(f, " {}", w)?;
+    }
+    if words.len() > SHOW_WORDS {
+        write!(f, " ...")?;
+    }
+    Ok(())
+}
+
+/// Differences between two machine states. Each pair is
+/// the value in the first state and then in the second;
+/// fields that are the same in both are `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StateDiff {
+    /// Instruction pointers.
+    pub ip: Option<(usize, usize)>,
+    /// Relative bases.
+    pub rel_base: Option<(i64, i64)>,
+    /// Memory lengths.
+    pub len: Option<(usize, usize)>,
+    /// Pending inputs.
+    pub inputs: Option<(Vec<i64>, Vec<i64>)>,
+    /// Runs of changed memory words, in address order.
+    pub memory: Vec<RangeDiff>,
+}
+
+impl StateDiff {
+    /// Compare the given states.
+    pub fn new(a: &Snapshot, b: &Snapshot) -> Self {
+        let inputs = differ(a.pending_inputs(), b.pending_inputs())
+            .map(|(x, y)| (x.to_vec(), y.to_vec()));
+        StateDiff {
+            ip: differ(a.ip(), b.ip()),
+            rel_base: differ(a.rel_base(), b.rel_base()),
+            len: differ(a.memory().len(), b.memory().len()),
+            inputs,
+            memory: diff_memory(a.memory(), b.memory()),
+        }
+    }
+
+    /// True if the states are the same.
+    pub fn is_empty(&self) -> bool {
+        *self == StateDiff::default()
+    }
+
+    /// Number of memory words that changed.
+    pub fn changed_words(&self) -> usize {
+        self.memory.iter().map(|r| r.old.len()).sum()
+    }
+
+    /// The values of the memory word at the given address
+    /// in the two states, if they differ.
+    pub fn changed(&self, addr: usize) -> Option<(i64, i64)> {
+        let i = self.memory.partition_point(|r| r.end() <= addr);
+        let r = self.memory.get(i).filter(|r| r.start <= addr)?;
+        let i = addr - r.start;
+        Some((r.old[i], r.new[i]))
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no differences");
+        }
+        if let Some((x, y)) = self.ip {
+            writeln!(f, "ip: {} -> {}", x, y)?;
+        }
+        if let Some((x, y)) = self.rel_base {
+            writeln!(f, "rel_base: {} -> {}", x, y)?;
+        }
+        if let Some((x, y)) = self.len {
+            writeln!(f, "len: {} -> {}", x, y)?;
+        }
+        if let Some((x, y)) = &self.inputs {
+            writeln!(f, "inputs: {:?} -> {:?}", x, y)?;
+        }
+        for r in &self.memory {
+            writeln!(f, "{}", r)?;
+        }
+        Ok(())
+    }
+}
+
+// The pair of values, if they differ.
+fn differ<T: PartialEq>(x: T, y: T) -> Option<(T, T)> {
+    if x != y {
+        Some((x, y))
+    } else {
+        None
+    }
+}
+
+/// The runs of words that differ between two memories,
+/// in address order.
+pub fn diff_memory(a: &Memory, b: &Memory) -> Vec<RangeDiff> {
+    let len = a.len().max(b.len());
+    let mut addrs: BTreeSet<usize> = BTreeSet::new();
+    for (base, p, q) in a.differing_pages(b) {
+        let end = (len - base.min(len)).min(p.len());
+        let changed = (0..end).filter(|&i| p[i] != q[i]);
+        addrs.extend(changed.map(|i| base + i));
+    }
+    let big: BTreeSet<usize> = a
+        .big_values()
+        .chain(b.big_values())
+        .map(|(addr, _)| addr)
+        .collect();
+    for addr in big {
+        if a.get_big(addr) != b.get_big(addr) {
+            addrs.insert(addr);
+        }
+    }
+
+    let word = |m: &Memory, addr| m.get(addr).unwrap_or(0);
+    let mut runs: Vec<RangeDiff> = Vec::new();
+    for addr in addrs {
+        let (old, new) = (word(a, addr), word(b, addr));
+        match runs.last_mut() {
+            Some(r) if r.end() == addr => {
+                r.old.push(old);
+                r.new.push(new);
+            }
+            _ => runs.push(RangeDiff {
+                start: addr,
+                old: vec![old],
+                new: vec![new],
+            }),
+        }
+    }
+    runs
+}
+
+impl Snapshot {
+    /// Compare this state with another.
+    pub fn diff(&self, other: &Snapshot) -> StateDiff {
+        StateDiff::new(self, other)
+    }
+}
+
+impl Intcode {
+    /// Compare this machine's state with another's.
+    pub fn diff(&self, other: &Intcode) -> StateDiff {
+        StateDiff::new(&self.snapshot(), &other.snapshot())
+    }
+}
+
+#[test]
+fn test_diff_runs() {
+    // Day 7 amplifier program: the phase and signal inputs
+    // end up in memory.
+    let prog = vec![
+        3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+    ];
+    let run = |phase, signal| {
+        let mut ic =
+            Intcode::new(prog.clone()).with_inputs(vec![phase, signal]);
+        let out = ic.collect_outputs();
+        (ic, out)
+    };
+    let (a, out_a) = run(4, 0);
+    let (b, out_b) = run(3, 43);
+    assert_eq!((out_a, out_b), (vec![4], vec![433]));
+    let diff = a.diff(&b);
+    assert_eq!(diff.ip, None);
+    assert_eq!(diff.changed_words(), 2);
+    assert_eq!(diff.changed(15), Some((4, 433)));
+    assert_eq!(diff.changed(16), Some((0, 430)));
+    assert_eq!(diff.to_string(), "memory 15..17: 4 0 -> 433 430\n");
+    assert!(b.diff(&b.clone()).is_empty());
+    assert_eq!(a.diff(&a).to_string(), "no differences\n");
+
+    // A run interrupted by an unchanged word is two runs.
+    let x = Memory::from(vec![1, 2, 3, 4, 5]);
+    let y = Memory::from(vec![1, 0, 3, 0, 0]);
+    let runs = diff_memory(&x, &y);
+    assert_eq!(runs.len(), 2);
+    assert_eq!((runs[0].start, runs[0].end()), (1, 2));
+    assert_eq!(
+        (runs[1].old.clone(), runs[1].new.clone()),
+        (vec![4, 5], vec![0, 0])
+    );
+}
+
+#[test]
+fn test_diff_state() {
+    use super::BigInt;
+
+    // Far-off stores and relative base changes, against a
+    // snapshot of the starting state.
+    let far = 1 << 40;
+    let prog = vec![109, 3, 21101, 1, 1, far, 3, 20, 99];
+    let mut ic = Intcode::new(prog).with_inputs(vec![7, 8]);
+    let start = ic.snapshot();
+    ic.run();
+    let diff = start.diff(&ic.snapshot());
+    assert_eq!(diff.ip, Some((0, 8)));
+    assert_eq!(diff.rel_base, Some((0, 3)));
+    assert_eq!(diff.len, Some((9, far as usize + 4)));
+    assert_eq!(diff.inputs, Some((vec![7, 8], vec![8])));
+    assert_eq!(diff.changed(far as usize + 3), Some((0, 2)));
+    assert_eq!(diff.changed(20), Some((0, 7)));
+    assert_eq!(diff.changed_words(), 2);
+    assert_eq!(
+        diff.to_string(),
+        format!(
+            "ip: 0 -> 8\nrel_base: 0 -> 3\nlen: 9 -> {}\n\
+             inputs: [7, 8] -> [8]\nmemory 20: 0 -> 7\n\
+             memory {}: 0 -> 2\n",
+            far + 4,
+            far + 3,
+        ),
+    );
+
+    // Long runs are elided, and big values compared in
+    // full.
+    let mut a = Memory::from(vec![0; 20]);
+    let mut b = a.clone();
+    for addr in 0..10 {
+        b.set(addr, addr as i64 + 1);
+    }
+    let big = &BigInt::from(i64::MAX) * &BigInt::from(2);
+    // Differs from `big` only above the low 64 bits.
+    let two_64 = &BigInt::from(1 << 32) * &BigInt::from(1 << 32);
+    a.set_big(15, big.clone());
+    b.set_big(15, &big + &two_64);
+    assert_eq!(a.get(15), b.get(15));
+    let runs = diff_memory(&a, &b);
+    assert_eq!(runs.len(), 2);
+    assert_eq!(
+        runs[0].to_string(),
+        "memory 0..10: 0 0 0 0 0 0 0 0 ... -> 1 2 3 4 5 6 7 8 ...",
+    );
+    assert_eq!(runs[1].start, 15);
+}
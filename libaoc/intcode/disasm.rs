@@ -0,0 +1,258 @@
+//! Intcode disassembler.
+//!
+//! Walks a program vector decoding instructions the same
+//! way the interpreter does. Words that do not decode as a
+//! complete, legal instruction are shown as `.data`. Since
+//! Intcode freely mixes code and data, this is necessarily
+//! a guess: a data word that happens to look like an
+//! instruction will be shown as one.
+//!
+//! Operands are shown in the syntax of the `asm` module:
+//! `[addr]`, `#imm` and `rb+off`. The output of
+//! `disassemble_source()` can be fed back to `assemble()`
+//! to reproduce the original program.
+//!
+//! # Examples
+//!
+//! ```rust
+//! let prog = vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0];
+//! let src = aoc::disassemble_source(&prog);
+//! assert!(src.contains("mul [9], #2, [9]"));
+//! assert_eq!(aoc::assemble(&src).unwrap(), prog);
+//! ```
+
+use std::fmt;
+
+use super::{Intcode, Opcode, OpndMode};
+
+/// A decoded instruction operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// Position mode: the value at the given address.
+    Pos(i64),
+    /// Immediate mode: the given value.
+    Imm(i64),
+    /// Relative mode: the value at the given offset from
+    /// the relative base.
+    Rel(i64),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Pos(a) => write!(f, "[{}]", a),
+            Operand::Imm(v) => write!(f, "#{}", v),
+            Operand::Rel(o) if o < 0 => write!(f, "rb{}", o),
+            Operand::Rel(o) => write!(f, "rb+{}", o),
+        }
+    }
+}
+
+/// A decoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Insn {
+    pub(super) op: Opcode,
+    /// The instruction's operands, in order.
+    pub opnds: Vec<Operand>,
+}
+
+impl Insn {
+    /// The assembler mnemonic of this instruction.
+    pub fn mnemonic(&self) -> &'static str {
+        self.op.mnemonic()
+    }
+
+    /// Number of memory words occupied by this
+    /// instruction.
+    pub fn size(&self) -> usize {
+        1 + self.opnds.len()
+    }
+}
+
+impl fmt::Display for Insn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())?;
+        for (i, opnd) in self.opnds.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", sep, opnd)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode the instruction at the given address. Returns
+/// `None` if the word there is not a legal instruction, or
+/// if the instruction's operands would run off the end of
+/// the program.
+pub fn decode(prog: &[i64], addr: usize) -> Option<Insn> {
+    decode_with(|a| prog.get(a).cloned(), addr)
+}
+
+// Decode the instruction at the given address, using the
+// given function to look up memory words.
+fn decode_with<F>(word_at: F, addr: usize) -> Option<Insn>
+where
+    F: Fn(usize) -> Option<i64>,
+{
+    let word = word_at(addr)?;
+    if word < 0 {
+        return None;
+    }
+    let word = word as usize;
+    let op = Opcode::try_new(word % 100)?;
+    let mut modebits = word / 100;
+    let mut opnds = Vec::with_capacity(op.nopnds());
+    for i in 0..op.nopnds() {
+        let val = word_at(addr + 1 + i)?;
+        let mode = OpndMode::try_new(modebits % 10)?;
+        modebits /= 10;
+        if mode == OpndMode::Imm && op.store_opnd() == Some(i) {
+            return None;
+        }
+        opnds.push(match mode {
+            OpndMode::Pos => Operand::Pos(val),
+            OpndMode::Imm => Operand::Imm(val),
+            OpndMode::Rel => Operand::Rel(val),
+        });
+    }
+    if modebits != 0 {
+        return None;
+    }
+    Some(Insn { op, opnds })
+}
+
+impl Intcode {
+    /// Decode the instruction at the given address in this
+    /// machine's memory, as for `decode()`.
+    pub fn decode(&self, addr: usize) -> Option<Insn> {
+        decode_with(|a| self.prog.get(a), addr)
+    }
+}
+
+/// One line of disassembly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisasmLine {
+    /// An instruction starting at the given address.
+    Insn(usize, Insn),
+    /// A word at the given address that was not decoded as
+    /// an instruction.
+    Data(usize, i64),
+}
+
+impl DisasmLine {
+    /// Address of the first word of this line.
+    pub fn addr(&self) -> usize {
+        match *self {
+            DisasmLine::Insn(addr, _) => addr,
+            DisasmLine::Data(addr, _) => addr,
+        }
+    }
+
+    /// Number of memory words covered by this line.
+    pub fn size(&self) -> usize {
+        match self {
+            DisasmLine::Insn(_, insn) => insn.size(),
+            DisasmLine::Data(_, _) => 1,
+        }
+    }
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisasmLine::Insn(_, insn) => write!(f, "{}", insn),
+            DisasmLine::Data(_, val) => write!(f, ".data {}", val),
+        }
+    }
+}
+
+/// Disassemble the given program, starting at address 0
+/// and proceeding linearly.
+pub fn disassemble(prog: &[i64]) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut addr = 0;
+    while addr < prog.len() {
+        let line = match decode(prog, addr) {
+            Some(insn) => DisasmLine::Insn(addr, insn),
+            None => DisasmLine::Data(addr, prog[addr]),
+        };
+        addr += line.size();
+        lines.push(line);
+    }
+    lines
+}
+
+/// Produce a human-readable listing of the given program,
+/// one line per instruction, showing the address, the raw
+/// words and the disassembly.
+pub fn listing(prog: &[i64]) -> String {
+    let mut result = String::new();
+    for line in disassemble(prog) {
+        let addr = line.addr();
+        let words: Vec<String> = prog[addr..addr + line.size()]
+            .iter()
+            .map(|w| w.to_string())
+            .collect();
+        result +=
+            &format!("{:6}  {:<28} {}\n", addr, words.join(" "), line);
+    }
+    result
+}
+
+/// Produce assembler source for the given program. Each
+/// line carries its address as a comment. Assembling the
+/// result gives back the original program.
+pub fn disassemble_source(prog: &[i64]) -> String {
+    let mut result = String::new();
+    for line in disassemble(prog) {
+        let text = line.to_string();
+        result += &format!("    {:<32} ; {}\n", text, line.addr());
+    }
+    result
+}
+
+#[test]
+fn test_disassemble() {
+    let prog = vec![
+        109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006,
+        101, 0, 99,
+    ];
+    let text: Vec<String> =
+        disassemble(&prog).iter().map(|l| l.to_string()).collect();
+    assert_eq!(
+        text,
+        vec![
+            "rbo #1",
+            "out rb-1",
+            "add [100], #1, [100]",
+            "eq [100], #16, [101]",
+            "jf [101], #0",
+            "halt",
+        ],
+    );
+
+    // Illegal opcodes, illegal modes, stores to immediate,
+    // leftover mode bits and truncated instructions all
+    // fall back to data.
+    let prog = vec![98, 301, 11101, 10099, 1, 5];
+    let lines = disassemble(&prog);
+    assert_eq!(lines.len(), prog.len());
+    assert!(lines.iter().all(|l| matches!(l, DisasmLine::Data(..))));
+
+    // Round trip through the assembler.
+    let prog = vec![
+        3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20,
+        31, 1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1,
+        46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1,
+        46, 98, 99, 22201, -3, 7, 0,
+    ];
+    let src = disassemble_source(&prog);
+    assert_eq!(super::assemble(&src).unwrap(), prog);
+
+    // The most negative word, as data and as operands.
+    let min = i64::MIN;
+    let prog = vec![min, 204, min, 1101, min, 1, 0, 99];
+    let src = disassemble_source(&prog);
+    assert!(src.contains("out rb-9223372036854775808"));
+    assert_eq!(super::assemble(&src).unwrap(), prog);
+}
@@ -0,0 +1,470 @@
+//! Pre-decoded Intcode execution.
+//!
+//! The standard interpreter decodes every instruction from
+//! scratch each time it is executed. With
+//! `Engine::Predecoded`, a machine instead keeps a cache of
+//! decoded instructions indexed by address, so that a loop
+//! body is decoded only once. A decoded instruction holds
+//! just its opcode and modes: its operands are read from
+//! memory as it executes, so only a store to the first word
+//! of a cached instruction, whether by the program itself or
+//! by `poke()`, drops it from the cache.
+//!
+//! Like memory, the cache is kept in pages shared between
+//! clones of a machine until one of them changes a page. A
+//! brute-force search that runs many variants of one
+//! program can call `warm_cache()` on the original before
+//! cloning it, so that no clone has to decode anything.
+//!
+//! Either engine runs instructions in a tight loop directly
+//! on flat memory for as long as they cannot fault, grow
+//! memory or suspend. Anything else, and everything on
+//! paged memory or memory holding big values, runs through
+//! the standard interpreter, so both engines behave
+//! identically. See `bin/intcode-bench.rs` for timings.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::{Engine, Intcode};
+//!
+//! let prog = vec![1101, 100, -1, 4, 0];
+//! let mut ic = Intcode::new(prog).with_engine(Engine::Predecoded);
+//! ic.run();
+//! assert_eq!(ic.peek(4), 99);
+//! ```
+
+use std::sync::Arc;
+
+use super::{Arith, Intcode, Opcode, OpndMode};
+
+/// Intcode execution engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Decode each instruction as it is executed.
+    Interpreter,
+    /// Cache decoded instructions.
+    Predecoded,
+}
+
+// An instruction whose opcode and modes have been checked
+// and extracted.
+#[derive(Debug, Clone, Copy)]
+struct Predecoded {
+    op: Opcode,
+    modes: [OpndMode; 3],
+}
+
+// Decode the instruction at the given address, or return
+// `None` if it could fault while decoding or extends past
+// the end of memory.
+#[inline(always)]
+fn predecode(words: &[i64], ip: usize) -> Option<Predecoded> {
+    let word = *words.get(ip)?;
+    if word < 0 {
+        return None;
+    }
+    let op = Opcode::try_new(word as usize % 100)?;
+    let nopnds = op.nopnds();
+    if ip + nopnds >= words.len() {
+        return None;
+    }
+    let mut modebits = word as usize / 100;
+    let mut modes = [OpndMode::Pos; 3];
+    for mode in &mut modes[..nopnds] {
+        *mode = OpndMode::try_new(modebits % 10)?;
+        modebits /= 10;
+    }
+    if modebits != 0 {
+        return None;
+    }
+    if let Some(i) = op.store_opnd() {
+        if modes[i] == OpndMode::Imm {
+            return None;
+        }
+    }
+    Some(Predecoded { op, modes })
+}
+
+// Address named by a positional or relative operand, or
+// `None` if it is outside memory.
+#[inline(always)]
+fn address(
+    len: usize,
+    mode: OpndMode,
+    opnd: i64,
+    rel_base: i64,
+) -> Option<usize> {
+    let addr = match mode {
+        OpndMode::Imm => return None,
+        OpndMode::Pos => opnd,
+        OpndMode::Rel => opnd.checked_add(rel_base)?,
+    };
+    if addr < 0 || addr as usize >= len {
+        return None;
+    }
+    Some(addr as usize)
+}
+
+// Value of an operand, or `None` if it is outside memory.
+#[inline(always)]
+fn load(
+    words: &[i64],
+    mode: OpndMode,
+    opnd: i64,
+    rel_base: i64,
+) -> Option<i64> {
+    if mode == OpndMode::Imm {
+        return Some(opnd);
+    }
+    address(words.len(), mode, opnd, rel_base).map(|addr| words[addr])
+}
+
+// Next instruction pointer, any store, and relative base
+// after an instruction.
+type Effect = (usize, Option<(usize, i64)>, i64);
+
+// Machine state used while running instructions directly
+// on flat memory.
+struct Flat<'a> {
+    words: &'a mut [i64],
+    ip: usize,
+    rel_base: i64,
+    last_store: Option<usize>,
+    arith: Arith,
+}
+
+impl<'a> Flat<'a> {
+    // The effect of a decoded instruction at the instruction
+    // pointer: the next instruction pointer, the address and
+    // value of any store, and the new relative base. `None`
+    // if the instruction could fault, grow memory or
+    // suspend, and so is left to the standard interpreter.
+    #[inline(always)]
+    fn effect(&self, insn: Predecoded) -> Option<Effect> {
+        let words = &*self.words;
+        let (ip, rel_base) = (self.ip, self.rel_base);
+        let [m0, m1, m2] = insn.modes;
+        let opnd = |i: usize| words.get(ip + i).cloned();
+
+        use Opcode::*;
+        match insn.op {
+            Add | Mul | LessThan | Equals => {
+                let a = load(words, m0, opnd(1)?, rel_base)?;
+                let b = load(words, m1, opnd(2)?, rel_base)?;
+                let addr =
+                    address(words.len(), m2, opnd(3)?, rel_base)?;
+                let val = self.arith.apply(insn.op, a, b).ok()?;
+                Some((ip + 4, Some((addr, val)), rel_base))
+            }
+            JumpIfTrue | JumpIfFalse => {
+                let test = load(words, m0, opnd(1)?, rel_base)?;
+                if (test != 0) != (insn.op == JumpIfTrue) {
+                    return Some((ip + 3, None, rel_base));
+                }
+                let target = load(words, m1, opnd(2)?, rel_base)?;
+                if target < 0 {
+                    return None;
+                }
+                Some((target as usize, None, rel_base))
+            }
+            RBO => {
+                let offset = load(words, m0, opnd(1)?, rel_base)?;
+                let rel_base = self.arith.add(rel_base, offset).ok()?;
+                Some((ip + 2, None, rel_base))
+            }
+            Input | Output | Halt => None,
+        }
+    }
+
+    // Execute a decoded instruction at the instruction
+    // pointer, returning `false` without changing anything
+    // if it is left to the standard interpreter.
+    #[inline(always)]
+    fn execute(&mut self, insn: Predecoded) -> bool {
+        let (next, store, rel_base) = match self.effect(insn) {
+            Some(effect) => effect,
+            None => return false,
+        };
+        self.last_store = store.map(|(addr, val)| {
+            self.words[addr] = val;
+            addr
+        });
+        self.ip = next;
+        self.rel_base = rel_base;
+        true
+    }
+}
+
+// Cache pages hold the instructions at `CACHE_PAGE_SIZE`
+// consecutive addresses.
+const CACHE_PAGE_BITS: usize = 6;
+const CACHE_PAGE_SIZE: usize = 1 << CACHE_PAGE_BITS;
+const CACHE_PAGE_MASK: usize = CACHE_PAGE_SIZE - 1;
+
+type CachePage = [Option<Predecoded>; CACHE_PAGE_SIZE];
+
+/// Cache of decoded instructions, in pages shared between
+/// clones of a machine until one of them changes a page.
+#[derive(Debug, Clone, Default)]
+pub(super) struct DecodeCache {
+    pages: Vec<Arc<CachePage>>,
+}
+
+impl DecodeCache {
+    // The cached instruction at the given address.
+    #[inline]
+    fn get(&self, ip: usize) -> Option<&Predecoded> {
+        let page = self.pages.get(ip >> CACHE_PAGE_BITS)?;
+        page[ip & CACHE_PAGE_MASK].as_ref()
+    }
+
+    // Drop any cached instruction starting at the given
+    // address.
+    #[inline]
+    pub(super) fn invalidate(&mut self, addr: usize) {
+        if self.get(addr).is_some() {
+            self.set(addr, None);
+        }
+    }
+
+    fn insert(&mut self, ip: usize, insn: Predecoded) {
+        let index = ip >> CACHE_PAGE_BITS;
+        if index >= self.pages.len() {
+            let empty = Arc::new([None; CACHE_PAGE_SIZE]);
+            self.pages.resize(index + 1, empty);
+        }
+        self.set(ip, Some(insn));
+    }
+
+    // Set the entry at an address within the cache pages.
+    fn set(&mut self, ip: usize, entry: Option<Predecoded>) {
+        let page = &mut self.pages[ip >> CACHE_PAGE_BITS];
+        Arc::make_mut(page)[ip & CACHE_PAGE_MASK] = entry;
+    }
+}
+
+impl Intcode {
+    /// Builder for choosing the execution engine. The
+    /// default is `Engine::Interpreter`.
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.set_engine(engine);
+        self
+    }
+
+    /// Choose the execution engine.
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.cache = match engine {
+            Engine::Interpreter => None,
+            Engine::Predecoded => Some(DecodeCache::default()),
+        };
+    }
+
+    /// The current execution engine.
+    pub fn engine(&self) -> Engine {
+        match self.cache {
+            Some(_) => Engine::Predecoded,
+            None => Engine::Interpreter,
+        }
+    }
+
+    /// Decode and cache every instruction found by a linear
+    /// sweep of memory, so that clones of this machine need
+    /// not decode them. Does nothing with
+    /// `Engine::Interpreter` or paged memory.
+    pub fn warm_cache(&mut self) {
+        let (cache, words) =
+            match (&mut self.cache, self.prog.flat_mut()) {
+                (Some(cache), Some(words)) => (cache, words),
+                _ => return,
+            };
+        let mut addr = 0;
+        while addr < words.len() {
+            match predecode(words, addr) {
+                Some(insn) => {
+                    cache.insert(addr, insn);
+                    addr += insn.op.nopnds() + 1;
+                }
+                None => addr += 1,
+            }
+        }
+    }
+
+    // Run instructions directly on flat memory, decoding
+    // each or taking it from the decode cache, until one
+    // could fault, grow memory or suspend, or the given
+    // number have run. Returns the number run; the
+    // instruction stopped at is left for `execute()`.
+    pub(super) fn run_flat(&mut self, limit: u64) -> u64 {
+        if self.arith == Arith::Big || self.isa.is_some() {
+            return 0;
+        }
+        let words = match self.prog.flat_mut() {
+            Some(words) => words,
+            None => return 0,
+        };
+        let mut m = Flat {
+            words,
+            ip: self.ip,
+            rel_base: self.rel_base,
+            last_store: self.last_store,
+            arith: self.arith,
+        };
+        let mut n = 0;
+        match self.cache {
+            None => {
+                while n < limit {
+                    let insn = match predecode(m.words, m.ip) {
+                        Some(insn) => insn,
+                        None => break,
+                    };
+                    if !m.execute(insn) {
+                        break;
+                    }
+                    n += 1;
+                }
+            }
+            Some(ref mut cache) => {
+                while n < limit {
+                    let insn = match cache.get(m.ip) {
+                        Some(&insn) => insn,
+                        None => match predecode(m.words, m.ip) {
+                            Some(insn) => {
+                                cache.insert(m.ip, insn);
+                                insn
+                            }
+                            None => break,
+                        },
+                    };
+                    if !m.execute(insn) {
+                        break;
+                    }
+                    if let Some(addr) = m.last_store {
+                        cache.invalidate(addr);
+                    }
+                    n += 1;
+                }
+            }
+        }
+        self.ip = m.ip;
+        self.rel_base = m.rel_base;
+        self.last_store = m.last_store;
+        n
+    }
+}
+
+#[test]
+fn test_engines_agree() {
+    use super::Terminus;
+
+    // Programs from the other tests, and self-modifying
+    // loops that bump the immediate operand of their own add
+    // instruction: the second with that add straddling two
+    // cache pages, so that the bumped word is on the second.
+    let straddle = super::assemble(
+        "
+                jt #1, #loop
+                .zero 59
+        loop:   add #5, #0, [x]
+                add [loop+2], #1, [loop+2]
+                add [n], #-1, [n]
+                jt [n], #loop
+                out [x]
+                halt
+        x:      .data 0
+        n:      .data 3
+        ",
+    )
+    .unwrap();
+    let selfmod = super::assemble(
+        "
+        loop:   add [x], #0, [x]
+                add [loop+2], #1, [loop+2]
+                add [n], #-1, [n]
+                jt [n], #loop
+                out [x]
+                halt
+        x:      .data 0
+        n:      .data 10
+        ",
+    )
+    .unwrap();
+    let programs: Vec<(Vec<i64>, Vec<i64>)> = vec![
+        (selfmod, vec![]),
+        (straddle, vec![]),
+        (vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50], vec![]),
+        (vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8], vec![8]),
+        (
+            vec![
+                109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101,
+                1006, 101, 0, 99,
+            ],
+            vec![],
+        ),
+        (vec![10104, 0], vec![]),
+        (vec![1105, 1, 7], vec![]),
+        (vec![21101, 1, 1, -3, 99], vec![]),
+        (vec![1101, 1, 1], vec![]),
+    ];
+    for (prog, inputs) in programs {
+        let run = |engine, paged| {
+            let mut ic = Intcode::new(prog.clone())
+                .with_inputs(inputs.clone())
+                .with_engine(engine);
+            if paged {
+                ic = ic.with_paged_memory();
+            }
+            let mut outputs = Vec::new();
+            let result = loop {
+                match ic.try_run() {
+                    Ok(Terminus::HaveOutput(v)) => outputs.push(v),
+                    r => break r,
+                }
+            };
+            (
+                result,
+                outputs,
+                ic.memory().clone(),
+                ic.ip(),
+                ic.executed(),
+            )
+        };
+        let expected = run(Engine::Interpreter, true);
+        assert_eq!(run(Engine::Interpreter, false), expected);
+        assert_eq!(run(Engine::Predecoded, false), expected);
+    }
+
+    // The self-modifying loop adds 0 + 1 + ... + 9.
+    let mut ic = Intcode::new(
+        super::assemble(
+            "
+        loop:   add [x], #0, [x]
+                add [loop+2], #1, [loop+2]
+                jt #1, #loop
+        x:      .data 0
+        ",
+        )
+        .unwrap(),
+    )
+    .with_engine(Engine::Predecoded)
+    .with_fuel(30);
+    assert_eq!(ic.run(), Terminus::OutOfFuel);
+    assert_eq!(ic.peek(11), 45);
+
+    // Poking a cached instruction takes effect.
+    ic.poke(0, 99);
+    ic.set_fuel(None);
+    assert_eq!(ic.run(), Terminus::Halted);
+
+    // Clones of a warmed machine share its cache, but not
+    // their operands.
+    let mut ic = Intcode::new(vec![1, 0, 0, 0, 99])
+        .with_engine(Engine::Predecoded);
+    ic.warm_cache();
+    for (noun, result) in [(0, 2), (4, 100)] {
+        let mut ic = ic.clone();
+        ic.poke(1, noun);
+        ic.run();
+        assert_eq!(ic.peek(0), result);
+    }
+}
@@ -0,0 +1,618 @@
+//! Differential fuzzing of the Intcode interpreter.
+//!
+//! Random programs, some well-formed and some not, are run
+//! both by `Intcode`, with each execution engine, and by
+//! the small reference interpreter here, which is written
+//! straight from the semantics described in `intcode.rs`.
+//! The runs must produce the same sequence of
+//! suspensions and errors and leave the same memory,
+//! instruction pointer, relative base, pending inputs and
+//! instruction count.
+//!
+//! The cases are generated from a seed, which can be set
+//! with the environment variable `INTCODE_FUZZ_SEED`, and
+//! their number with `INTCODE_FUZZ_CASES`. A failing case
+//! is shrunk to a small program that still fails, and
+//! reported along with the seed and `INTCODE_FUZZ_CASE`
+//! setting that reproduce it.
+
+extern crate rand;
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::env;
+
+use self::rand::{Rng, SeedableRng, XorShiftRng};
+use super::{Arith, Engine, Fault, Intcode, IntcodeError, Terminus};
+
+// Default seed and number of cases.
+const SEED: u32 = 0x1c0d_e5ee;
+const CASES: u32 = 3000;
+
+// Instructions each run may execute.
+const FUEL: u64 = 300;
+
+// Largest memory compared word for word. Larger memories,
+// left by stores to far-off addresses, are compared only
+// at the addresses the reference machine touched, since
+// scanning them is slow.
+const SCAN_LEN: usize = 1 << 16;
+
+// A test case: a program, inputs and machine settings.
+#[derive(Debug, Clone)]
+struct Case {
+    prog: Vec<i64>,
+    // Inputs, of which the first `preload` are given up
+    // front and the rest one at a time when asked for.
+    inputs: Vec<i64>,
+    preload: usize,
+    arith: Arith,
+}
+
+// Reference Intcode machine, with sparse memory.
+#[derive(Debug, Clone)]
+struct Reference {
+    mem: BTreeMap<usize, i64>,
+    len: usize,
+    ip: usize,
+    rb: i64,
+    inputs: VecDeque<i64>,
+    arith: Arith,
+    fuel: u64,
+    executed: u64,
+    // Mode digits of the instruction being executed.
+    modes: i64,
+    // Addresses loaded from or stored to.
+    touched: BTreeSet<usize>,
+}
+
+impl Reference {
+    fn new(case: &Case) -> Self {
+        let mem = case
+            .prog
+            .iter()
+            .enumerate()
+            .filter(|&(_, &w)| w != 0)
+            .map(|(a, &w)| (a, w))
+            .collect();
+        Reference {
+            mem,
+            len: case.prog.len(),
+            ip: 0,
+            rb: 0,
+            inputs: case.inputs[..case.preload]
+                .iter()
+                .cloned()
+                .collect(),
+            arith: case.arith,
+            fuel: FUEL,
+            executed: 0,
+            modes: 0,
+            touched: BTreeSet::new(),
+        }
+    }
+
+    // The word at the given address, growing memory to
+    // include it.
+    fn word(&mut self, addr: usize) -> i64 {
+        self.len = self.len.max(addr + 1);
+        self.touched.insert(addr);
+        self.mem.get(&addr).cloned().unwrap_or(0)
+    }
+
+    fn set(&mut self, addr: usize, val: i64) {
+        self.len = self.len.max(addr + 1);
+        self.touched.insert(addr);
+        if val == 0 {
+            self.mem.remove(&addr);
+        } else {
+            self.mem.insert(addr, val);
+        }
+    }
+
+    // Mode digits of the current instruction after the
+    // first `k`.
+    fn modes(&self, k: u32) -> i64 {
+        self.modes / 10i64.pow(k)
+    }
+
+    // Mode and raw word of operand `k`.
+    fn operand(&mut self, k: u32) -> Result<(i64, i64), Fault> {
+        let raw = self.word(self.ip + 1 + k as usize);
+        let mode = self.modes(k) % 10;
+        if mode > 2 {
+            return Err(Fault::IllegalMode(mode as usize));
+        }
+        Ok((mode, raw))
+    }
+
+    // Address referred to by a position or relative operand.
+    fn address(&mut self, mode: i64, raw: i64) -> Result<usize, Fault> {
+        let addr = match mode {
+            2 => raw.checked_add(self.rb).ok_or(Fault::Overflow)?,
+            _ => raw,
+        };
+        if addr < 0 {
+            return Err(Fault::NegativeAddress(addr));
+        }
+        let addr = addr as usize;
+        self.len = self.len.max(addr + 1);
+        Ok(addr)
+    }
+
+    fn load(&mut self, k: u32) -> Result<i64, Fault> {
+        let (mode, raw) = self.operand(k)?;
+        if mode == 1 {
+            return Ok(raw);
+        }
+        let addr = self.address(mode, raw)?;
+        Ok(self.word(addr))
+    }
+
+    fn store(&mut self, k: u32, val: i64) -> Result<(), Fault> {
+        let (mode, raw) = self.operand(k)?;
+        if mode == 1 {
+            return Err(Fault::StoreToImmediate);
+        }
+        let addr = self.address(mode, raw)?;
+        self.set(addr, val);
+        Ok(())
+    }
+
+    // Fail if there are mode digits beyond the `n` operands.
+    fn check_modes(&self, n: u32) -> Result<(), Fault> {
+        if self.modes(n) != 0 {
+            return Err(Fault::UnusedModeBits);
+        }
+        Ok(())
+    }
+
+    fn add(&self, a: i64, b: i64) -> Result<i64, Fault> {
+        match self.arith {
+            Arith::Wrapping => Ok(a.wrapping_add(b)),
+            _ => a.checked_add(b).ok_or(Fault::Overflow),
+        }
+    }
+
+    // Execute the instruction at `ip`.
+    fn execute(&mut self) -> Result<Option<Terminus>, Fault> {
+        if self.ip >= self.len {
+            return Err(Fault::RanOffEnd);
+        }
+        let word = self.word(self.ip);
+        if word < 0 {
+            return Err(Fault::IllegalOpcode(word));
+        }
+        self.modes = word / 100;
+        match word % 100 {
+            op @ 1..=2 | op @ 7..=8 => {
+                let a = self.load(0)?;
+                let b = self.load(1)?;
+                let c = match op {
+                    1 => self.add(a, b)?,
+                    2 if self.arith == Arith::Wrapping => {
+                        a.wrapping_mul(b)
+                    }
+                    2 => a.checked_mul(b).ok_or(Fault::Overflow)?,
+                    7 => (a < b) as i64,
+                    _ => (a == b) as i64,
+                };
+                self.store(2, c)?;
+                self.check_modes(3)?;
+                self.ip += 4;
+            }
+            3 => {
+                let input = match self.inputs.front() {
+                    Some(&input) => input,
+                    None => return Ok(Some(Terminus::NeedInput)),
+                };
+                self.store(0, input)?;
+                self.check_modes(1)?;
+                self.inputs.pop_front();
+                self.ip += 2;
+            }
+            4 => {
+                let output = self.load(0)?;
+                self.check_modes(1)?;
+                self.ip += 2;
+                return Ok(Some(Terminus::HaveOutput(output)));
+            }
+            op @ 5..=6 => {
+                let test = self.load(0)?;
+                if (test != 0) == (op == 5) {
+                    let target = self.load(1)?;
+                    if target < 0 {
+                        return Err(Fault::NegativeJump(target));
+                    }
+                    self.ip = target as usize;
+                } else {
+                    self.check_modes(2)?;
+                    self.ip += 3;
+                }
+            }
+            9 => {
+                let offset = self.load(0)?;
+                self.rb = self.add(self.rb, offset)?;
+                self.check_modes(1)?;
+                self.ip += 2;
+            }
+            99 => return Ok(Some(Terminus::Halted)),
+            op => return Err(Fault::IllegalOpcode(op)),
+        }
+        Ok(None)
+    }
+
+    fn step(&mut self) -> Result<Option<Terminus>, IntcodeError> {
+        if self.fuel == 0 {
+            return Ok(Some(Terminus::OutOfFuel));
+        }
+        let ip = self.ip;
+        let insn = if ip < self.len {
+            Some(self.word(ip))
+        } else {
+            None
+        };
+        let result = self.execute();
+        if let Ok(None) | Ok(Some(Terminus::HaveOutput(_))) = result {
+            self.executed += 1;
+            self.fuel -= 1;
+        }
+        result.map_err(|fault| IntcodeError { ip, insn, fault })
+    }
+
+    fn run(&mut self) -> Result<Terminus, IntcodeError> {
+        loop {
+            if let Some(t) = self.step()? {
+                return Ok(t);
+            }
+        }
+    }
+}
+
+// What a run did and where it left the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Outcome {
+    events: Vec<Result<Terminus, IntcodeError>>,
+    // Length and nonzero words of memory.
+    len: usize,
+    mem: BTreeMap<usize, i64>,
+    ip: usize,
+    rb: i64,
+    pending: Vec<i64>,
+    executed: u64,
+}
+
+// Run a machine to completion, supplying the later inputs
+// when asked and running once more after halting.
+fn drive<F>(
+    case: &Case,
+    mut run: F,
+) -> Vec<Result<Terminus, IntcodeError>>
+where
+    F: FnMut(Option<i64>) -> Result<Terminus, IntcodeError>,
+{
+    let mut events = Vec::new();
+    let mut later = case.inputs[case.preload..].iter().cloned();
+    let mut input = None;
+    loop {
+        let event = run(input.take());
+        events.push(event.clone());
+        match event {
+            Ok(Terminus::HaveOutput(_)) => (),
+            Ok(Terminus::NeedInput) => match later.next() {
+                Some(v) => input = Some(v),
+                None => return events,
+            },
+            Ok(Terminus::Halted) => {
+                let again = run(None);
+                events.push(again);
+                return events;
+            }
+            _ => return events,
+        }
+    }
+}
+
+// Run the reference machine, also returning the addresses
+// it touched.
+fn run_reference(case: &Case) -> (Outcome, BTreeSet<usize>) {
+    let mut m = Reference::new(case);
+    let events = drive(case, |input| {
+        m.inputs.extend(input);
+        m.run()
+    });
+    let outcome = Outcome {
+        events,
+        len: m.len,
+        mem: m.mem,
+        ip: m.ip,
+        rb: m.rb,
+        pending: m.inputs.into_iter().collect(),
+        executed: m.executed,
+    };
+    (outcome, m.touched)
+}
+
+// Run `Intcode`, comparing memory at the given addresses if
+// it grows too large to scan.
+fn run_intcode(
+    case: &Case,
+    engine: Engine,
+    touched: &BTreeSet<usize>,
+) -> Outcome {
+    let mut ic = Intcode::new(case.prog.clone())
+        .with_inputs(case.inputs[..case.preload].to_vec())
+        .with_arith(case.arith)
+        .with_engine(engine)
+        .with_fuel(FUEL);
+    let events = drive(case, |input| {
+        if let Some(v) = input {
+            ic.add_input(v);
+        }
+        ic.try_run()
+    });
+    let memory = ic.memory();
+    let mut mem = BTreeMap::new();
+    if memory.len() <= SCAN_LEN {
+        for (base, words) in memory.chunks() {
+            for (i, w) in words.into_iter().enumerate() {
+                if w != 0 {
+                    mem.insert(base + i, w);
+                }
+            }
+        }
+    } else {
+        let prog = (0..case.prog.len()).filter(|&a| case.prog[a] != 0);
+        for addr in touched.iter().cloned().chain(prog) {
+            if let Some(w) = memory.get(addr).filter(|&w| w != 0) {
+                mem.insert(addr, w);
+            }
+        }
+    }
+    Outcome {
+        events,
+        len: memory.len(),
+        mem,
+        ip: ic.ip(),
+        rb: ic.rel_base(),
+        pending: ic.pending_inputs(),
+        executed: ic.executed(),
+    }
+}
+
+// Check a case, describing the first difference found.
+fn check(case: &Case) -> Result<(), String> {
+    let (expected, touched) = run_reference(case);
+    for &engine in &[Engine::Interpreter, Engine::Predecoded] {
+        let actual = run_intcode(case, engine, &touched);
+        if actual != expected {
+            return Err(format!(
+                "{:?} engine gave {:?}, reference {:?}",
+                engine, actual, expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Generate an operand value: usually an address in or near
+// the program, sometimes something nastier.
+fn gen_value(rng: &mut XorShiftRng, len: usize) -> i64 {
+    match rng.gen_range(0, 20) {
+        0 => rng.gen_range(-5, 0),
+        1 => *rng
+            .choose(&[
+                i64::MAX,
+                i64::MIN,
+                i64::MAX - 1,
+                1 << 40,
+                -(1 << 40),
+            ])
+            .unwrap(),
+        2 => rng.gen(),
+        _ => rng.gen_range(0, len as i64 + 8),
+    }
+}
+
+// Generate a program, usually a sequence of well-formed
+// instructions but with occasional damage.
+fn gen_prog(rng: &mut XorShiftRng) -> Vec<i64> {
+    // Opcode and number of operands, with the store
+    // operand if any.
+    const OPS: [(i64, usize, Option<usize>); 9] = [
+        (1, 3, Some(2)),
+        (2, 3, Some(2)),
+        (3, 1, Some(0)),
+        (4, 1, None),
+        (5, 2, None),
+        (6, 2, None),
+        (7, 3, Some(2)),
+        (8, 3, Some(2)),
+        (9, 1, None),
+    ];
+    let len = rng.gen_range(1, 40);
+    let damage = rng.gen_range(0, 4) == 0;
+    let mut prog = Vec::new();
+    while prog.len() < len {
+        if damage && rng.gen_weighted_bool(8) {
+            prog.push(gen_value(rng, len));
+            continue;
+        }
+        if rng.gen_weighted_bool(12) {
+            prog.push(99);
+            continue;
+        }
+        let (op, nopnds, store) = *rng.choose(&OPS).unwrap();
+        let mut word = op;
+        let mut scale = 100;
+        for i in 0..nopnds {
+            let mode = match rng.gen_range(0, 3) {
+                1 if store == Some(i) => 0,
+                mode => mode,
+            };
+            word += mode * scale;
+            scale *= 10;
+        }
+        if damage && rng.gen_weighted_bool(4) {
+            word +=
+                rng.gen_range(1, 10) * 10i64.pow(rng.gen_range(2, 6));
+        }
+        prog.push(word);
+        for _ in 0..nopnds {
+            prog.push(gen_value(rng, len));
+        }
+    }
+    if rng.gen_range(0, 5) != 0 {
+        prog.push(99);
+    }
+    prog
+}
+
+fn gen_case(rng: &mut XorShiftRng) -> Case {
+    let prog = gen_prog(rng);
+    let ninputs = rng.gen_range(0, 6);
+    let inputs: Vec<i64> =
+        (0..ninputs).map(|_| gen_value(rng, prog.len())).collect();
+    let preload = rng.gen_range(0, ninputs + 1);
+    let arith = if rng.gen_weighted_bool(3) {
+        Arith::Wrapping
+    } else {
+        Arith::Checked
+    };
+    Case {
+        prog,
+        inputs,
+        preload,
+        arith,
+    }
+}
+
+// Smaller variants of a case to try when shrinking.
+fn shrinks(case: &Case) -> Vec<Case> {
+    let mut smaller = Vec::new();
+    let n = case.prog.len();
+    let mut size = n / 2;
+    while size >= 1 {
+        for start in (0..=n - size).step_by(size) {
+            let mut c = case.clone();
+            c.prog.drain(start..start + size);
+            smaller.push(c);
+        }
+        size /= 2;
+    }
+    for i in 0..case.inputs.len() {
+        let mut c = case.clone();
+        c.inputs.remove(i);
+        c.preload = c.preload.min(c.inputs.len());
+        smaller.push(c);
+    }
+    for (i, &w) in case.prog.iter().enumerate() {
+        let mut simpler = vec![0, w / 2];
+        if w >= 100 {
+            simpler.push(w % 100);
+        }
+        for v in simpler {
+            if v.unsigned_abs() < w.unsigned_abs() {
+                let mut c = case.clone();
+                c.prog[i] = v;
+                smaller.push(c);
+            }
+        }
+    }
+    smaller
+}
+
+// Shrink a case for which the given test fails to a
+// smaller one for which it still fails.
+fn shrink<F>(case: Case, fails: F) -> Case
+where
+    F: Fn(&Case) -> bool,
+{
+    let mut case = case;
+    while let Some(c) = shrinks(&case).into_iter().find(|c| fails(c)) {
+        case = c;
+    }
+    case
+}
+
+fn env_u32(name: &str) -> Option<u32> {
+    env::var(name).ok().map(|v| {
+        let v = v.trim();
+        match v.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => v.parse(),
+        }
+        .unwrap_or_else(|_| panic!("bad {}: {}", name, v))
+    })
+}
+
+// Random source for the given case of the given seed.
+fn case_rng(seed: u32, case: u32) -> XorShiftRng {
+    XorShiftRng::from_seed([seed, case, 0x9e37_79b9, 0x7f4a_7c15])
+}
+
+#[test]
+fn test_fuzz() {
+    let seed = env_u32("INTCODE_FUZZ_SEED").unwrap_or(SEED);
+    let cases = match env_u32("INTCODE_FUZZ_CASE") {
+        Some(case) => case..case + 1,
+        None => 0..env_u32("INTCODE_FUZZ_CASES").unwrap_or(CASES),
+    };
+    for i in cases {
+        let case = gen_case(&mut case_rng(seed, i));
+        if let Err(msg) = check(&case) {
+            let shrunk = shrink(case.clone(), |c| check(c).is_err());
+            panic!(
+                "INTCODE_FUZZ_SEED={:#x} INTCODE_FUZZ_CASE={}: {}\n\
+                 case: {:?}\nshrunk: {:?}\nshrunk failure: {}",
+                seed,
+                i,
+                msg,
+                case,
+                shrunk,
+                check(&shrunk).unwrap_err()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_fuzz_shrink() {
+    // The reference interpreter agrees with hand-worked
+    // results.
+    let case = Case {
+        prog: vec![3, 9, 1001, 9, -1, 9, 1005, 9, 2, 0],
+        inputs: vec![2, 7],
+        preload: 1,
+        arith: Arith::Checked,
+    };
+    let (outcome, _) = run_reference(&case);
+    assert_eq!(
+        outcome.events,
+        vec![Err(IntcodeError {
+            ip: 9,
+            insn: Some(0),
+            fault: Fault::IllegalOpcode(0),
+        })]
+    );
+    assert_eq!(outcome.executed, 5);
+    assert_eq!(outcome.pending, Vec::<i64>::new());
+    assert!(check(&case).is_ok());
+
+    // Shrinking a program on which checked and wrapping
+    // arithmetic differ leaves just an overflow.
+    let differs = |c: &Case| {
+        let mut wrapping = c.clone();
+        wrapping.arith = Arith::Wrapping;
+        run_reference(c).0 != run_reference(&wrapping).0
+    };
+    let mut rng = case_rng(SEED, 0);
+    let case = (0..)
+        .map(|_| gen_case(&mut rng))
+        .find(|c| {
+            c.arith == Arith::Checked && c.prog.len() > 10 && differs(c)
+        })
+        .unwrap();
+    let shrunk = shrink(case, differs);
+    assert!(shrunk.prog.len() <= 4, "{:?}", shrunk);
+    assert!(differs(&shrunk));
+}
@@ -0,0 +1,261 @@
+//! Pluggable Intcode I/O.
+//!
+//! `Intcode::run()` suspends on every output and whenever
+//! it needs input. `Intcode::run_io()` instead takes an
+//! `InputSource` and an `OutputSink` and calls them
+//! directly, suspending only when the source has no more
+//! input. Implementations are provided for `VecDeque`
+//! queues, closures, `std::sync::mpsc` channels, and
+//! line-oriented ASCII text.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::collections::VecDeque;
+//!
+//! // Double each input until a zero is seen.
+//! let prog = aoc::assemble("
+//! loop:   in [x]
+//!         jf [x], #done
+//!         mul [x], #2, [x]
+//!         out [x]
+//!         jt #1, #loop
+//! done:   halt
+//! x:      .data 0
+//! ").unwrap();
+//! let mut input: VecDeque<i64> = vec![1, 2, 3].into();
+//! let mut output = Vec::new();
+//! let mut ic = aoc::Intcode::new(prog);
+//! assert_eq!(ic.run_io(&mut input, &mut output), aoc::Terminus::NeedInput);
+//! assert_eq!(output, vec![2, 4, 6]);
+//! input.push_back(0);
+//! assert_eq!(ic.run_io(&mut input, &mut output), aoc::Terminus::Halted);
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+use super::{encode_ascii, Intcode, IntcodeError, Terminus};
+
+/// Supplier of Intcode program inputs.
+pub trait InputSource {
+    /// Return the next input, or `None` if there is none
+    /// available right now.
+    fn next_input(&mut self) -> Option<i64>;
+}
+
+/// Consumer of Intcode program outputs.
+pub trait OutputSink {
+    /// Accept the next output.
+    fn put_output(&mut self, val: i64);
+}
+
+/// Inputs are taken from the front of the queue.
+impl InputSource for VecDeque<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+/// Outputs are added to the back of the queue.
+impl OutputSink for VecDeque<i64> {
+    fn put_output(&mut self, val: i64) {
+        self.push_back(val);
+    }
+}
+
+impl OutputSink for Vec<i64> {
+    fn put_output(&mut self, val: i64) {
+        self.push(val);
+    }
+}
+
+impl<F: FnMut() -> Option<i64>> InputSource for F {
+    fn next_input(&mut self) -> Option<i64> {
+        self()
+    }
+}
+
+impl<F: FnMut(i64)> OutputSink for F {
+    fn put_output(&mut self, val: i64) {
+        self(val)
+    }
+}
+
+/// Blocks until an input arrives. Returns `None` once the
+/// sending side has hung up.
+impl InputSource for Receiver<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// Outputs sent after the receiving side has hung up are
+/// dropped.
+impl OutputSink for Sender<i64> {
+    fn put_output(&mut self, val: i64) {
+        let _ = self.send(val);
+    }
+}
+
+/// Input source that reads lines of text, supplying each
+/// character (including the line's terminating newline) as
+/// an input.
+pub struct AsciiInput<R> {
+    reader: R,
+    pending: VecDeque<i64>,
+}
+
+impl<R: BufRead> AsciiInput<R> {
+    /// Read lines from the given reader as needed.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+/// # Panics
+/// Will panic on a read error.
+impl<R: BufRead> InputSource for AsciiInput<R> {
+    fn next_input(&mut self) -> Option<i64> {
+        if self.pending.is_empty() {
+            let mut line = String::new();
+            let nread = self
+                .reader
+                .read_line(&mut line)
+                .expect("could not read ASCII input");
+            if nread == 0 {
+                return None;
+            }
+            if !line.ends_with('\n') {
+                line.push('\n');
+            }
+            self.pending.extend(line.bytes().map(i64::from));
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Output sink that writes ASCII outputs as characters.
+/// Outputs outside the ASCII range are written in decimal
+/// on a line of their own.
+pub struct AsciiOutput<W> {
+    writer: W,
+}
+
+impl<W: Write> AsciiOutput<W> {
+    /// Write to the given writer.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Give back the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// # Panics
+/// Will panic on a write error.
+impl<W: Write> OutputSink for AsciiOutput<W> {
+    fn put_output(&mut self, val: i64) {
+        let mut buf = Vec::new();
+        encode_ascii(val, &mut buf);
+        self.writer
+            .write_all(&buf)
+            .expect("could not write ASCII output");
+    }
+}
+
+impl Intcode {
+    /// Run this Intcode program, taking inputs from the
+    /// given source and sending outputs to the given sink,
+    /// until it halts, runs out of fuel, or needs input
+    /// that the source does not have.
+    ///
+    /// # Panics
+    /// Will panic on an execution fault: see
+    /// `try_run_io()`.
+    pub fn run_io<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Terminus
+    where
+        I: InputSource + ?Sized,
+        O: OutputSink + ?Sized,
+    {
+        self.try_run_io(input, output)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Run this Intcode program as with `run_io()`,
+    /// returning an error on an execution fault.
+    pub fn try_run_io<I, O>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<Terminus, IntcodeError>
+    where
+        I: InputSource + ?Sized,
+        O: OutputSink + ?Sized,
+    {
+        loop {
+            match self.try_run()? {
+                Terminus::NeedInput => match input.next_input() {
+                    Some(val) => self.add_input(val),
+                    None => return Ok(Terminus::NeedInput),
+                },
+                Terminus::HaveOutput(val) => output.put_output(val),
+                t => return Ok(t),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_io() {
+    use std::sync::mpsc::channel;
+
+    // Add one to each input, forever.
+    let prog = vec![3, 9, 1001, 9, 1, 9, 4, 9, 1105, 1, 0];
+
+    // Closures.
+    let mut ic = Intcode::new(prog.clone());
+    let mut inputs = vec![10, 20].into_iter();
+    let mut total = 0;
+    let t = ic.run_io(&mut || inputs.next(), &mut |v| total += v);
+    assert_eq!(t, Terminus::NeedInput);
+    assert_eq!(total, 32);
+
+    // Channels between two machines, one on a thread.
+    let (tx_in, rx_in) = channel();
+    let (tx_out, mut rx_out) = channel();
+    let mut ic = Intcode::new(prog.clone());
+    let worker = std::thread::spawn(move || {
+        let mut rx_in = rx_in;
+        let mut tx_out = tx_out;
+        ic.run_io(&mut rx_in, &mut tx_out)
+    });
+    for i in 0..3 {
+        tx_in.send(i).unwrap();
+    }
+    drop(tx_in);
+    assert_eq!(worker.join().unwrap(), Terminus::NeedInput);
+    let mut outputs = Vec::new();
+    while let Some(v) = rx_out.next_input() {
+        outputs.push(v);
+    }
+    assert_eq!(outputs, vec![1, 2, 3]);
+
+    // ASCII: shift each character up by one.
+    let mut input = AsciiInput::new("HAL\n@".as_bytes());
+    let mut output = AsciiOutput::new(Vec::new());
+    let mut ic = Intcode::new(prog);
+    ic.run_io(&mut input, &mut output);
+    let text = String::from_utf8(output.into_inner()).unwrap();
+    assert_eq!(text, "IBM\u{b}A\u{b}");
+}
@@ -0,0 +1,505 @@
+//! Instruction-set dialects and user-defined opcodes.
+//!
+//! By default a machine runs the full instruction set of
+//! Day 9. Giving it an `Isa` with `Intcode::with_isa()`
+//! instead restricts it to the opcodes and operand modes of
+//! an earlier puzzle's `Dialect`, so that a program using
+//! anything newer faults with `IllegalOpcode` or
+//! `IllegalMode` just as it would have on that day, and
+//! adds any user-defined opcodes registered with
+//! `Isa::define()`.
+//!
+//! A user-defined opcode has a code that no built-in
+//! instruction uses and a signature saying whether each
+//! operand is read or written. As with the built-in
+//! instructions, at most one operand may be written. Its
+//! handler gets the values of the read operands, in order,
+//! and returns the `Action` to take, or an error message
+//! that becomes a `Fault::UserOp`. Operand modes work as
+//! usual, within the dialect.
+//!
+//! Machines with an `Isa` always run on the interpreter:
+//! `Engine::Predecoded` is ignored. The disassembler and
+//! the static analyses know only the built-in opcodes, and
+//! show user-defined instructions as data.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::{Access, Action, Dialect, Fault, Intcode, Isa};
+//!
+//! // Add `sub` as opcode 10 to the Day 5 instruction set.
+//! let isa = Isa::new(Dialect::Day5).define(
+//!     10,
+//!     "sub",
+//!     &[Access::Read, Access::Read, Access::Write],
+//!     |args| match args[0].checked_sub(args[1]) {
+//!         Some(diff) => Ok(Action::Store(diff)),
+//!         None => Err("sub overflow".to_string()),
+//!     },
+//! );
+//! let prog = vec![3, 9, 1010, 9, 7, 9, 4, 9, 99, 0];
+//! let mut ic = Intcode::new(prog).with_isa(isa.clone());
+//! ic.add_input(50);
+//! assert_eq!(ic.collect_outputs(), vec![43]);
+//!
+//! // Relative mode came later, with Day 9.
+//! let mut ic = Intcode::new(vec![204, 0, 99]).with_isa(isa);
+//! assert_eq!(ic.try_run().unwrap_err().fault, Fault::IllegalMode(2));
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{Decode, Fault, Intcode, Opcode, OpndMode, Terminus};
+
+/// The instruction set of a given day's puzzle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Dialect {
+    /// `add`, `mul` and `halt`, with no mode digits: the
+    /// whole word is the opcode.
+    Day2,
+    /// Day 2 plus I/O, jumps and comparisons, with
+    /// immediate mode.
+    Day5,
+    /// Day 5 plus `rbo`, with relative mode: the full
+    /// instruction set.
+    Day9,
+}
+
+impl Dialect {
+    // True if the dialect has the given built-in opcode.
+    fn has_op(self, op: Opcode) -> bool {
+        use Opcode::*;
+        match op {
+            Add | Mul | Halt => true,
+            Input | Output | JumpIfTrue | JumpIfFalse | LessThan
+            | Equals => self >= Dialect::Day5,
+            RBO => self >= Dialect::Day9,
+        }
+    }
+
+    // True if the dialect has the given operand mode.
+    fn has_mode(self, mode: OpndMode) -> bool {
+        match mode {
+            OpndMode::Pos => true,
+            OpndMode::Imm => self >= Dialect::Day5,
+            OpndMode::Rel => self >= Dialect::Day9,
+        }
+    }
+}
+
+/// Whether an operand of a user-defined opcode is read or
+/// written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// What a user-defined instruction does, as returned by its
+/// handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Store the given value to the written operand and go
+    /// on to the next instruction.
+    Store(i64),
+    /// Go on to the next instruction without storing.
+    Next,
+    /// Jump to the given address without storing.
+    Jump(i64),
+}
+
+/// Handler for a user-defined opcode.
+pub type Handler =
+    dyn Fn(&[i64]) -> Result<Action, String> + Send + Sync;
+
+// A user-defined opcode.
+#[derive(Clone)]
+struct UserOp {
+    mnemonic: String,
+    signature: Vec<Access>,
+    handler: Arc<Handler>,
+}
+
+impl UserOp {
+    // Index of the written operand, if any.
+    fn store_opnd(&self) -> Option<usize> {
+        self.signature.iter().position(|&a| a == Access::Write)
+    }
+}
+
+/// An instruction set: a dialect plus any user-defined
+/// opcodes.
+#[derive(Clone)]
+pub struct Isa {
+    dialect: Dialect,
+    ops: BTreeMap<i64, UserOp>,
+}
+
+impl Isa {
+    /// The given dialect, with no user-defined opcodes.
+    pub fn new(dialect: Dialect) -> Self {
+        Self {
+            dialect,
+            ops: BTreeMap::new(),
+        }
+    }
+
+    /// Builder adding a user-defined opcode with the given
+    /// code, mnemonic, operand signature and handler.
+    ///
+    /// # Panics
+    /// Will panic if the code is not in `1..=98`, is used by
+    /// a built-in or already defined opcode, or if the
+    /// signature writes more than one operand.
+    pub fn define<F>(
+        mut self,
+        code: i64,
+        mnemonic: &str,
+        signature: &[Access],
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(&[i64]) -> Result<Action, String> + Send + Sync + 'static,
+    {
+        assert!(
+            (1..=98).contains(&code),
+            "opcode {} out of range",
+            code
+        );
+        assert!(
+            Opcode::try_new(code as usize).is_none(),
+            "opcode {} is built in",
+            code,
+        );
+        assert!(
+            !self.ops.contains_key(&code),
+            "opcode {} already defined",
+            code,
+        );
+        let nwrites =
+            signature.iter().filter(|&&a| a == Access::Write).count();
+        assert!(
+            nwrites <= 1,
+            "opcode {} writes {} operands",
+            code,
+            nwrites
+        );
+        let op = UserOp {
+            mnemonic: mnemonic.to_string(),
+            signature: signature.to_vec(),
+            handler: Arc::new(handler),
+        };
+        self.ops.insert(code, op);
+        self
+    }
+
+    /// The dialect of this instruction set.
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Mnemonic of the user-defined opcode with the given
+    /// code, if there is one.
+    pub fn mnemonic(&self, code: i64) -> Option<&str> {
+        self.ops.get(&code).map(|op| op.mnemonic.as_str())
+    }
+
+    /// Number of operands of the user-defined opcode with
+    /// the given code, if there is one.
+    pub fn nopnds(&self, code: i64) -> Option<usize> {
+        self.ops.get(&code).map(|op| op.signature.len())
+    }
+}
+
+impl Default for Isa {
+    fn default() -> Self {
+        Self::new(Dialect::Day9)
+    }
+}
+
+impl fmt::Debug for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ops: BTreeMap<i64, &str> = self
+            .ops
+            .iter()
+            .map(|(&code, op)| (code, op.mnemonic.as_str()))
+            .collect();
+        f.debug_struct("Isa")
+            .field("dialect", &self.dialect)
+            .field("ops", &ops)
+            .finish()
+    }
+}
+
+impl Intcode {
+    /// Builder setting the instruction set.
+    pub fn with_isa(mut self, isa: Isa) -> Self {
+        self.set_isa(Some(isa));
+        self
+    }
+
+    /// Set the instruction set, or `None` for the full
+    /// built-in one.
+    pub fn set_isa(&mut self, isa: Option<Isa>) {
+        self.isa = isa.map(Arc::new);
+    }
+
+    /// The instruction set, if one has been set.
+    pub fn isa(&self) -> Option<&Isa> {
+        self.isa.as_deref()
+    }
+
+    // Check the instruction at `ip` against the instruction
+    // set, executing it if it is user-defined. Returns
+    // `None` if it is a built-in instruction allowed by the
+    // dialect, to be executed as usual.
+    pub(super) fn execute_isa(
+        &mut self,
+        isa: &Isa,
+    ) -> Option<Result<Option<Terminus>, Fault>> {
+        let word = self.prog.get(self.ip)?;
+        if word < 0 || self.prog.is_big(self.ip) {
+            return None;
+        }
+        let code = word % 100;
+        let modes = word as usize / 100;
+        if isa.dialect == Dialect::Day2 && modes != 0 {
+            return Some(Err(Fault::IllegalOpcode(word)));
+        }
+        let (nopnds, user) = match isa.ops.get(&code) {
+            Some(op) => (op.signature.len(), Some(op)),
+            None => match Opcode::try_new(code as usize) {
+                Some(op) if isa.dialect.has_op(op) => {
+                    (op.nopnds(), None)
+                }
+                _ => return Some(Err(Fault::IllegalOpcode(code))),
+            },
+        };
+        let mut digits = modes;
+        for _ in 0..nopnds {
+            let digit = digits % 10;
+            let allowed = OpndMode::try_new(digit)
+                .is_some_and(|mode| isa.dialect.has_mode(mode));
+            if !allowed {
+                return Some(Err(Fault::IllegalMode(digit)));
+            }
+            digits /= 10;
+        }
+        let op = user?;
+        Some(self.execute_user(op, modes))
+    }
+
+    // Execute a user-defined instruction at `ip`.
+    fn execute_user(
+        &mut self,
+        op: &UserOp,
+        modes: usize,
+    ) -> Result<Option<Terminus>, Fault> {
+        let ip = self.ip;
+        let mut opnds = Decode {
+            prog: &mut self.prog,
+            index: ip + 1,
+            modebits: modes,
+            rel_base: self.rel_base,
+        };
+        let mut args = Vec::with_capacity(op.signature.len());
+        for &access in &op.signature {
+            match access {
+                Access::Read => args.push(opnds.fetch()?),
+                Access::Write => {
+                    opnds.operand()?;
+                    opnds.skip();
+                }
+            }
+        }
+        let next = opnds.finish()?;
+        let action = (op.handler)(&args).map_err(Fault::UserOp)?;
+        self.ip = match action {
+            Action::Next => next,
+            Action::Store(val) => {
+                let w = op.store_opnd().ok_or_else(|| {
+                    Fault::UserOp(format!(
+                        "{} has no operand to store to",
+                        op.mnemonic,
+                    ))
+                })?;
+                let mut opnds = Decode {
+                    prog: &mut self.prog,
+                    index: ip + 1 + w,
+                    modebits: modes / 10usize.pow(w as u32),
+                    rel_base: self.rel_base,
+                };
+                self.last_store = Some(opnds.store(val)?);
+                next
+            }
+            Action::Jump(target) => {
+                if target < 0 {
+                    return Err(Fault::NegativeJump(target));
+                }
+                target as usize
+            }
+        };
+        Ok(None)
+    }
+
+    // Address the user-defined instruction at `ip` would
+    // store to, if it is one and writes an operand.
+    pub(super) fn user_store_addr(&self) -> Option<usize> {
+        let isa = self.isa.as_ref()?;
+        let word = self.prog.get(self.ip)?;
+        let op = isa.ops.get(&(word % 100)).filter(|_| word >= 0)?;
+        let w = op.store_opnd()?;
+        let opnd = self.prog.get(self.ip + 1 + w)?;
+        let addr =
+            match word as usize / 100 / 10usize.pow(w as u32) % 10 {
+                0 => opnd,
+                2 => opnd.checked_add(self.rel_base)?,
+                _ => return None,
+            };
+        if addr < 0 {
+            return None;
+        }
+        Some(addr as usize)
+    }
+}
+
+#[test]
+fn test_isa_dialects() {
+    // Day 2 program: no modes, no I/O.
+    let day2 = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+    for &dialect in &[Dialect::Day2, Dialect::Day5, Dialect::Day9] {
+        let mut ic =
+            Intcode::new(day2.clone()).with_isa(Isa::new(dialect));
+        ic.run();
+        assert_eq!(ic.peek(0), 3500);
+    }
+
+    let fault = |prog: &[i64], dialect| {
+        let mut ic = Intcode::new(prog.to_vec())
+            .with_inputs(vec![1])
+            .with_isa(Isa::new(dialect));
+        ic.try_run().err().map(|e| e.fault)
+    };
+    let echo = [3, 0, 4, 0, 99];
+    assert_eq!(
+        fault(&echo, Dialect::Day2),
+        Some(Fault::IllegalOpcode(3))
+    );
+    assert_eq!(fault(&echo, Dialect::Day5), None);
+    let imm = [1101, 1, 1, 0, 99];
+    assert_eq!(
+        fault(&imm, Dialect::Day2),
+        Some(Fault::IllegalOpcode(1101))
+    );
+    assert_eq!(fault(&imm, Dialect::Day5), None);
+    let rel = [109, 5, 99];
+    assert_eq!(
+        fault(&rel, Dialect::Day5),
+        Some(Fault::IllegalOpcode(9))
+    );
+    assert_eq!(fault(&rel, Dialect::Day9), None);
+    let rel_mode = [1201, 0, 0, 0, 99];
+    assert_eq!(
+        fault(&rel_mode, Dialect::Day5),
+        Some(Fault::IllegalMode(2))
+    );
+    assert_eq!(
+        fault(&[42, 99], Dialect::Day9),
+        Some(Fault::IllegalOpcode(42))
+    );
+
+    // The dialect is checked even with the predecoding
+    // engine selected.
+    let mut ic = Intcode::new(rel.to_vec())
+        .with_engine(super::Engine::Predecoded)
+        .with_isa(Isa::new(Dialect::Day5));
+    ic.warm_cache();
+    assert!(ic.try_run().is_err());
+}
+
+#[test]
+fn test_isa_user_ops() {
+    // `jmp` jumps unconditionally; `div` divides, failing
+    // on zero; `nop` does nothing.
+    let isa = Isa::default()
+        .define(
+            20,
+            "div",
+            &[Access::Read, Access::Read, Access::Write],
+            |args| match args[0].checked_div(args[1]) {
+                Some(q) => Ok(Action::Store(q)),
+                None => Err("division by zero".to_string()),
+            },
+        )
+        .define(21, "jmp", &[Access::Read], |args| {
+            Ok(Action::Jump(args[0]))
+        })
+        .define(22, "nop", &[], |_| Ok(Action::Next));
+    assert_eq!(isa.mnemonic(21), Some("jmp"));
+    assert_eq!(isa.nopnds(20), Some(3));
+
+    // Count down from the input by halving, printing each
+    // value.
+    let prog = vec![
+        3, 17, // in [x]
+        4, 17, // out [x]
+        22, // nop
+        1020, 17, 2, 17, // div [x], #2, [x]
+        1006, 17, 16, // jf [x], #16
+        121, 2, // jmp #2
+        0, 0,  // unused
+        99, // halt
+        0,  // x
+    ];
+    let mut ic = Intcode::new(prog.clone())
+        .with_isa(isa.clone())
+        .with_inputs(vec![100])
+        .with_journal();
+    assert_eq!(ic.collect_outputs(), vec![100, 50, 25, 12, 6, 3, 1]);
+    assert_eq!(ic.peek(17), 0);
+
+    // User-defined stores are undone like any other.
+    ic.rewind(usize::MAX);
+    assert_eq!(ic.memory().to_vec(), prog);
+
+    // Handler errors become faults.
+    let mut ic = Intcode::new(vec![20, 5, 6, 7, 99, 1, 0, 0])
+        .with_isa(isa.clone());
+    let err = ic.try_run().unwrap_err();
+    assert_eq!(
+        err.fault,
+        Fault::UserOp("division by zero".to_string())
+    );
+    assert_eq!(
+        err.to_string(),
+        "ip 0 (insn 20): user-defined instruction failed: division by zero",
+    );
+
+    // Handler errors are escaped in JSON traces.
+    let buf = super::tracer::Buf::default();
+    let nope = Isa::new(Dialect::Day9).define(
+        10,
+        "nope",
+        &[Access::Read],
+        |_| Err("said \"no\" \\ twice\n".to_string()),
+    );
+    let mut ic = Intcode::new(vec![110, 7]).with_isa(nope);
+    ic.set_trace(buf.clone(), super::TraceFormat::Json);
+    assert!(ic.try_run().is_err());
+    let text =
+        String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(
+        text,
+        r#"{"n":0,"ip":0,"reads":[],"rel_base":0,"fault":"user-defined instruction failed: said \"no\" \\ twice\n"}"#.to_string() + "\n",
+    );
+
+    // Without the `Isa`, user-defined opcodes are illegal.
+    let mut ic = Intcode::new(vec![22, 99]);
+    assert_eq!(
+        ic.try_run().unwrap_err().fault,
+        Fault::IllegalOpcode(22)
+    );
+}
@@ -0,0 +1,424 @@
+//! Reverse execution of Intcode machines.
+//!
+//! A machine with a journal, turned on by
+//! `Intcode::start_journal()`, records enough about every
+//! instruction it executes to undo it: the old value of
+//! any memory it stores to, its `ip` and `rel_base`, and
+//! any input it consumes. `Intcode::rewind()` then steps
+//! the machine backward, and `rewind_to_suspension()` takes
+//! it back to the last time it suspended.
+//!
+//! The journal also keeps the machine as it was when
+//! journaling started, and a log of every input consumed
+//! since. Since Intcode machines are deterministic, these
+//! are enough to replay the run to any point with
+//! `Journal::replay()`, even after the oldest entries have
+//! been dropped to bound the journal's size.
+//!
+//! Rewinding restores memory, `ip`, `rel_base`, pending
+//! inputs and the executed-instruction count. Fuel,
+//! tracing and profiling are not affected.
+//!
+//! Poking memory or restoring a snapshot changes the
+//! machine other than by executing instructions, so it
+//! restarts the journal from the changed machine.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::Terminus;
+//!
+//! // Count down from the input, outputting each value.
+//! let prog = aoc::assemble("
+//!         in [n]
+//! loop:   out [n]
+//!         add [n], #-1, [n]
+//!         jt [n], #loop
+//!         halt
+//! n:      .data 0
+//! ").unwrap();
+//! let mut ic = aoc::Intcode::new(prog).with_journal();
+//! ic.add_input(3);
+//! assert_eq!(ic.run(), Terminus::HaveOutput(3));
+//! assert_eq!(ic.run(), Terminus::HaveOutput(2));
+//! ic.rewind_to_suspension();
+//! assert_eq!(ic.run(), Terminus::HaveOutput(2));
+//! assert_eq!(ic.rewind(10), 5);
+//! assert_eq!(ic.pending_inputs(), vec![3]);
+//! ```
+
+use std::collections::VecDeque;
+
+use super::{
+    BigInt, DecodeCache, Intcode, IntcodeError, Operand, Terminus,
+};
+
+// Undo record for a single instruction.
+#[derive(Debug, Clone)]
+pub(super) struct Entry {
+    ip: usize,
+    rel_base: i64,
+    // Memory length before the instruction, which may have
+    // extended memory.
+    len: usize,
+    executed: u64,
+    // Address stored to, with its old value and any old
+    // value too large for an `i64`.
+    store: Option<(usize, i64, Option<BigInt>)>,
+    input: Option<i64>,
+    // The machine suspended after this instruction.
+    suspends: bool,
+}
+
+/// Execution journal of a machine.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    start: Box<Intcode>,
+    entries: VecDeque<Entry>,
+    limit: Option<usize>,
+    inputs: Vec<i64>,
+}
+
+impl Journal {
+    /// Number of instructions that can be rewound.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if nothing can be rewound.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The machine as it was when journaling started.
+    pub fn start(&self) -> &Intcode {
+        &self.start
+    }
+
+    /// Every input consumed since journaling started, in
+    /// order, less any consumed by instructions since
+    /// rewound.
+    pub fn input_log(&self) -> &[i64] {
+        &self.inputs
+    }
+
+    /// Replay the journaled run from its start, using the
+    /// input log, until the replayed machine's
+    /// `executed()` count reaches the given count. See
+    /// `Intcode::replay()`.
+    pub fn replay(&self, until: u64) -> Result<Intcode, IntcodeError> {
+        let mut ic = (*self.start).clone();
+        ic.replay(&self.inputs, until)?;
+        Ok(ic)
+    }
+}
+
+impl Intcode {
+    /// Builder for turning on the journal.
+    pub fn with_journal(mut self) -> Self {
+        self.start_journal(None);
+        self
+    }
+
+    /// Start journaling from scratch, discarding any
+    /// journal so far. With a limit, only that many of the
+    /// most recent instructions can be rewound.
+    pub fn start_journal(&mut self, limit: Option<usize>) {
+        let mut start = self.clone();
+        start.journal = None;
+        start.tracer = None;
+        start.profile = None;
+        self.journal = Some(Journal {
+            start: Box::new(start),
+            entries: VecDeque::new(),
+            limit,
+            inputs: Vec::new(),
+        });
+    }
+
+    // Restart journaling, keeping the limit, after the
+    // machine was changed other than by executing.
+    pub(super) fn restart_journal(&mut self) {
+        if let Some(limit) = self.journal.as_ref().map(|j| j.limit) {
+            self.start_journal(limit);
+        }
+    }
+
+    /// The journal, if journaling.
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Stop journaling, returning the journal.
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    /// Undo up to the given number of instructions.
+    /// Returns the number undone, which is less than asked
+    /// only if the journal ran out.
+    pub fn rewind(&mut self, n: usize) -> usize {
+        for i in 0..n {
+            if !self.undo() {
+                return i;
+            }
+        }
+        n
+    }
+
+    /// Undo instructions back to the last time the machine
+    /// suspended, before the current suspension if it has
+    /// just suspended. Returns the number undone.
+    pub fn rewind_to_suspension(&mut self) -> usize {
+        let mut n = 0;
+        while self.undo() {
+            n += 1;
+            let top =
+                self.journal.as_ref().and_then(|j| j.entries.back());
+            if top.is_none_or(|e| e.suspends) {
+                break;
+            }
+        }
+        n
+    }
+
+    /// Run from the current state, with the given inputs in
+    /// place of any pending, until `executed()` reaches the
+    /// given count, skipping any outputs. Returns the
+    /// suspension that stopped the run early, if any.
+    /// Inputs the run did not consume are left pending.
+    pub fn replay(
+        &mut self,
+        inputs: &[i64],
+        until: u64,
+    ) -> Result<Option<Terminus>, IntcodeError> {
+        self.inputs = inputs.iter().cloned().collect();
+        while self.executed < until {
+            match self.try_step()? {
+                None | Some(Terminus::HaveOutput(_)) => (),
+                t => return Ok(t),
+            }
+        }
+        Ok(None)
+    }
+
+    // Undo the most recent journaled instruction, if any.
+    fn undo(&mut self) -> bool {
+        let journal = match self.journal {
+            Some(ref mut journal) => journal,
+            None => return false,
+        };
+        let entry = match journal.entries.pop_back() {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if let Some(input) = entry.input {
+            journal.inputs.pop();
+            self.inputs.push_front(input);
+        }
+        if let Some((addr, old, ref big)) = entry.store {
+            match big {
+                Some(big) => self.prog.set_big(addr, big.clone()),
+                None => self.prog.set(addr, old),
+            }
+            if let Some(ref mut cache) = self.cache {
+                cache.invalidate(addr);
+            }
+        }
+        if entry.len < self.prog.len() {
+            self.prog.truncate(entry.len);
+            if self.cache.is_some() {
+                self.cache = Some(DecodeCache::default());
+            }
+        }
+        self.ip = entry.ip;
+        self.rel_base = entry.rel_base;
+        self.executed = entry.executed;
+        self.last_store = None;
+        self.big_output = None;
+        true
+    }
+
+    // Make an undo record for the instruction about to be
+    // executed.
+    pub(super) fn journal_entry(&self) -> Entry {
+        let store = self
+            .decode(self.ip)
+            .and_then(|insn| {
+                let addr = match insn.opnds[insn.op.store_opnd()?] {
+                    Operand::Pos(a) => a,
+                    Operand::Rel(o) => o.checked_add(self.rel_base)?,
+                    Operand::Imm(_) => return None,
+                };
+                Some(addr as usize)
+            })
+            .or_else(|| self.user_store_addr())
+            .map(|addr| {
+                let old = self.prog.get(addr).unwrap_or(0);
+                let big = match self.prog.is_big(addr) {
+                    true => self.prog.get_big(addr),
+                    false => None,
+                };
+                (addr, old, big)
+            });
+        Entry {
+            ip: self.ip,
+            rel_base: self.rel_base,
+            len: self.prog.len(),
+            executed: self.executed,
+            store,
+            input: self.inputs.front().cloned(),
+            suspends: false,
+        }
+    }
+
+    // Record the instruction just executed, given its undo
+    // record and its result.
+    pub(super) fn update_journal(
+        &mut self,
+        mut entry: Entry,
+        ninputs: usize,
+        result: &Result<Option<Terminus>, IntcodeError>,
+    ) {
+        let journal = match self.journal {
+            Some(ref mut journal) => journal,
+            None => return,
+        };
+        match result {
+            Ok(Some(Terminus::NeedInput)) => {
+                if let Some(top) = journal.entries.back_mut() {
+                    top.suspends = true;
+                }
+                return;
+            }
+            Ok(Some(Terminus::OutOfFuel)) => return,
+            Ok(Some(_)) => entry.suspends = true,
+            _ => (),
+        }
+        if entry.store.as_ref().map(|&(addr, _, _)| addr)
+            != self.last_store
+        {
+            entry.store = None;
+        }
+        if self.inputs.len() < ninputs {
+            journal.inputs.extend(entry.input);
+        } else {
+            entry.input = None;
+        }
+        journal.entries.push_back(entry);
+        if journal.limit.is_some_and(|l| journal.entries.len() > l) {
+            journal.entries.pop_front();
+        }
+    }
+}
+
+#[test]
+fn test_journal() {
+    // Read values into a far-off buffer until a zero,
+    // then output their sum.
+    let prog = super::assemble(
+        "
+                rbo #5000
+        loop:   in rb
+                add [sum], rb, [sum]
+                rbo #1
+                jt rb-1, #loop
+                out [sum]
+                halt
+        sum:    .data 0
+        ",
+    )
+    .unwrap();
+    let mut ic = Intcode::new(prog).with_journal();
+    let start = ic.clone();
+    let mut states = vec![start.snapshot()];
+    ic.add_input(4);
+    ic.add_input(5);
+    assert_eq!(ic.run(), Terminus::NeedInput);
+    states.push(ic.snapshot());
+    ic.add_input(0);
+    assert_eq!(ic.run(), Terminus::HaveOutput(9));
+    assert_eq!(ic.run(), Terminus::Halted);
+    assert_eq!(ic.journal().unwrap().input_log(), &[4, 5, 0]);
+    let executed = ic.executed();
+
+    // Step back to the output, and then to the input
+    // request.
+    let halted = ic.snapshot();
+    assert_eq!(ic.rewind_to_suspension(), 1);
+    assert_eq!(ic.run(), Terminus::Halted);
+    assert_eq!(ic.snapshot(), halted);
+    assert_eq!(ic.rewind_to_suspension(), 1);
+    assert_eq!(ic.rewind_to_suspension(), 5);
+    assert_eq!(ic.snapshot().ip(), states[1].ip());
+    assert_eq!(ic.snapshot().memory(), states[1].memory());
+    assert_eq!(ic.pending_inputs(), vec![0]);
+    assert_eq!(ic.journal().unwrap().input_log(), &[4, 5]);
+
+    // All the way back, including shrinking memory.
+    ic.rewind(1000);
+    assert_eq!(ic.snapshot().memory(), states[0].memory());
+    assert_eq!(ic.memory().len(), start.memory().len());
+    assert_eq!(ic.executed(), 0);
+    assert_eq!(ic.pending_inputs(), vec![4, 5, 0]);
+    assert_eq!(ic.rewind(1), 0);
+    assert_eq!(ic.collect_outputs(), vec![9]);
+
+    // Replaying reproduces the run.
+    let journal = ic.take_journal().unwrap();
+    let replayed = journal.replay(executed).unwrap();
+    assert_eq!(replayed.snapshot(), halted);
+    let mut ic = journal.replay(3).unwrap();
+    assert_eq!(ic.executed(), 3);
+    assert_eq!(ic.pending_inputs(), vec![5, 0]);
+    assert_eq!(ic.collect_outputs(), vec![9]);
+
+    // A limited journal rewinds only so far.
+    let mut ic =
+        Intcode::new(vec![1101, 1, 1, 5, 1105, 1, 0]).with_fuel(10);
+    ic.start_journal(Some(4));
+    ic.run();
+    assert_eq!(ic.journal().unwrap().len(), 4);
+    assert_eq!(ic.rewind(10), 4);
+}
+
+#[test]
+fn test_journal_poke() {
+    // Output a running sum of the inputs.
+    let prog = super::assemble(
+        "
+        loop:   in [x]
+                add [x], [sum], [sum]
+                out [sum]
+                jt #1, #loop
+        x:      .data 0
+        sum:    .data 0
+        ",
+    )
+    .unwrap();
+    let mut ic = Intcode::new(prog).with_journal();
+    ic.add_input(2);
+    assert_eq!(ic.run(), Terminus::HaveOutput(2));
+    assert_eq!(ic.run(), Terminus::NeedInput);
+
+    // A poke restarts the journal from the poked machine.
+    ic.poke(12, 40);
+    assert!(ic.journal().unwrap().is_empty());
+    assert_eq!(ic.journal().unwrap().start().try_peek(12).unwrap(), 40);
+    ic.add_input(2);
+    assert_eq!(ic.run(), Terminus::HaveOutput(42));
+    let executed = ic.executed();
+    let state = ic.snapshot();
+
+    // Replaying keeps the poke, and rewinding stops at it.
+    let replayed = ic.journal().unwrap().replay(executed).unwrap();
+    assert_eq!(replayed.snapshot(), state);
+    assert_eq!(ic.rewind(100), 3);
+    assert_eq!(ic.peek(12), 40);
+    assert_eq!(ic.pending_inputs(), vec![2]);
+
+    // So does restoring a snapshot.
+    ic.restore(&state);
+    assert!(ic.journal().unwrap().is_empty());
+    assert_eq!(ic.journal().unwrap().start().snapshot(), state);
+}
@@ -0,0 +1,1561 @@
+//! A small structured language compiled to Intcode.
+//!
+//! Writing Intcode by hand, even with the assembler, is
+//! tedious when the point is to exercise relative-mode
+//! stack frames. This module compiles a little language
+//! with integer variables, `if`/`while`, recursive
+//! functions and I/O into Intcode assembly, and provides a
+//! reference interpreter for it so that compiled programs
+//! can be checked against their intended behavior.
+//!
+//! A program is a sequence of global variables, each
+//! initialized with a constant, and functions. Execution
+//! starts at `main`, which takes no arguments.
+//!
+//! ```text
+//! var count = 0;
+//!
+//! fn fib(n) {
+//!     count = count + 1;
+//!     if n < 2 { return n; }
+//!     return fib(n - 1) + fib(n - 2);
+//! }
+//!
+//! fn main() {
+//!     var n = input();
+//!     while n > 0 {
+//!         output(fib(n));
+//!         n = input();
+//!     }
+//!     output(count);
+//! }
+//! ```
+//!
+//! Statements are `var x = e;` (declaring a variable local
+//! to the enclosing block), `x = e;`, `if e { ... }` with
+//! optional `else { ... }` or `else if`, `while e { ... }`,
+//! `break;`, `continue;`, `return;`, `return e;`,
+//! `output(e);` and `e;`. Expressions are built from
+//! integer constants, variables, calls, `input()`, unary
+//! `-` and `!`, and the binary operators `*`, `+`, `-`,
+//! `<`, `>`, `<=`, `>=`, `==`, `!=`, `&&` and `||` from
+//! tightest to loosest binding, which otherwise behave as
+//! in Rust on `i64` values, with comparisons and logical
+//! operators giving 0 or 1 and any nonzero value counting
+//! as true. A function that ends without `return` returns
+//! 0. Comments run from `//` to the end of the line.
+//!
+//! Arithmetic is checked, as with `Arith::Checked`: `a - b`
+//! is computed as `a + -1 * b`, and overflow anywhere is an
+//! error.
+//!
+//! Compiled functions keep their frames on a stack above
+//! the program, addressed through the relative base, which
+//! always points just past the current frame. A call puts
+//! the return address and arguments at `rb+0` and up and
+//! jumps to the function, which moves the relative base up
+//! past its frame; it returns with its result at `rb+1`.
+//! These are the idioms that `analyze()` recognizes.
+//!
+//! # Examples
+//!
+//! ```rust
+//! let src = "
+//!     fn square(x) { return x * x; }
+//!     fn main() { output(square(input())); }
+//! ";
+//! let prog = aoc::compile(src).unwrap();
+//! let mut ic = aoc::Intcode::new(prog).with_inputs(vec![12]);
+//! assert_eq!(ic.collect_outputs(), vec![144]);
+//! assert_eq!(aoc::interpret(src, &[12]).unwrap(), vec![144]);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use super::assemble;
+
+/// Error in a program, either found while compiling or
+/// while interpreting. Line and column numbers start at
+/// 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LangError {
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+impl LangError {
+    fn new(line: usize, col: usize, msg: impl Into<String>) -> Self {
+        Self {
+            line,
+            col,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for LangError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.msg)
+    }
+}
+
+impl std::error::Error for LangError {}
+
+// Deepest call nesting the interpreter allows. Compiled
+// code is limited only by the memory its stack grows into,
+// and the interpreter does not recurse on calls, so this
+// just turns runaway recursion into an error.
+const MAX_DEPTH: usize = 100_000;
+
+// Tokens of the language, without positions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Num(i64),
+    Punct(&'static str),
+}
+
+// Punctuation, longest first so that it can be matched
+// greedily.
+const PUNCTS: [&str; 19] = [
+    "==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "<", ">", "=",
+    "!", "(", ")", "{", "}", ",", ";",
+];
+
+// Words that can't be used as names.
+const KEYWORDS: [&str; 10] = [
+    "var", "fn", "if", "else", "while", "break", "continue", "return",
+    "input", "output",
+];
+
+// Split the source into tokens, each with its line and
+// column.
+fn tokenize(
+    src: &str,
+) -> Result<Vec<(Token, usize, usize)>, LangError> {
+    let mut tokens = Vec::new();
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        let text = match text.find("//") {
+            Some(c) => &text[..c],
+            None => text,
+        };
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let col = i + 1;
+            if c.is_whitespace() {
+                i += 1;
+            } else if c.is_ascii_digit() {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let digits: String = chars[start..i].iter().collect();
+                let n = digits.parse().map_err(|_| {
+                    LangError::new(line, col, "number out of range")
+                })?;
+                tokens.push((Token::Num(n), line, col));
+            } else if c.is_alphabetic() || c == '_' {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_')
+                {
+                    i += 1;
+                }
+                let ident: String = chars[start..i].iter().collect();
+                tokens.push((Token::Ident(ident), line, col));
+            } else {
+                let rest: String = chars[i..].iter().take(2).collect();
+                let punct = PUNCTS
+                    .iter()
+                    .find(|p| rest.starts_with(*p))
+                    .ok_or_else(|| {
+                        let msg =
+                            format!("unexpected character {:?}", c);
+                        LangError::new(line, col, msg)
+                    })?;
+                tokens.push((Token::Punct(punct), line, col));
+                i += punct.len();
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// A variable, resolved to its storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    // Index in the function's locals, parameters first.
+    Local(usize),
+    // Index in the program's globals.
+    Global(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Mul,
+    Add,
+    Sub,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+// An expression with its source position.
+#[derive(Debug, Clone)]
+struct Expr {
+    kind: ExprKind,
+    line: usize,
+    col: usize,
+}
+
+#[derive(Debug, Clone)]
+enum ExprKind {
+    Num(i64),
+    Var(Var),
+    Input,
+    // Function index and arguments.
+    Call(usize, Vec<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // True if evaluating this expression might change a
+    // global.
+    fn has_call(&self) -> bool {
+        match self.kind {
+            ExprKind::Call(_, _) => true,
+            ExprKind::Unary(_, ref e) => e.has_call(),
+            ExprKind::Binary(_, ref l, ref r) => {
+                l.has_call() || r.has_call()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Assign(Var, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Break,
+    Continue,
+    Return(Option<Expr>),
+    Output(Expr),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone)]
+struct Func {
+    name: String,
+    nparams: usize,
+    // Number of locals, including the parameters.
+    nlocals: usize,
+    body: Vec<Stmt>,
+}
+
+// A parsed program with all names resolved.
+#[derive(Debug, Clone)]
+struct Program {
+    globals: Vec<(String, i64)>,
+    funcs: Vec<Func>,
+    main: usize,
+}
+
+// Recursive-descent parser, resolving names as it goes.
+struct Parser {
+    tokens: Vec<(Token, usize, usize)>,
+    posn: usize,
+    // Position just past the end of the source, for errors
+    // at end of input.
+    eof: (usize, usize),
+    // Global and function names, found by a scan ahead of
+    // parsing, with the function arities.
+    global_names: HashMap<String, usize>,
+    func_names: HashMap<String, (usize, usize)>,
+    // Local scopes of the function being parsed, innermost
+    // last.
+    scopes: Vec<HashMap<String, usize>>,
+    nlocals: usize,
+    loop_depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.posn).map(|(t, _, _)| t)
+    }
+
+    fn pos(&self) -> (usize, usize) {
+        self.tokens
+            .get(self.posn)
+            .map(|&(_, l, c)| (l, c))
+            .unwrap_or(self.eof)
+    }
+
+    fn error<T>(&self, msg: impl Into<String>) -> Result<T, LangError> {
+        let (line, col) = self.pos();
+        Err(LangError::new(line, col, msg))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.peek().cloned();
+        self.posn += 1;
+        t
+    }
+
+    fn eat(&mut self, p: &str) -> bool {
+        match self.peek() {
+            Some(Token::Punct(q)) if *q == p => {
+                self.posn += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn eat_keyword(&mut self, k: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(i)) if i == k => {
+                self.posn += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, p: &str) -> Result<(), LangError> {
+        if self.eat(p) {
+            Ok(())
+        } else {
+            self.error(format!("expected '{}'", p))
+        }
+    }
+
+    // Parse a name that is not a keyword.
+    fn name(&mut self) -> Result<String, LangError> {
+        match self.peek() {
+            Some(Token::Ident(name))
+                if !KEYWORDS.contains(&name.as_str()) =>
+            {
+                let name = name.clone();
+                self.posn += 1;
+                Ok(name)
+            }
+            _ => self.error("expected name"),
+        }
+    }
+
+    // Find the global and function names, so that they can
+    // be used before they are defined.
+    fn scan(&mut self) {
+        let mut depth = 0;
+        let mut i = 0;
+        while i < self.tokens.len() {
+            match (&self.tokens[i].0, self.tokens.get(i + 1)) {
+                (Token::Punct("{"), _) => depth += 1,
+                (Token::Punct("}"), _) => depth -= 1,
+                (Token::Ident(k), Some((Token::Ident(name), _, _)))
+                    if depth == 0 && k == "var" =>
+                {
+                    let nglobals = self.global_names.len();
+                    self.global_names
+                        .entry(name.clone())
+                        .or_insert(nglobals);
+                }
+                (Token::Ident(k), Some((Token::Ident(name), _, _)))
+                    if depth == 0 && k == "fn" =>
+                {
+                    let mut arity = 0;
+                    let mut j = i + 3;
+                    while let Some((Token::Ident(_), _, _)) =
+                        self.tokens.get(j)
+                    {
+                        arity += 1;
+                        j += 2;
+                    }
+                    let nfuncs = self.func_names.len();
+                    self.func_names
+                        .entry(name.clone())
+                        .or_insert((nfuncs, arity));
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+    }
+
+    // Parse a whole program.
+    fn program(&mut self) -> Result<Program, LangError> {
+        self.scan();
+        let mut globals = Vec::new();
+        let mut funcs: Vec<Option<Func>> =
+            vec![None; self.func_names.len()];
+        while self.peek().is_some() {
+            let (line, col) = self.pos();
+            if self.eat_keyword("var") {
+                let name = self.name()?;
+                if self.global_names[&name] != globals.len() {
+                    let msg = format!("duplicate global {}", name);
+                    return Err(LangError::new(line, col, msg));
+                }
+                self.expect("=")?;
+                let neg = self.eat("-");
+                let val = match self.next() {
+                    Some(Token::Num(n)) if neg => -n,
+                    Some(Token::Num(n)) => n,
+                    _ => {
+                        self.posn -= 1;
+                        return self.error("expected constant");
+                    }
+                };
+                self.expect(";")?;
+                globals.push((name, val));
+            } else if self.eat_keyword("fn") {
+                let name = self.name()?;
+                let index = self.func_names[&name].0;
+                if funcs[index].is_some() {
+                    let msg = format!("duplicate function {}", name);
+                    return Err(LangError::new(line, col, msg));
+                }
+                funcs[index] = Some(self.func(name)?);
+            } else {
+                return self.error("expected 'var' or 'fn'");
+            }
+        }
+        let main = match self.func_names.get("main") {
+            Some(&(main, 0)) => main,
+            Some(_) => {
+                return Err(LangError::new(
+                    1,
+                    1,
+                    "main takes arguments",
+                ))
+            }
+            None => {
+                return Err(LangError::new(1, 1, "no main function"))
+            }
+        };
+        let funcs = funcs.into_iter().map(|f| f.unwrap()).collect();
+        Ok(Program {
+            globals,
+            funcs,
+            main,
+        })
+    }
+
+    // Parse a function after its name.
+    fn func(&mut self, name: String) -> Result<Func, LangError> {
+        self.expect("(")?;
+        self.scopes = vec![HashMap::new()];
+        self.nlocals = 0;
+        if !self.eat(")") {
+            loop {
+                let (line, col) = self.pos();
+                let param = self.name()?;
+                if self.scopes[0].insert(param, self.nlocals).is_some()
+                {
+                    return Err(LangError::new(
+                        line,
+                        col,
+                        "duplicate parameter",
+                    ));
+                }
+                self.nlocals += 1;
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let nparams = self.nlocals;
+        let body = self.block()?;
+        Ok(Func {
+            name,
+            nparams,
+            nlocals: self.nlocals,
+            body,
+        })
+    }
+
+    // Parse a braced block in a new scope.
+    fn block(&mut self) -> Result<Vec<Stmt>, LangError> {
+        self.expect("{")?;
+        self.scopes.push(HashMap::new());
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return self.error("expected '}'");
+            }
+            stmts.push(self.stmt()?);
+        }
+        self.scopes.pop();
+        Ok(stmts)
+    }
+
+    // Look up a variable by name.
+    fn var(&self, name: &str) -> Option<Var> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|s| s.get(name).map(|&i| Var::Local(i)))
+            .or_else(|| {
+                self.global_names.get(name).map(|&g| Var::Global(g))
+            })
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, LangError> {
+        let (line, col) = self.pos();
+        if self.eat_keyword("var") {
+            let name = self.name()?;
+            self.expect("=")?;
+            let e = self.expr()?;
+            self.expect(";")?;
+            let local = self.nlocals;
+            self.nlocals += 1;
+            self.scopes.last_mut().unwrap().insert(name, local);
+            return Ok(Stmt::Assign(Var::Local(local), e));
+        }
+        if self.eat_keyword("if") {
+            return self.if_rest();
+        }
+        if self.eat_keyword("while") {
+            let cond = self.expr()?;
+            self.loop_depth += 1;
+            let body = self.block()?;
+            self.loop_depth -= 1;
+            return Ok(Stmt::While(cond, body));
+        }
+        let jump = if self.eat_keyword("break") {
+            Some(Stmt::Break)
+        } else if self.eat_keyword("continue") {
+            Some(Stmt::Continue)
+        } else {
+            None
+        };
+        if let Some(jump) = jump {
+            if self.loop_depth == 0 {
+                return Err(LangError::new(line, col, "not in a loop"));
+            }
+            self.expect(";")?;
+            return Ok(jump);
+        }
+        if self.eat_keyword("return") {
+            if self.eat(";") {
+                return Ok(Stmt::Return(None));
+            }
+            let e = self.expr()?;
+            self.expect(";")?;
+            return Ok(Stmt::Return(Some(e)));
+        }
+        if self.eat_keyword("output") {
+            self.expect("(")?;
+            let e = self.expr()?;
+            self.expect(")")?;
+            self.expect(";")?;
+            return Ok(Stmt::Output(e));
+        }
+        if let (
+            Some(Token::Ident(name)),
+            Some((Token::Punct("="), _, _)),
+        ) = (self.peek(), self.tokens.get(self.posn + 1))
+        {
+            let var = self.var(name).ok_or_else(|| {
+                let msg = format!("undefined variable {}", name);
+                LangError::new(line, col, msg)
+            })?;
+            self.posn += 2;
+            let e = self.expr()?;
+            self.expect(";")?;
+            return Ok(Stmt::Assign(var, e));
+        }
+        let e = self.expr()?;
+        self.expect(";")?;
+        Ok(Stmt::Expr(e))
+    }
+
+    // Parse the rest of an `if` statement.
+    fn if_rest(&mut self) -> Result<Stmt, LangError> {
+        let cond = self.expr()?;
+        let then = self.block()?;
+        let els = if !self.eat_keyword("else") {
+            Vec::new()
+        } else if self.eat_keyword("if") {
+            vec![self.if_rest()?]
+        } else {
+            self.block()?
+        };
+        Ok(Stmt::If(cond, then, els))
+    }
+
+    fn expr(&mut self) -> Result<Expr, LangError> {
+        self.binary(0)
+    }
+
+    // Parse a binary expression whose operators bind at
+    // least as tightly as the given level.
+    fn binary(&mut self, level: usize) -> Result<Expr, LangError> {
+        const LEVELS: [&[(&str, BinOp)]; 5] = [
+            &[("||", BinOp::Or)],
+            &[("&&", BinOp::And)],
+            &[
+                ("<", BinOp::Lt),
+                (">", BinOp::Gt),
+                ("<=", BinOp::Le),
+                (">=", BinOp::Ge),
+                ("==", BinOp::Eq),
+                ("!=", BinOp::Ne),
+            ],
+            &[("+", BinOp::Add), ("-", BinOp::Sub)],
+            &[("*", BinOp::Mul)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        loop {
+            let (line, col) = self.pos();
+            let op = LEVELS[level].iter().find(|(p, _)| self.eat(p));
+            let op = match op {
+                Some(&(_, op)) => op,
+                None => return Ok(left),
+            };
+            let right = self.binary(level + 1)?;
+            let kind =
+                ExprKind::Binary(op, Box::new(left), Box::new(right));
+            left = Expr { kind, line, col };
+            // Comparisons don't chain.
+            if level == 2 {
+                return Ok(left);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, LangError> {
+        let (line, col) = self.pos();
+        let op = if self.eat("-") {
+            UnOp::Neg
+        } else if self.eat("!") {
+            UnOp::Not
+        } else {
+            return self.primary();
+        };
+        let kind = ExprKind::Unary(op, Box::new(self.unary()?));
+        Ok(Expr { kind, line, col })
+    }
+
+    fn primary(&mut self) -> Result<Expr, LangError> {
+        let (line, col) = self.pos();
+        let kind = match self.next() {
+            Some(Token::Num(n)) => ExprKind::Num(n),
+            Some(Token::Punct("(")) => {
+                let e = self.expr()?;
+                self.expect(")")?;
+                return Ok(e);
+            }
+            Some(Token::Ident(ref k)) if k == "input" => {
+                self.expect("(")?;
+                self.expect(")")?;
+                ExprKind::Input
+            }
+            Some(Token::Ident(ref name))
+                if !KEYWORDS.contains(&name.as_str()) =>
+            {
+                if self.eat("(") {
+                    self.call(name, line, col)?
+                } else {
+                    let var = self.var(name).ok_or_else(|| {
+                        let msg =
+                            format!("undefined variable {}", name);
+                        LangError::new(line, col, msg)
+                    })?;
+                    ExprKind::Var(var)
+                }
+            }
+            _ => {
+                self.posn -= 1;
+                return self.error("expected expression");
+            }
+        };
+        Ok(Expr { kind, line, col })
+    }
+
+    // Parse the arguments of a call after the opening
+    // parenthesis.
+    fn call(
+        &mut self,
+        name: &str,
+        line: usize,
+        col: usize,
+    ) -> Result<ExprKind, LangError> {
+        let (index, arity) =
+            *self.func_names.get(name).ok_or_else(|| {
+                let msg = format!("undefined function {}", name);
+                LangError::new(line, col, msg)
+            })?;
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                args.push(self.expr()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        if args.len() != arity {
+            let msg = format!(
+                "{} takes {} arguments, got {}",
+                name,
+                arity,
+                args.len()
+            );
+            return Err(LangError::new(line, col, msg));
+        }
+        Ok(ExprKind::Call(index, args))
+    }
+}
+
+// Parse and resolve a program.
+fn parse(src: &str) -> Result<Program, LangError> {
+    let tokens = tokenize(src)?;
+    let lines = src.lines().count();
+    let eof =
+        (lines.max(1), src.lines().last().map_or(0, |l| l.len()) + 1);
+    Parser {
+        tokens,
+        posn: 0,
+        eof,
+        global_names: HashMap::new(),
+        func_names: HashMap::new(),
+        scopes: Vec::new(),
+        nlocals: 0,
+        loop_depth: 0,
+    }
+    .program()
+}
+
+// An operation of the reference interpreter. Each function
+// is lowered to a list of these, run with explicit value
+// and call stacks, so that deep recursion in a program does
+// not recurse in the interpreter. Operations that can fail
+// carry the line and column to blame.
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Push(i64),
+    Load(Var),
+    // Pop a value and store it.
+    Store(Var),
+    Input(usize, usize),
+    // Call a function with its arguments on the stack.
+    Call(usize, usize, usize),
+    Neg(usize, usize),
+    Not,
+    // Replace the top value with 1 if nonzero, 0 if zero.
+    Test,
+    // Apply an arithmetic or comparison operator to the
+    // top two values: not `&&` or `||`, which are jumps.
+    Binary(BinOp, usize, usize),
+    Jump(usize),
+    // Pop a value and jump if it is zero.
+    JumpIfZero(usize),
+    Output,
+    Pop,
+    // Return the top value to the caller.
+    Return,
+}
+
+// Lowering of a function body to operations.
+#[derive(Default)]
+struct Lower {
+    ops: Vec<Op>,
+    // For each enclosing loop, its start and the jumps to
+    // patch to its end.
+    loops: Vec<(usize, Vec<usize>)>,
+}
+
+impl Lower {
+    // Emit an operation, returning its index.
+    fn emit(&mut self, op: Op) -> usize {
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    // Point the jump at the given index here.
+    fn patch(&mut self, jump: usize) {
+        let here = self.ops.len();
+        match self.ops[jump] {
+            Op::Jump(ref mut t) | Op::JumpIfZero(ref mut t) => {
+                *t = here
+            }
+            _ => unreachable!("patching a non-jump"),
+        }
+    }
+
+    fn func(func: &Func) -> Vec<Op> {
+        let mut lower = Lower::default();
+        lower.block(&func.body);
+        lower.emit(Op::Push(0));
+        lower.emit(Op::Return);
+        lower.ops
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Assign(var, e) => {
+                    self.expr(e);
+                    self.emit(Op::Store(*var));
+                }
+                Stmt::If(cond, then, els) => {
+                    self.expr(cond);
+                    let skip_then = self.emit(Op::JumpIfZero(0));
+                    self.block(then);
+                    if els.is_empty() {
+                        self.patch(skip_then);
+                    } else {
+                        let skip_else = self.emit(Op::Jump(0));
+                        self.patch(skip_then);
+                        self.block(els);
+                        self.patch(skip_else);
+                    }
+                }
+                Stmt::While(cond, body) => {
+                    let start = self.ops.len();
+                    self.expr(cond);
+                    let exit = self.emit(Op::JumpIfZero(0));
+                    self.loops.push((start, vec![exit]));
+                    self.block(body);
+                    self.emit(Op::Jump(start));
+                    let (_, exits) = self.loops.pop().expect("no loop");
+                    for jump in exits {
+                        self.patch(jump);
+                    }
+                }
+                Stmt::Break => {
+                    let jump = self.emit(Op::Jump(0));
+                    let (_, exits) =
+                        self.loops.last_mut().expect("not in a loop");
+                    exits.push(jump);
+                }
+                Stmt::Continue => {
+                    let (start, _) =
+                        *self.loops.last().expect("not in a loop");
+                    self.emit(Op::Jump(start));
+                }
+                Stmt::Return(e) => {
+                    match e {
+                        Some(e) => self.expr(e),
+                        None => {
+                            self.emit(Op::Push(0));
+                        }
+                    }
+                    self.emit(Op::Return);
+                }
+                Stmt::Output(e) => {
+                    self.expr(e);
+                    self.emit(Op::Output);
+                }
+                Stmt::Expr(e) => {
+                    self.expr(e);
+                    self.emit(Op::Pop);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) {
+        let (line, col) = (e.line, e.col);
+        match e.kind {
+            ExprKind::Num(n) => {
+                self.emit(Op::Push(n));
+            }
+            ExprKind::Var(var) => {
+                self.emit(Op::Load(var));
+            }
+            ExprKind::Input => {
+                self.emit(Op::Input(line, col));
+            }
+            ExprKind::Call(f, ref args) => {
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(Op::Call(f, line, col));
+            }
+            ExprKind::Unary(UnOp::Neg, ref x) => {
+                self.expr(x);
+                self.emit(Op::Neg(line, col));
+            }
+            ExprKind::Unary(UnOp::Not, ref x) => {
+                self.expr(x);
+                self.emit(Op::Not);
+            }
+            ExprKind::Binary(BinOp::And, ref l, ref r) => {
+                // l ? (r != 0) : 0
+                self.expr(l);
+                let skip_r = self.emit(Op::JumpIfZero(0));
+                self.expr(r);
+                self.emit(Op::Test);
+                let done = self.emit(Op::Jump(0));
+                self.patch(skip_r);
+                self.emit(Op::Push(0));
+                self.patch(done);
+            }
+            ExprKind::Binary(BinOp::Or, ref l, ref r) => {
+                // l ? 1 : (r != 0)
+                self.expr(l);
+                let eval_r = self.emit(Op::JumpIfZero(0));
+                self.emit(Op::Push(1));
+                let done = self.emit(Op::Jump(0));
+                self.patch(eval_r);
+                self.expr(r);
+                self.emit(Op::Test);
+                self.patch(done);
+            }
+            ExprKind::Binary(op, ref l, ref r) => {
+                self.expr(l);
+                self.expr(r);
+                self.emit(Op::Binary(op, line, col));
+            }
+        }
+    }
+}
+
+// A call in progress in the reference interpreter.
+struct Frame {
+    func: usize,
+    // Index of the next operation.
+    pc: usize,
+    // Index of the first local in the locals stack.
+    base: usize,
+}
+
+/// Run the given program with the given inputs using the
+/// reference interpreter, returning its outputs. Returns
+/// an error if the program is malformed, or if at run time
+/// it needs more input than given, overflows, or nests
+/// calls too deeply.
+pub fn interpret(
+    src: &str,
+    inputs: &[i64],
+) -> Result<Vec<i64>, LangError> {
+    let prog = parse(src)?;
+    let code: Vec<Vec<Op>> =
+        prog.funcs.iter().map(Lower::func).collect();
+    let mut globals: Vec<i64> =
+        prog.globals.iter().map(|&(_, v)| v).collect();
+    let mut inputs: VecDeque<i64> = inputs.iter().cloned().collect();
+    let mut outputs = Vec::new();
+    let mut values: Vec<i64> = Vec::new();
+    let mut locals = vec![0; prog.funcs[prog.main].nlocals];
+    let mut frames = vec![Frame {
+        func: prog.main,
+        pc: 0,
+        base: 0,
+    }];
+    let pop = |values: &mut Vec<i64>| {
+        values.pop().expect("value stack underflow")
+    };
+    loop {
+        let frame = frames.last_mut().expect("no frame");
+        let op = code[frame.func][frame.pc];
+        frame.pc += 1;
+        let base = frame.base;
+        let overflow = |line, col| {
+            LangError::new(line, col, "arithmetic overflow")
+        };
+        match op {
+            Op::Push(n) => values.push(n),
+            Op::Load(Var::Local(i)) => values.push(locals[base + i]),
+            Op::Load(Var::Global(g)) => values.push(globals[g]),
+            Op::Store(var) => {
+                let v = pop(&mut values);
+                match var {
+                    Var::Local(i) => locals[base + i] = v,
+                    Var::Global(g) => globals[g] = v,
+                }
+            }
+            Op::Input(line, col) => match inputs.pop_front() {
+                Some(v) => values.push(v),
+                None => {
+                    return Err(LangError::new(
+                        line,
+                        col,
+                        "out of input",
+                    ))
+                }
+            },
+            Op::Call(f, line, col) => {
+                if frames.len() == MAX_DEPTH {
+                    return Err(LangError::new(
+                        line,
+                        col,
+                        "recursion too deep",
+                    ));
+                }
+                let func = &prog.funcs[f];
+                let base = locals.len();
+                let args = values.len() - func.nparams;
+                locals.extend(values.drain(args..));
+                locals.resize(base + func.nlocals, 0);
+                frames.push(Frame {
+                    func: f,
+                    pc: 0,
+                    base,
+                });
+            }
+            Op::Neg(line, col) => {
+                let v = pop(&mut values);
+                let v = v
+                    .checked_mul(-1)
+                    .ok_or_else(|| overflow(line, col))?;
+                values.push(v);
+            }
+            Op::Not => {
+                let v = pop(&mut values);
+                values.push((v == 0) as i64);
+            }
+            Op::Test => {
+                let v = pop(&mut values);
+                values.push((v != 0) as i64);
+            }
+            Op::Binary(op, line, col) => {
+                let b = pop(&mut values);
+                let a = pop(&mut values);
+                let v = match op {
+                    BinOp::Mul => a.checked_mul(b),
+                    BinOp::Add => a.checked_add(b),
+                    BinOp::Sub => {
+                        b.checked_mul(-1).and_then(|b| a.checked_add(b))
+                    }
+                    BinOp::Lt => Some((a < b) as i64),
+                    BinOp::Gt => Some((a > b) as i64),
+                    BinOp::Le => Some((a <= b) as i64),
+                    BinOp::Ge => Some((a >= b) as i64),
+                    BinOp::Eq => Some((a == b) as i64),
+                    BinOp::Ne => Some((a != b) as i64),
+                    BinOp::And | BinOp::Or => {
+                        unreachable!("logical op")
+                    }
+                };
+                values.push(v.ok_or_else(|| overflow(line, col))?);
+            }
+            Op::Jump(target) => frame.pc = target,
+            Op::JumpIfZero(target) => {
+                if pop(&mut values) == 0 {
+                    frame.pc = target;
+                }
+            }
+            Op::Output => outputs.push(pop(&mut values)),
+            Op::Pop => {
+                pop(&mut values);
+            }
+            Op::Return => {
+                frames.pop();
+                locals.truncate(base);
+                if frames.is_empty() {
+                    return Ok(outputs);
+                }
+            }
+        }
+    }
+}
+
+// Operand of a generated instruction. Frame slots are
+// numbered up from the return address at slot 0, and are
+// rendered relative to the top of the frame once its size
+// is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Opnd {
+    Imm(i64),
+    Label(String),
+    Global(usize),
+    // Return address, parameters and locals.
+    Slot(usize),
+    Temp(usize),
+    // Offset from the top of the frame, where calls put
+    // their arguments.
+    Top(i64),
+    // Negated frame size, for popping the frame.
+    Pop,
+}
+
+// Code generator for one program.
+struct Gen<'a> {
+    prog: &'a Program,
+    // Lines of the function being generated: instructions
+    // and labels.
+    lines: Vec<(Option<String>, &'static str, Vec<Opnd>)>,
+    labels: usize,
+    temps: usize,
+    max_temps: usize,
+    // Continue and break labels of the enclosing loops.
+    loops: Vec<(String, String)>,
+}
+
+impl Gen<'_> {
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    fn place(&mut self, label: String) {
+        self.lines.push((Some(label), "", Vec::new()));
+    }
+
+    fn emit(&mut self, op: &'static str, opnds: Vec<Opnd>) {
+        self.lines.push((None, op, opnds));
+    }
+
+    fn temp(&mut self) -> Opnd {
+        let t = self.temps;
+        self.temps += 1;
+        self.max_temps = self.max_temps.max(self.temps);
+        Opnd::Temp(t)
+    }
+
+    fn var(&self, var: Var) -> Opnd {
+        match var {
+            Var::Local(i) => Opnd::Slot(1 + i),
+            Var::Global(g) => Opnd::Global(g),
+        }
+    }
+
+    // Generate a function, returning its assembly.
+    fn func(&mut self, func: &Func) -> String {
+        self.lines.clear();
+        self.temps = 0;
+        self.max_temps = 0;
+        self.block(&func.body);
+        self.ret(None);
+
+        let size = 1 + func.nlocals + self.max_temps;
+        let render = |opnd: &Opnd| match *opnd {
+            Opnd::Imm(v) => format!("#{}", v),
+            Opnd::Label(ref l) => format!("#{}", l),
+            Opnd::Global(g) => {
+                format!("[g_{}]", self.prog.globals[g].0)
+            }
+            Opnd::Slot(s) => rel(s as i64 - size as i64),
+            Opnd::Temp(t) => {
+                rel((1 + func.nlocals + t) as i64 - size as i64)
+            }
+            Opnd::Top(off) => rel(off),
+            Opnd::Pop => format!("#-{}", size),
+        };
+        let mut asm = format!(
+            "; fn {}: {} params, frame {}\nf_{}:\n        rbo #{}\n",
+            func.name, func.nparams, size, func.name, size,
+        );
+        for (label, op, opnds) in &self.lines {
+            match label {
+                Some(label) => asm += &format!("{}:\n", label),
+                None if opnds.is_empty() => {
+                    asm += &format!("        {}\n", op)
+                }
+                None => {
+                    let opnds: Vec<String> =
+                        opnds.iter().map(render).collect();
+                    asm += &format!(
+                        "        {} {}\n",
+                        op,
+                        opnds.join(", ")
+                    );
+                }
+            }
+        }
+        asm
+    }
+
+    // Return from the function, with the given value or 0.
+    fn ret(&mut self, e: Option<&Expr>) {
+        match e {
+            Some(e) => self.expr_into(e, Opnd::Slot(1)),
+            None => self.emit(
+                "add",
+                vec![Opnd::Imm(0), Opnd::Imm(0), Opnd::Slot(1)],
+            ),
+        }
+        self.emit("rbo", vec![Opnd::Pop]);
+        self.emit("jf", vec![Opnd::Imm(0), Opnd::Top(0)]);
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            let mark = self.temps;
+            self.stmt(stmt);
+            self.temps = mark;
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Assign(var, e) => {
+                let dest = self.var(*var);
+                self.expr_into(e, dest);
+            }
+            Stmt::If(cond, then, els) => {
+                let (else_l, end_l) = (self.label(), self.label());
+                let c = self.operand(cond);
+                self.emit("jf", vec![c, Opnd::Label(else_l.clone())]);
+                self.block(then);
+                if !els.is_empty() {
+                    self.emit(
+                        "jt",
+                        vec![Opnd::Imm(1), Opnd::Label(end_l.clone())],
+                    );
+                }
+                self.place(else_l);
+                if !els.is_empty() {
+                    self.block(els);
+                    self.place(end_l);
+                }
+            }
+            Stmt::While(cond, body) => {
+                let (top_l, end_l) = (self.label(), self.label());
+                self.place(top_l.clone());
+                let c = self.operand(cond);
+                self.emit("jf", vec![c, Opnd::Label(end_l.clone())]);
+                self.loops.push((top_l.clone(), end_l.clone()));
+                self.block(body);
+                self.loops.pop();
+                self.emit("jt", vec![Opnd::Imm(1), Opnd::Label(top_l)]);
+                self.place(end_l);
+            }
+            Stmt::Break => {
+                let end_l = self.loops.last().unwrap().1.clone();
+                self.emit("jt", vec![Opnd::Imm(1), Opnd::Label(end_l)]);
+            }
+            Stmt::Continue => {
+                let top_l = self.loops.last().unwrap().0.clone();
+                self.emit("jt", vec![Opnd::Imm(1), Opnd::Label(top_l)]);
+            }
+            Stmt::Return(e) => self.ret(e.as_ref()),
+            Stmt::Output(e) => {
+                let v = self.operand(e);
+                self.emit("out", vec![v]);
+            }
+            Stmt::Expr(e) => {
+                self.operand(e);
+            }
+        }
+    }
+
+    // Generate code for an expression, returning an operand
+    // holding its value.
+    fn operand(&mut self, e: &Expr) -> Opnd {
+        match e.kind {
+            ExprKind::Num(n) => Opnd::Imm(n),
+            ExprKind::Var(var) => self.var(var),
+            _ => {
+                let t = self.temp();
+                self.expr_into(e, t.clone());
+                t
+            }
+        }
+    }
+
+    // As `operand()`, but the value must survive the
+    // evaluation of the given later expressions. Calls in
+    // them may change globals.
+    fn held_operand(&mut self, e: &Expr, later: &[Expr]) -> Opnd {
+        let v = self.operand(e);
+        match v {
+            Opnd::Global(_) if later.iter().any(Expr::has_call) => {
+                let t = self.temp();
+                self.emit("add", vec![v, Opnd::Imm(0), t.clone()]);
+                t
+            }
+            v => v,
+        }
+    }
+
+    // Generate code to store the value of an expression in
+    // the given destination, which is written only by the
+    // last instruction.
+    fn expr_into(&mut self, e: &Expr, dest: Opnd) {
+        match e.kind {
+            ExprKind::Num(_) | ExprKind::Var(_) => {
+                let v = self.operand(e);
+                self.emit("add", vec![v, Opnd::Imm(0), dest]);
+            }
+            ExprKind::Input => self.emit("in", vec![dest]),
+            ExprKind::Call(f, ref args) => {
+                let mut vals = Vec::new();
+                for (i, arg) in args.iter().enumerate() {
+                    vals.push(self.held_operand(arg, &args[i + 1..]));
+                }
+                for (i, v) in vals.into_iter().enumerate() {
+                    self.emit(
+                        "add",
+                        vec![v, Opnd::Imm(0), Opnd::Top(1 + i as i64)],
+                    );
+                }
+                let ret_l = self.label();
+                self.emit(
+                    "add",
+                    vec![
+                        Opnd::Label(ret_l.clone()),
+                        Opnd::Imm(0),
+                        Opnd::Top(0),
+                    ],
+                );
+                let target = format!("f_{}", self.prog.funcs[f].name);
+                self.emit(
+                    "jt",
+                    vec![Opnd::Imm(1), Opnd::Label(target)],
+                );
+                self.place(ret_l);
+                self.emit(
+                    "add",
+                    vec![Opnd::Top(1), Opnd::Imm(0), dest],
+                );
+            }
+            ExprKind::Unary(op, ref x) => {
+                let v = self.operand(x);
+                match op {
+                    UnOp::Neg => {
+                        self.emit("mul", vec![v, Opnd::Imm(-1), dest])
+                    }
+                    UnOp::Not => {
+                        self.emit("eq", vec![v, Opnd::Imm(0), dest])
+                    }
+                }
+            }
+            ExprKind::Binary(op @ BinOp::And, ref l, ref r)
+            | ExprKind::Binary(op @ BinOp::Or, ref l, ref r) => {
+                let end_l = self.label();
+                let t = self.temp();
+                let a = self.operand(l);
+                let (init, skip) = match op {
+                    BinOp::And => (0, "jf"),
+                    _ => (1, "jt"),
+                };
+                self.emit(
+                    "add",
+                    vec![Opnd::Imm(init), Opnd::Imm(0), t.clone()],
+                );
+                self.emit(skip, vec![a, Opnd::Label(end_l.clone())]);
+                let b = self.operand(r);
+                self.emit("eq", vec![b, Opnd::Imm(0), t.clone()]);
+                self.emit(
+                    "eq",
+                    vec![t.clone(), Opnd::Imm(0), t.clone()],
+                );
+                self.place(end_l);
+                self.emit("add", vec![t, Opnd::Imm(0), dest]);
+            }
+            ExprKind::Binary(op, ref l, ref r) => {
+                let a = self.held_operand(l, std::slice::from_ref(r));
+                let b = self.operand(r);
+                match op {
+                    BinOp::Mul => self.emit("mul", vec![a, b, dest]),
+                    BinOp::Add => self.emit("add", vec![a, b, dest]),
+                    BinOp::Sub => match b {
+                        Opnd::Imm(v) if v != i64::MIN => self
+                            .emit("add", vec![a, Opnd::Imm(-v), dest]),
+                        b => {
+                            let t = self.temp();
+                            self.emit(
+                                "mul",
+                                vec![b, Opnd::Imm(-1), t.clone()],
+                            );
+                            self.emit("add", vec![a, t, dest]);
+                        }
+                    },
+                    BinOp::Lt => self.emit("lt", vec![a, b, dest]),
+                    BinOp::Gt => self.emit("lt", vec![b, a, dest]),
+                    BinOp::Eq => self.emit("eq", vec![a, b, dest]),
+                    BinOp::Le | BinOp::Ge | BinOp::Ne => {
+                        let t = self.temp();
+                        match op {
+                            BinOp::Le => {
+                                self.emit("lt", vec![b, a, t.clone()])
+                            }
+                            BinOp::Ge => {
+                                self.emit("lt", vec![a, b, t.clone()])
+                            }
+                            _ => self.emit("eq", vec![a, b, t.clone()]),
+                        }
+                        self.emit("eq", vec![t, Opnd::Imm(0), dest]);
+                    }
+                    BinOp::And | BinOp::Or => {
+                        unreachable!("logical op")
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Render an offset from the relative base.
+fn rel(off: i64) -> String {
+    if off < 0 {
+        format!("rb{}", off)
+    } else {
+        format!("rb+{}", off)
+    }
+}
+
+/// Compile the given program to Intcode assembly source,
+/// for `assemble()`.
+pub fn compile_asm(src: &str) -> Result<String, LangError> {
+    let prog = parse(src)?;
+    let mut gen = Gen {
+        prog: &prog,
+        lines: Vec::new(),
+        labels: 0,
+        temps: 0,
+        max_temps: 0,
+        loops: Vec::new(),
+    };
+    let main = &prog.funcs[prog.main].name;
+    let mut asm = format!(
+        "        rbo #stack\n        add #start_ret, #0, rb\n        jt #1, #f_{}\nstart_ret:\n        halt\n",
+        main,
+    );
+    for func in &prog.funcs {
+        asm += &gen.func(func);
+    }
+    for (name, val) in &prog.globals {
+        asm += &format!("g_{}: .data {}\n", name, val);
+    }
+    asm += "stack:\n";
+    Ok(asm)
+}
+
+/// Compile the given program to an Intcode program vector.
+pub fn compile(src: &str) -> Result<Vec<i64>, LangError> {
+    let asm = compile_asm(src)?;
+    let prog = assemble(&asm).expect("compiler produced bad assembly");
+    Ok(prog)
+}
+
+// Run a compiled program, returning its outputs, or `None`
+// if it faults or runs out of input.
+#[cfg(test)]
+fn run_compiled(prog: Vec<i64>, inputs: &[i64]) -> Option<Vec<i64>> {
+    use super::{Intcode, Terminus};
+
+    let mut ic = Intcode::new(prog).with_inputs(inputs.to_vec());
+    let mut outputs = Vec::new();
+    loop {
+        match ic.try_run().ok()? {
+            Terminus::Halted => return Some(outputs),
+            Terminus::HaveOutput(v) => outputs.push(v),
+            _ => return None,
+        }
+    }
+}
+
+#[test]
+fn test_lang_corpus() {
+    const CORPUS: [(&str, &str); 9] = [
+        ("fib", include_str!("corpus/fib.icl")),
+        ("gcd", include_str!("corpus/gcd.icl")),
+        ("primes", include_str!("corpus/primes.icl")),
+        ("globals", include_str!("corpus/globals.icl")),
+        ("scopes", include_str!("corpus/scopes.icl")),
+        ("collatz", include_str!("corpus/collatz.icl")),
+        ("parity", include_str!("corpus/parity.icl")),
+        ("overflow", include_str!("corpus/overflow.icl")),
+        ("echo_sum", include_str!("corpus/echo_sum.icl")),
+    ];
+    let header = |src: &str, key: &str| -> String {
+        let prefix = format!("// {}:", key);
+        src.lines()
+            .find_map(|l| l.strip_prefix(prefix.as_str()))
+            .unwrap_or_else(|| panic!("no {} header", key))
+            .trim()
+            .to_string()
+    };
+    let nums = |s: &str| -> Vec<i64> {
+        s.split_whitespace().map(|v| v.parse().unwrap()).collect()
+    };
+    for &(name, src) in &CORPUS {
+        let inputs = nums(&header(src, "inputs"));
+        let expect = header(src, "expect");
+        let interpreted = interpret(src, &inputs);
+        let prog =
+            compile(src).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let compiled = run_compiled(prog, &inputs);
+        if expect == "error" {
+            assert!(interpreted.is_err(), "{}", name);
+            assert_eq!(compiled, None, "{}", name);
+        } else {
+            let interpreted = interpreted
+                .unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(interpreted, nums(&expect), "{}", name);
+            assert_eq!(compiled, Some(interpreted), "{}", name);
+        }
+    }
+}
+
+#[test]
+fn test_lang_errors() {
+    let cases = [
+        ("fn f() {}", "1:1: no main function"),
+        ("fn main(x) {}", "1:1: main takes arguments"),
+        ("fn main() { x = 1; }", "1:13: undefined variable x"),
+        ("fn main() { f(); }", "1:13: undefined function f"),
+        (
+            "fn f(a) {}\nfn main() { f(); }",
+            "2:13: f takes 1 arguments, got 0",
+        ),
+        (
+            "var g = 1;\nvar g = 2;\nfn main() {}",
+            "2:1: duplicate global g",
+        ),
+        ("fn main() {}\nfn main() {}", "2:1: duplicate function main"),
+        ("fn main() { break; }", "1:13: not in a loop"),
+        ("fn main() { output(1 < 2 < 3); }", "1:26: expected ')'"),
+        ("fn main() { var if = 1; }", "1:17: expected name"),
+        ("fn main() { output(1) }", "1:23: expected ';'"),
+        ("fn main() {", "1:12: expected '}'"),
+        ("fn main() { $ }", "1:13: unexpected character '$'"),
+    ];
+    for &(src, msg) in &cases {
+        let err = compile(src).unwrap_err();
+        assert_eq!(err.to_string(), msg, "{}", src);
+    }
+
+    // Run-time errors are found only by the interpreter.
+    let src = "fn main() { output(input()); output(input()); }";
+    assert_eq!(
+        interpret(src, &[1]).unwrap_err().to_string(),
+        "1:37: out of input"
+    );
+    let src = "fn f(n) { return f(n + 1); }\nfn main() { f(0); }";
+    assert_eq!(
+        interpret(src, &[]).unwrap_err().msg,
+        "recursion too deep"
+    );
+    assert!(compile_asm(src).unwrap().contains("f_f:"));
+
+    // Recursion as deep as compiled code runs is fine.
+    let src = "
+        fn depth(n) { if n == 0 { return 0; } return depth(n - 1) + 1; }
+        fn main() { output(depth(input())); }
+    ";
+    for &n in &[300, 20_000] {
+        let compiled = run_compiled(compile(src).unwrap(), &[n]);
+        assert_eq!(compiled, Some(vec![n]));
+        assert_eq!(interpret(src, &[n]).unwrap(), vec![n]);
+    }
+}
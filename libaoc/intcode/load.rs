@@ -0,0 +1,295 @@
+//! Loading Intcode programs from text or binary images.
+//!
+//! Programs are normally text: words separated by commas.
+//! `parse_program()`, and the `Intcode` constructors built
+//! on it, allow whitespace and newlines anywhere between
+//! words, a trailing comma, and comments running from `;`
+//! or `#` to the end of a line. Bad input is reported with
+//! the line and column of the offending token.
+//!
+//! Large programs can also be kept as binary images, which
+//! are written by `Intcode::save_image()`. An image is the
+//! magic bytes `ICI1` followed by memory in the encoding
+//! used for snapshots: see `snapshot`. Runs of zeros take
+//! a couple of bytes, so sparse memory stays small.
+//! `Intcode::from_reader()` and `Intcode::from_path()`
+//! accept either form, telling them apart by the magic.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::Intcode;
+//!
+//! let mut ic = Intcode::parse("
+//!     3, 0,    ; in [0]
+//!     4, 0,    ; out [0]
+//!     99,
+//! ").unwrap();
+//! ic.add_input(5);
+//! assert_eq!(ic.collect_outputs(), vec![5]);
+//!
+//! let err = Intcode::parse("1,0,\n0,x,99").unwrap_err();
+//! assert_eq!(err.to_string(), "2:3: bad word `x`");
+//!
+//! let mut image = Vec::new();
+//! ic.save_image(&mut image).unwrap();
+//! let ic = Intcode::from_reader(image.as_slice()).unwrap();
+//! assert_eq!(ic.memory().to_vec(), vec![5, 0, 4, 0, 99]);
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::snapshot::{bad_data, read_memory, write_memory};
+use super::Intcode;
+
+const IMAGE_MAGIC: &[u8; 4] = b"ICI1";
+
+/// Error produced when loading a program fails.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The program could not be read, or its binary image
+    /// is malformed.
+    Io(io::Error),
+    /// The program text is malformed at the given line and
+    /// column, both starting at 1.
+    Parse {
+        line: usize,
+        col: usize,
+        msg: String,
+    },
+}
+
+impl LoadError {
+    fn parse(line: usize, col: usize, msg: impl Into<String>) -> Self {
+        LoadError::Parse {
+            line,
+            col,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Parse { line, col, msg } => {
+                write!(f, "{}:{}: {}", line, col, msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+/// Parse program text into words.
+pub fn parse_program(text: &str) -> Result<Vec<i64>, LoadError> {
+    let mut words = Vec::new();
+    // A word is wanted next, rather than a comma.
+    let mut want_word = true;
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let end = line.find([';', '#']).unwrap_or(line.len());
+        let mut rest = &line[..end];
+        let mut col = 1;
+        loop {
+            let trimmed = rest.trim_start();
+            col += rest[..rest.len() - trimmed.len()].chars().count();
+            rest = trimmed;
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(after) = rest.strip_prefix(',') {
+                if want_word {
+                    return Err(LoadError::parse(
+                        line_no,
+                        col,
+                        "missing word",
+                    ));
+                }
+                want_word = true;
+                rest = after;
+                col += 1;
+                continue;
+            }
+            let len = rest
+                .find(|c: char| c == ',' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let token = &rest[..len];
+            if !want_word {
+                let msg = format!("missing comma before `{}`", token);
+                return Err(LoadError::parse(line_no, col, msg));
+            }
+            let word = token.parse().map_err(|_| {
+                let digits = token.trim_start_matches(['-', '+']);
+                let msg = if !digits.is_empty()
+                    && digits.chars().all(|c| c.is_ascii_digit())
+                {
+                    format!("word `{}` out of range", token)
+                } else {
+                    format!("bad word `{}`", token)
+                };
+                LoadError::parse(line_no, col, msg)
+            })?;
+            words.push(word);
+            want_word = false;
+            rest = &rest[len..];
+            col += token.chars().count();
+        }
+    }
+    if words.is_empty() {
+        return Err(LoadError::parse(1, 1, "no program"));
+    }
+    Ok(words)
+}
+
+impl Intcode {
+    /// Make a new machine from program text.
+    pub fn parse(text: &str) -> Result<Self, LoadError> {
+        Ok(Self::new(parse_program(text)?))
+    }
+
+    /// Make a new machine from program text or a binary
+    /// image read from the given reader.
+    pub fn from_reader<R: Read>(mut r: R) -> Result<Self, LoadError> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        if let Some(mut image) = bytes.strip_prefix(IMAGE_MAGIC) {
+            let mut ic = Self::new(Vec::new());
+            ic.prog = read_memory(&mut image)?;
+            if !image.is_empty() {
+                return Err(
+                    bad_data("trailing data after image").into()
+                );
+            }
+            return Ok(ic);
+        }
+        let text = String::from_utf8(bytes)
+            .map_err(|_| bad_data("program is not text"))?;
+        Self::parse(&text)
+    }
+
+    /// Make a new machine from program text or a binary
+    /// image in the file at the given path.
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<Self, LoadError> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    /// Write this machine's memory as a binary image.
+    pub fn save_image<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(IMAGE_MAGIC)?;
+        write_memory(&mut w, &self.prog)
+    }
+}
+
+impl FromStr for Intcode {
+    type Err = LoadError;
+
+    fn from_str(text: &str) -> Result<Self, LoadError> {
+        Self::parse(text)
+    }
+}
+
+#[cfg(test)]
+fn parse_error(text: &str) -> String {
+    parse_program(text).unwrap_err().to_string()
+}
+
+#[test]
+fn test_load_text() {
+    let text = "# A program.\r\n1,9,10,3,\n  2,3,11,0, ; mul\n\n99,\n30,40,50\n";
+    let prog = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+    assert_eq!(parse_program(text).unwrap(), prog);
+    assert_eq!(parse_program(" -1 , +2,3").unwrap(), vec![-1, 2, 3]);
+    let mut ic: Intcode = text.parse().unwrap();
+    ic.run();
+    assert_eq!(ic.peek(0), 3500);
+
+    assert_eq!(parse_error("1,2,,3"), "1:5: missing word");
+    assert_eq!(parse_error(",1"), "1:1: missing word");
+    assert_eq!(parse_error("1,2\n3"), "2:1: missing comma before `3`");
+    assert_eq!(parse_error("1,\t2x"), "1:4: bad word `2x`");
+    assert_eq!(
+        parse_error("1,99999999999999999999"),
+        "1:3: word `99999999999999999999` out of range",
+    );
+    assert_eq!(parse_error(" ; nothing\n"), "1:1: no program");
+    assert_eq!(parse_error(""), "1:1: no program");
+}
+
+#[test]
+fn test_load_image() {
+    use super::BigInt;
+
+    let mut ic = Intcode::new(vec![1101, 2, 3, 7, 99]);
+    ic.prog.resize(1_000_001);
+    ic.prog.set(1_000_000, -5);
+    ic.prog
+        .set_big(9, &BigInt::from(i64::MAX) * &BigInt::from(4));
+    let mut image = Vec::new();
+    ic.save_image(&mut image).unwrap();
+    assert!(image.len() < 100);
+    let loaded = Intcode::from_reader(image.as_slice()).unwrap();
+    assert_eq!(loaded.memory(), ic.memory());
+
+    // Images and text files load the same way.
+    let dir = std::env::temp_dir();
+    let path =
+        dir.join(format!("intcode-load-{}.img", std::process::id()));
+    std::fs::write(&path, &image).unwrap();
+    let from_file = Intcode::from_path(&path);
+    std::fs::write(&path, "1101,2,3,7,99\n").unwrap();
+    let from_text = Intcode::from_path(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(from_file.unwrap().memory(), ic.memory());
+    assert_eq!(
+        from_text.unwrap().memory().to_vec(),
+        vec![1101, 2, 3, 7, 99]
+    );
+
+    let mut trailing = image.clone();
+    trailing.push(0);
+    assert!(Intcode::from_reader(trailing.as_slice()).is_err());
+    let truncated = &image[..image.len() - 1];
+    assert!(Intcode::from_reader(truncated).is_err());
+    // Hostile images: a run of `u64::MAX` zeros, and a
+    // length with bits past the top of a `u64`.
+    let huge_run =
+        b"ICI1\x0a\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01\x01";
+    assert_eq!(
+        Intcode::from_reader(&huge_run[..]).unwrap_err().to_string(),
+        "memory overrun",
+    );
+    let long_len = b"ICI1\xff\xff\xff\xff\xff\xff\xff\xff\xff\x7f";
+    assert_eq!(
+        Intcode::from_reader(&long_len[..]).unwrap_err().to_string(),
+        "varint too long",
+    );
+    let bad = [b'1', b',', 0xff];
+    assert_eq!(
+        Intcode::from_reader(&bad[..]).unwrap_err().to_string(),
+        "program is not text",
+    );
+    assert!(
+        Intcode::from_path(dir.join("no-such-intcode-file")).is_err()
+    );
+}
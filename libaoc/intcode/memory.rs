@@ -0,0 +1,669 @@
+//! Intcode machine memory.
+//!
+//! Memory is normally flat: a plain vector of words, as
+//! fast as it gets for the usual program that keeps to a
+//! few thousand words around its image. Cloning flat memory
+//! copies it.
+//!
+//! Memory can instead be paged, either on request with
+//! `Memory::make_paged()` or `Intcode::with_paged_memory()`,
+//! or by growing past the range a flat vector may cover.
+//! Paged memory is split into fixed-size pages shared
+//! between clones of a machine. Cloning copies only the
+//! page table; the first store to a shared page copies just
+//! that page. This makes branching thousands of machines in
+//! a search, or taking many snapshots, cheap.
+//!
+//! The low part of paged memory, where the program image
+//! lives, has a dense page table for fast access. Pages
+//! above that are kept in a sparse table and allocated only
+//! when written, so a program can use addresses in the
+//! trillions without trillions of words of storage.
+//!
+//! Values too large for an `i64`, as produced by
+//! `Arith::Big`, are kept in a side table: memory holds
+//! just their low 64 bits.
+//!
+//! Pages are made of atomic words, all accessed `Relaxed`:
+//! a page held by this memory alone is then written in
+//! place, without the atomic read-modify-write of
+//! `Arc::make_mut()`. Only a shared page is copied first.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicI64, Ordering::Relaxed};
+use std::sync::Arc;
+
+use super::{BigInt, Intcode};
+
+// Pages are `PAGE_SIZE` words.
+const PAGE_BITS: usize = 10;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+const PAGE_MASK: usize = PAGE_SIZE - 1;
+
+// The dense page table grows to at most this many pages.
+const DENSE_PAGES: usize = 1 << 12;
+
+// Flat memory grows to at most the range of the dense page
+// table. Growing past it makes memory paged.
+const FLAT_WORDS: usize = DENSE_PAGES << PAGE_BITS;
+
+type Page = [AtomicI64; PAGE_SIZE];
+
+// Stands in for pages that have never been written. It is
+// never stored to.
+static ZERO_PAGE: Page = [const { AtomicI64::new(0) }; PAGE_SIZE];
+
+// A new page holding the given words followed by zeros.
+fn new_page(words: &[i64]) -> Arc<Page> {
+    let word = |i| AtomicI64::new(words.get(i).cloned().unwrap_or(0));
+    Arc::new(std::array::from_fn(word))
+}
+
+// Store to a page, copying it first if it is shared. A page
+// not shared cannot be seen by anyone else while this has
+// it mutably.
+#[inline]
+fn store(page: &mut Arc<Page>, i: usize, val: i64) {
+    if Arc::strong_count(page) != 1 {
+        *page = copy_page(page);
+    }
+    page[i].store(val, Relaxed);
+}
+
+// A private copy of a page. Kept out of line, so that its
+// page-sized temporary does not weigh on every store.
+#[cold]
+#[inline(never)]
+fn copy_page(page: &Page) -> Arc<Page> {
+    let word = |i: usize| AtomicI64::new(page[i].load(Relaxed));
+    Arc::new(std::array::from_fn(word))
+}
+
+// A page's worth of memory, in either representation.
+#[derive(Clone, Copy)]
+enum PageRef<'a> {
+    Paged(&'a Page),
+    // Short at the end of memory.
+    Flat(&'a [i64]),
+}
+
+impl<'a> PageRef<'a> {
+    // The words of the page, with zeros past the end of
+    // memory.
+    fn words(self) -> impl Iterator<Item = i64> + 'a {
+        (0..PAGE_SIZE).map(move |i| match self {
+            PageRef::Paged(page) => page[i].load(Relaxed),
+            PageRef::Flat(words) => words.get(i).cloned().unwrap_or(0),
+        })
+    }
+
+    // True if two pages hold the same words.
+    fn same_words(self, other: PageRef) -> bool {
+        match (self, other) {
+            (PageRef::Paged(p), PageRef::Paged(q))
+                if std::ptr::eq(p, q) =>
+            {
+                true
+            }
+            (PageRef::Flat(p), PageRef::Flat(q))
+                if p.len() == q.len() =>
+            {
+                p == q
+            }
+            _ => self.words().eq(other.words()),
+        }
+    }
+}
+
+// Copy-on-write paged words.
+#[derive(Clone)]
+struct Pages {
+    dense: Vec<Arc<Page>>,
+    sparse: BTreeMap<usize, Arc<Page>>,
+    len: usize,
+}
+
+impl Pages {
+    #[inline]
+    fn get(&self, addr: usize) -> Option<i64> {
+        if addr >= self.len {
+            return None;
+        }
+        let index = addr >> PAGE_BITS;
+        let page = match self.dense.get(index) {
+            Some(page) => page,
+            None => self.page(index),
+        };
+        Some(page[addr & PAGE_MASK].load(Relaxed))
+    }
+
+    // Store to a dense page, returning false if the address
+    // is not in one.
+    #[inline]
+    fn set_dense(&mut self, addr: usize, val: i64) -> bool {
+        if addr >= self.len {
+            return false;
+        }
+        match self.dense.get_mut(addr >> PAGE_BITS) {
+            Some(page) => {
+                store(page, addr & PAGE_MASK, val);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn set(&mut self, addr: usize, val: i64) {
+        let index = addr >> PAGE_BITS;
+        let page = if index < self.dense.len() {
+            &mut self.dense[index]
+        } else {
+            self.sparse.entry(index).or_insert_with(|| new_page(&[]))
+        };
+        store(page, addr & PAGE_MASK, val);
+    }
+
+    fn grow(&mut self, len: usize) {
+        let npages = ((len - 1) >> PAGE_BITS) + 1;
+        let ndense = npages.min(DENSE_PAGES);
+        if ndense > self.dense.len() {
+            // All the new pages share a single zero page
+            // until written. Sparse pages now in the dense
+            // range move over.
+            let zero = new_page(&[]);
+            for index in self.dense.len()..ndense {
+                let page = self
+                    .sparse
+                    .remove(&index)
+                    .unwrap_or_else(|| zero.clone());
+                self.dense.push(page);
+            }
+        }
+        self.len = len;
+    }
+
+    fn truncate(&mut self, len: usize) {
+        // Clear the rest of the last page kept, since
+        // growing again must give zeros.
+        let end = match len & PAGE_MASK {
+            0 => len,
+            _ => self.len.min((len | PAGE_MASK) + 1),
+        };
+        for addr in len..end {
+            if self.get(addr) != Some(0) {
+                self.set(addr, 0);
+            }
+        }
+        let npages = (len + PAGE_MASK) >> PAGE_BITS;
+        self.dense.truncate(npages.min(DENSE_PAGES));
+        self.sparse.retain(|&index, _| index < npages);
+        self.len = len;
+    }
+
+    // The page with the given index.
+    fn page(&self, index: usize) -> &Page {
+        match self.dense.get(index) {
+            Some(page) => page,
+            None => self.sparse.get(&index).map_or(&ZERO_PAGE, |p| p),
+        }
+    }
+
+    // Pages in the page tables.
+    fn pages(&self) -> impl Iterator<Item = &Arc<Page>> {
+        self.dense.iter().chain(self.sparse.values())
+    }
+}
+
+impl From<&[i64]> for Pages {
+    fn from(words: &[i64]) -> Self {
+        let mut pages = Self {
+            dense: Vec::new(),
+            sparse: BTreeMap::new(),
+            len: 0,
+        };
+        if words.is_empty() {
+            return pages;
+        }
+        pages.grow(words.len());
+        for (index, chunk) in words.chunks(PAGE_SIZE).enumerate() {
+            let page = new_page(chunk);
+            if index < pages.dense.len() {
+                pages.dense[index] = page;
+            } else {
+                pages.sparse.insert(index, page);
+            }
+        }
+        pages
+    }
+}
+
+// The words of memory.
+#[derive(Clone)]
+enum Words {
+    Flat(Vec<i64>),
+    Paged(Pages),
+}
+
+/// Intcode memory, flat or paged. Behaves like a `Vec<i64>`
+/// that only grows.
+#[derive(Clone)]
+pub struct Memory {
+    words: Words,
+    big: BTreeMap<usize, BigInt>,
+}
+
+/// Memory footprint statistics, as returned by
+/// `Memory::stats()`. Flat memory has no pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryStats {
+    /// Number of words of memory.
+    pub len: usize,
+    /// Pages in the dense page table.
+    pub dense_pages: usize,
+    /// Pages allocated in the sparse page table.
+    pub sparse_pages: usize,
+    /// Pages not shared with any other machine.
+    pub private_pages: usize,
+    /// Approximate bytes used by the words, or by the page
+    /// tables and the private pages.
+    pub bytes: usize,
+}
+
+impl Memory {
+    /// Number of words of memory.
+    #[inline]
+    pub fn len(&self) -> usize {
+        match self.words {
+            Words::Flat(ref words) => words.len(),
+            Words::Paged(ref pages) => pages.len,
+        }
+    }
+
+    /// True if there is no memory at all.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// True if memory is paged.
+    pub fn is_paged(&self) -> bool {
+        matches!(self.words, Words::Paged(_))
+    }
+
+    /// Make memory paged, so that clones share its pages
+    /// until written.
+    pub fn make_paged(&mut self) {
+        let pages = match self.words {
+            Words::Flat(ref words) => Pages::from(words.as_slice()),
+            Words::Paged(_) => return,
+        };
+        self.words = Words::Paged(pages);
+    }
+
+    /// Value at the given address, or `None` if the address
+    /// is past the end of memory.
+    #[inline]
+    pub fn get(&self, addr: usize) -> Option<i64> {
+        match self.words {
+            Words::Flat(ref words) => words.get(addr).cloned(),
+            Words::Paged(ref pages) => pages.get(addr),
+        }
+    }
+
+    /// Store the given value at the given address.
+    ///
+    /// # Panics
+    /// Will panic if the address is past the end of memory.
+    #[inline]
+    pub fn set(&mut self, addr: usize, val: i64) {
+        // Fast path: flat memory or a dense page, with no big
+        // values to clear.
+        if self.big.is_empty() {
+            match self.words {
+                Words::Flat(ref mut words) => {
+                    if let Some(word) = words.get_mut(addr) {
+                        *word = val;
+                        return;
+                    }
+                }
+                Words::Paged(ref mut pages) => {
+                    if pages.set_dense(addr, val) {
+                        return;
+                    }
+                }
+            }
+        }
+        self.set_slow(addr, val);
+    }
+
+    // The general case of `set()`.
+    #[inline(never)]
+    fn set_slow(&mut self, addr: usize, val: i64) {
+        assert!(addr < self.len(), "memory store out of range");
+        match self.words {
+            Words::Flat(ref mut words) => words[addr] = val,
+            Words::Paged(ref mut pages) => pages.set(addr, val),
+        }
+        self.big.remove(&addr);
+    }
+
+    // The words of flat memory holding no big values, for
+    // instructions to run on directly.
+    #[inline]
+    pub(super) fn flat_mut(&mut self) -> Option<&mut [i64]> {
+        match self.words {
+            Words::Flat(ref mut words) if self.big.is_empty() => {
+                Some(words)
+            }
+            _ => None,
+        }
+    }
+
+    /// Full value at the given address, or `None` if the
+    /// address is past the end of memory.
+    pub fn get_big(&self, addr: usize) -> Option<BigInt> {
+        match self.big.get(&addr) {
+            Some(val) => Some(val.clone()),
+            None => self.get(addr).map(BigInt::from),
+        }
+    }
+
+    /// Store the given value at the given address, whether
+    /// or not it fits in an `i64`.
+    ///
+    /// # Panics
+    /// Will panic if the address is past the end of memory.
+    pub fn set_big(&mut self, addr: usize, val: BigInt) {
+        self.set(addr, val.low_i64());
+        if val.to_i64().is_none() {
+            self.big.insert(addr, val);
+        }
+    }
+
+    /// True if the value at the given address is too large
+    /// for an `i64`.
+    #[inline]
+    pub fn is_big(&self, addr: usize) -> bool {
+        !self.big.is_empty() && self.big.contains_key(&addr)
+    }
+
+    /// Iterate over the values too large for an `i64`, in
+    /// address order. `get()` returns just their low 64
+    /// bits.
+    pub fn big_values(&self) -> impl Iterator<Item = (usize, &BigInt)> {
+        self.big.iter().map(|(&addr, val)| (addr, val))
+    }
+
+    /// Grow memory with zeros to at least the given length.
+    /// Flat memory grown past the dense range becomes
+    /// paged.
+    #[inline]
+    pub fn resize(&mut self, len: usize) {
+        if len <= self.len() {
+            return;
+        }
+        self.grow(len);
+    }
+
+    // The general case of `resize()`.
+    #[inline(never)]
+    fn grow(&mut self, len: usize) {
+        if len > FLAT_WORDS {
+            self.make_paged();
+        }
+        match self.words {
+            Words::Flat(ref mut words) => words.resize(len, 0),
+            Words::Paged(ref mut pages) => pages.grow(len),
+        }
+    }
+
+    // Shrink memory to the given length, as if it had never
+    // grown past it.
+    pub(super) fn truncate(&mut self, len: usize) {
+        if len >= self.len() {
+            return;
+        }
+        match self.words {
+            Words::Flat(ref mut words) => words.truncate(len),
+            Words::Paged(ref mut pages) => pages.truncate(len),
+        }
+        self.big.retain(|&addr, _| addr < len);
+    }
+
+    /// Copy memory out into a vector.
+    pub fn to_vec(&self) -> Vec<i64> {
+        match self.words {
+            Words::Flat(ref words) => words.clone(),
+            Words::Paged(_) => self.iter().collect(),
+        }
+    }
+
+    /// Iterate over the words of memory. This visits every
+    /// word, written or not: see `chunks()` for a cheaper
+    /// way to look at sparse memory.
+    pub fn iter(&self) -> impl Iterator<Item = i64> + '_ {
+        let npages = (self.len() + PAGE_MASK) >> PAGE_BITS;
+        (0..npages)
+            .flat_map(move |index| self.page(index).words())
+            .take(self.len())
+    }
+
+    /// Iterate over the allocated parts of memory in
+    /// address order, as pairs of a starting address and
+    /// the words there. Memory outside these chunks is
+    /// zero.
+    pub fn chunks(
+        &self,
+    ) -> impl Iterator<Item = (usize, Vec<i64>)> + '_ {
+        let len = self.len();
+        self.indices().map(move |index| {
+            let base = index << PAGE_BITS;
+            let end = (len - base).min(PAGE_SIZE);
+            (base, self.page(index).words().take(end).collect())
+        })
+    }
+
+    /// Number of pages not shared with any other machine.
+    pub fn private_pages(&self) -> usize {
+        match self.words {
+            Words::Flat(_) => 0,
+            Words::Paged(ref pages) => pages
+                .pages()
+                .filter(|p| Arc::strong_count(p) == 1)
+                .count(),
+        }
+    }
+
+    /// Memory footprint statistics.
+    pub fn stats(&self) -> MemoryStats {
+        let pages = match self.words {
+            Words::Flat(ref words) => {
+                return MemoryStats {
+                    len: words.len(),
+                    bytes: words.capacity() * size_of::<i64>(),
+                    ..MemoryStats::default()
+                };
+            }
+            Words::Paged(ref pages) => pages,
+        };
+        let private_pages = self.private_pages();
+        let table_bytes = pages.dense.len() * size_of::<Arc<Page>>()
+            + pages.sparse.len()
+                * (size_of::<usize>() + size_of::<Arc<Page>>());
+        MemoryStats {
+            len: pages.len,
+            dense_pages: pages.dense.len(),
+            sparse_pages: pages.sparse.len(),
+            private_pages,
+            bytes: table_bytes + private_pages * size_of::<Page>(),
+        }
+    }
+
+    // Starting addresses and words of the pages that may
+    // differ between this memory and another, skipping
+    // pages the two share. Words past the end of memory
+    // are zero.
+    pub(super) fn differing_pages(
+        &self,
+        other: &Memory,
+    ) -> Vec<(usize, Vec<i64>, Vec<i64>)> {
+        let indices: BTreeSet<usize> =
+            self.indices().chain(other.indices()).collect();
+        indices
+            .into_iter()
+            .filter_map(|index| {
+                let (p, q) = (self.page(index), other.page(index));
+                if p.same_words(q) {
+                    return None;
+                }
+                let base = index << PAGE_BITS;
+                Some((base, p.words().collect(), q.words().collect()))
+            })
+            .collect()
+    }
+
+    // The page with the given index.
+    fn page(&self, index: usize) -> PageRef<'_> {
+        match self.words {
+            Words::Flat(ref words) => {
+                let base = (index << PAGE_BITS).min(words.len());
+                let end = (base + PAGE_SIZE).min(words.len());
+                PageRef::Flat(&words[base..end])
+            }
+            Words::Paged(ref pages) => {
+                PageRef::Paged(pages.page(index))
+            }
+        }
+    }
+
+    // Indices of the pages held, in order.
+    fn indices(&self) -> Box<dyn Iterator<Item = usize> + '_> {
+        match self.words {
+            Words::Flat(ref words) => {
+                Box::new(0..(words.len() + PAGE_MASK) >> PAGE_BITS)
+            }
+            Words::Paged(ref pages) => Box::new(
+                (0..pages.dense.len())
+                    .chain(pages.sparse.keys().cloned()),
+            ),
+        }
+    }
+}
+
+impl From<Vec<i64>> for Memory {
+    fn from(words: Vec<i64>) -> Self {
+        if words.len() > FLAT_WORDS {
+            return Self::from(words.as_slice());
+        }
+        Self {
+            words: Words::Flat(words),
+            big: BTreeMap::new(),
+        }
+    }
+}
+
+impl From<&[i64]> for Memory {
+    fn from(words: &[i64]) -> Self {
+        let words = if words.len() > FLAT_WORDS {
+            Words::Paged(Pages::from(words))
+        } else {
+            Words::Flat(words.to_vec())
+        };
+        Self {
+            words,
+            big: BTreeMap::new(),
+        }
+    }
+}
+
+impl PartialEq for Memory {
+    fn eq(&self, other: &Self) -> bool {
+        if let (Words::Flat(p), Words::Flat(q)) =
+            (&self.words, &other.words)
+        {
+            return p == q && self.big == other.big;
+        }
+        self.len() == other.len()
+            && self.big == other.big
+            && self.indices().chain(other.indices()).all(|index| {
+                self.page(index).same_words(other.page(index))
+            })
+    }
+}
+
+impl Eq for Memory {}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Memory({} words)", self.len())
+    }
+}
+
+impl Intcode {
+    /// Builder for giving the machine paged memory, so that
+    /// its clones and snapshots share memory until written.
+    /// See `Memory::make_paged()`.
+    pub fn with_paged_memory(mut self) -> Self {
+        self.prog.make_paged();
+        self
+    }
+}
+
+#[test]
+fn test_memory_cow() {
+    let words: Vec<i64> = (0..3000).collect();
+    let mut m1 = Memory::from(words.clone());
+    assert!(!m1.is_paged());
+    m1.make_paged();
+    assert!(m1.is_paged());
+    assert_eq!(m1.to_vec(), words);
+    assert_eq!(m1, Memory::from(words.clone()));
+    let mut m2 = m1.clone();
+    assert_eq!(m1.private_pages(), 0);
+    m2.set(1500, -1);
+    assert_eq!(m2.private_pages(), 1);
+    assert_eq!(m1.get(1500), Some(1500));
+    assert_eq!(m2.get(1500), Some(-1));
+    assert!(m1 != m2);
+
+    m1.resize(10_000);
+    assert_eq!(m1.len(), 10_000);
+    assert_eq!(m1.get(9999), Some(0));
+    assert_eq!(m1.get(10_000), None);
+    m1.set(9999, 7);
+    assert_eq!(m1.get(8000), Some(0));
+
+    // Snapshots of a machine with paged memory share it.
+    let ic = Intcode::new(words).with_paged_memory();
+    assert_eq!(ic.snapshot().memory().private_pages(), 0);
+}
+
+#[test]
+fn test_memory_sparse() {
+    let far = 1_000_000_000_000;
+    let mut m = Memory::from(vec![1, 2, 3]);
+    m.resize(far + 1);
+    assert!(m.is_paged());
+    assert_eq!(m.len(), far + 1);
+    m.set(far, 7);
+    m.set(far - 5000, 8);
+    assert_eq!(m.get(far), Some(7));
+    assert_eq!(m.get(far - 1), Some(0));
+    assert_eq!(m.get(2), Some(3));
+
+    let stats = m.stats();
+    assert_eq!(stats.dense_pages, DENSE_PAGES);
+    assert_eq!(stats.sparse_pages, 2);
+    assert!(stats.bytes < 1 << 20);
+
+    let chunks: Vec<usize> = m.chunks().map(|(a, _)| a).collect();
+    assert_eq!(chunks.len(), DENSE_PAGES + 2);
+    assert_eq!(chunks[DENSE_PAGES + 1], far & !PAGE_MASK);
+
+    let mut m2 = m.clone();
+    assert_eq!(m, m2);
+    m2.set(far, 9);
+    assert!(m != m2);
+    assert_eq!(m.get(far), Some(7));
+}
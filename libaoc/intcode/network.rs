@@ -0,0 +1,612 @@
+//! Networks of Intcode machines.
+//!
+//! A `Network` connects a number of machines according to a
+//! `Topology` and runs them until they all halt, until they
+//! are all stuck waiting for input, or until something goes
+//! wrong. Machines run in rounds: at the start of a round
+//! each machine that can make progress is given the inputs
+//! queued for it and run until it next needs input; its
+//! outputs are routed once every machine in the round has
+//! finished. Because of this, the `Cooperative` and
+//! `Threaded` schedules produce identical results.
+//!
+//! In an `Addressed` network, machines exchange packets
+//! whose first word is the destination address. Packets
+//! for addresses outside the network go to a `Monitor`,
+//! such as the `Nat`, which may also wake the network when
+//! it goes idle.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::{Intcode, NetStop, Network, Topology};
+//!
+//! // Add a phase to each of three inputs.
+//! let prog = aoc::assemble("
+//!         in [p]
+//! loop:   in [x]
+//!         add [x], [p], [x]
+//!         out [x]
+//!         add [n], #-1, [n]
+//!         jt [n], #loop
+//!         halt
+//! p:      .data 0
+//! x:      .data 0
+//! n:      .data 3
+//! ").unwrap();
+//! let machines = (1..=3)
+//!     .map(|p| Intcode::new(prog.clone()).with_inputs(vec![p]))
+//!     .collect();
+//! let mut net = Network::new(machines, Topology::Ring);
+//! net.add_input(0, 0);
+//! let report = net.run();
+//! assert_eq!(report.stop, NetStop::Halted);
+//! assert_eq!(report.first_halt, Some(0));
+//! assert_eq!(report.outputs, vec![6, 12, 18]);
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use super::{Intcode, IntcodeError, Terminus};
+
+/// How the machines of a `Network` are connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    /// Each machine's outputs are inputs to the next
+    /// machine. The last machine's outputs are the
+    /// network's outputs.
+    Pipeline,
+    /// As `Pipeline`, but the last machine's outputs are
+    /// also inputs to the first machine.
+    Ring,
+    /// Machines send packets of `packet_size` words, the
+    /// first of which is the destination address. Each
+    /// machine is given its own address as its first
+    /// input, and is given `idle_input` whenever it needs
+    /// input and has none queued.
+    Addressed { packet_size: usize, idle_input: i64 },
+}
+
+/// How the machines of a `Network` are run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Run the machines one after another on the calling
+    /// thread.
+    Cooperative,
+    /// Run each machine on its own thread.
+    Threaded,
+}
+
+/// A packet sent by a machine in an `Addressed` network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    /// Address of the sending machine.
+    pub src: usize,
+    /// Destination address.
+    pub dest: i64,
+    /// Packet contents after the destination address.
+    pub payload: Vec<i64>,
+}
+
+/// Observer for packets sent outside an `Addressed`
+/// network.
+pub trait Monitor {
+    /// Accept a packet sent to an address outside the
+    /// network. Return `false` to stop the network at the
+    /// end of the current round.
+    fn packet(&mut self, packet: &Packet) -> bool;
+
+    /// The network has gone idle. Return a destination
+    /// address and payload to wake it up, or `None` to stop
+    /// it.
+    fn idle(&mut self) -> Option<(usize, Vec<i64>)>;
+}
+
+/// Monitor that remembers the last packet sent outside the
+/// network and forwards its payload to machine 0 whenever
+/// the network goes idle. It stops the network rather than
+/// send the same payload twice in a row.
+#[derive(Debug, Clone, Default)]
+pub struct Nat {
+    last: Option<Vec<i64>>,
+    sent: Vec<Vec<i64>>,
+}
+
+impl Nat {
+    /// Make a new monitor with no packet remembered.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Payloads sent to machine 0 so far. If the network
+    /// was stopped for a repeat, the repeated payload is
+    /// included at the end.
+    pub fn sent(&self) -> &[Vec<i64>] {
+        &self.sent
+    }
+}
+
+impl Monitor for Nat {
+    fn packet(&mut self, packet: &Packet) -> bool {
+        self.last = Some(packet.payload.clone());
+        true
+    }
+
+    fn idle(&mut self) -> Option<(usize, Vec<i64>)> {
+        let payload = self.last.clone()?;
+        let repeat = self.sent.last() == Some(&payload);
+        self.sent.push(payload.clone());
+        if repeat {
+            None
+        } else {
+            Some((0, payload))
+        }
+    }
+}
+
+/// Reason a `Network` stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetStop {
+    /// Every machine halted.
+    Halted,
+    /// No machine can make progress, and the monitor (if
+    /// any) did not wake the network.
+    Idle,
+    /// The monitor asked for the network to stop.
+    Stopped,
+    /// The given machine ran out of fuel. It may be resumed
+    /// by adding fuel and running the network again.
+    OutOfFuel(usize),
+    /// The given machine faulted. It will not be run
+    /// again.
+    Fault(usize, IntcodeError),
+}
+
+/// Outcome of running a `Network`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetReport {
+    /// Why the network stopped.
+    pub stop: NetStop,
+    /// The first machine to halt, if any has. Ties within a
+    /// round go to the lowest-numbered machine.
+    pub first_halt: Option<usize>,
+    /// Network outputs produced by this run: the last
+    /// machine's outputs for `Pipeline` and `Ring`, or the
+    /// words of packets sent outside an unmonitored
+    /// `Addressed` network.
+    pub outputs: Vec<i64>,
+    /// Number of rounds run.
+    pub rounds: u64,
+}
+
+// A machine to be run in the current round.
+struct Job {
+    machine: usize,
+    // True if the only input is the idle input.
+    idle: bool,
+}
+
+// The result of running a machine: its outputs and why it
+// stopped.
+type RunResult = (Vec<i64>, Result<Terminus, IntcodeError>);
+
+// Routing state of a network, kept apart from the
+// machines so that they can be lent out to threads.
+#[derive(Debug, Clone)]
+struct Router {
+    topology: Topology,
+    queues: Vec<VecDeque<i64>>,
+    partial: Vec<Vec<i64>>,
+    // Machine has run and is waiting for input.
+    waiting: Vec<bool>,
+    // Machine has halted or faulted.
+    done: Vec<bool>,
+    first_halt: Option<usize>,
+    outputs: Vec<i64>,
+}
+
+/// A collection of Intcode machines connected together.
+#[derive(Debug, Clone)]
+pub struct Network {
+    machines: Vec<Intcode>,
+    schedule: Schedule,
+    router: Router,
+}
+
+impl Network {
+    /// Connect the given machines with the given topology.
+    /// Machines are addressed by their index.
+    pub fn new(machines: Vec<Intcode>, topology: Topology) -> Self {
+        let n = machines.len();
+        if let Topology::Addressed { packet_size, .. } = topology {
+            assert!(packet_size > 0, "empty network packets");
+        }
+        let mut queues = vec![VecDeque::new(); n];
+        if let Topology::Addressed { .. } = topology {
+            for (addr, queue) in queues.iter_mut().enumerate() {
+                queue.push_back(addr as i64);
+            }
+        }
+        let router = Router {
+            topology,
+            queues,
+            partial: vec![Vec::new(); n],
+            waiting: vec![false; n],
+            done: vec![false; n],
+            first_halt: None,
+            outputs: Vec::new(),
+        };
+        Self {
+            machines,
+            schedule: Schedule::Cooperative,
+            router,
+        }
+    }
+
+    /// Builder for choosing how the machines are run. The
+    /// default is `Schedule::Cooperative`.
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Queue an input for the given machine.
+    pub fn add_input(&mut self, machine: usize, input: i64) {
+        self.router.queues[machine].push_back(input);
+    }
+
+    /// The machines of the network.
+    pub fn machines(&self) -> &[Intcode] {
+        &self.machines
+    }
+
+    /// Mutable access to the machines of the network, for
+    /// example to refuel them.
+    pub fn machines_mut(&mut self) -> &mut [Intcode] {
+        &mut self.machines
+    }
+
+    /// Run the network until it stops. Packets sent outside
+    /// an `Addressed` network become network outputs.
+    pub fn run(&mut self) -> NetReport {
+        self.run_rounds(None)
+    }
+
+    /// Run the network until it stops, passing packets sent
+    /// outside an `Addressed` network to the given monitor.
+    pub fn run_monitored(
+        &mut self,
+        monitor: &mut dyn Monitor,
+    ) -> NetReport {
+        self.run_rounds(Some(monitor))
+    }
+
+    fn run_rounds<'m>(
+        &mut self,
+        mut monitor: Option<&mut (dyn Monitor + 'm)>,
+    ) -> NetReport {
+        let router = &mut self.router;
+        let machines = &mut self.machines;
+        let mut rounds = 0;
+        let stop = match self.schedule {
+            Schedule::Cooperative => loop {
+                let (jobs, inputs) = router.jobs();
+                let results = jobs
+                    .iter()
+                    .zip(inputs)
+                    .map(|(job, inputs)| {
+                        run_machine(&mut machines[job.machine], inputs)
+                    })
+                    .collect();
+                rounds += 1;
+                let monitor = monitor.as_deref_mut();
+                if let Some(stop) =
+                    router.deliver(jobs, results, monitor)
+                {
+                    break stop;
+                }
+            },
+            Schedule::Threaded => thread::scope(|scope| {
+                let (report_tx, report_rx) = channel();
+                let commands: Vec<_> = machines
+                    .iter_mut()
+                    .enumerate()
+                    .map(|(i, machine)| {
+                        let (command_tx, command_rx) = channel();
+                        let report_tx = report_tx.clone();
+                        scope.spawn(move || {
+                            for inputs in command_rx {
+                                let result =
+                                    run_machine(machine, inputs);
+                                if report_tx.send((i, result)).is_err()
+                                {
+                                    break;
+                                }
+                            }
+                        });
+                        command_tx
+                    })
+                    .collect();
+                drop(report_tx);
+                loop {
+                    let (jobs, inputs) = router.jobs();
+                    for (job, inputs) in jobs.iter().zip(inputs) {
+                        commands[job.machine]
+                            .send(inputs)
+                            .expect("network machine thread died");
+                    }
+                    let mut reports: Vec<(usize, RunResult)> = jobs
+                        .iter()
+                        .map(|_| {
+                            report_rx
+                                .recv()
+                                .expect("network machine thread died")
+                        })
+                        .collect();
+                    reports.sort_by_key(|&(i, _)| i);
+                    let results =
+                        reports.into_iter().map(|(_, r)| r).collect();
+                    rounds += 1;
+                    let monitor = monitor.as_deref_mut();
+                    if let Some(stop) =
+                        router.deliver(jobs, results, monitor)
+                    {
+                        break stop;
+                    }
+                }
+            }),
+        };
+        NetReport {
+            stop,
+            first_halt: router.first_halt,
+            outputs: router.outputs.drain(..).collect(),
+            rounds,
+        }
+    }
+}
+
+// Give a machine the given inputs and run it until it
+// needs more.
+fn run_machine(machine: &mut Intcode, inputs: Vec<i64>) -> RunResult {
+    for input in inputs {
+        machine.add_input(input);
+    }
+    let mut outputs = Vec::new();
+    let result = machine.try_run_io(&mut VecDeque::new(), &mut outputs);
+    (outputs, result)
+}
+
+impl Router {
+    // Choose the machines to run this round, in order, and
+    // take their inputs.
+    fn jobs(&mut self) -> (Vec<Job>, Vec<Vec<i64>>) {
+        let mut jobs = Vec::new();
+        let mut inputs = Vec::new();
+        for machine in 0..self.queues.len() {
+            if self.done[machine] {
+                continue;
+            }
+            let queued: Vec<i64> =
+                self.queues[machine].drain(..).collect();
+            let mut idle = false;
+            if queued.is_empty() && self.waiting[machine] {
+                match self.topology {
+                    Topology::Addressed { idle_input, .. } => {
+                        idle = true;
+                        inputs.push(vec![idle_input]);
+                    }
+                    _ => continue,
+                }
+            } else {
+                inputs.push(queued);
+            }
+            jobs.push(Job { machine, idle });
+        }
+        (jobs, inputs)
+    }
+
+    // Route the outputs of a round and record how each
+    // machine stopped. Return the reason the network
+    // stopped, if it did.
+    fn deliver<'m>(
+        &mut self,
+        jobs: Vec<Job>,
+        results: Vec<RunResult>,
+        mut monitor: Option<&mut (dyn Monitor + 'm)>,
+    ) -> Option<NetStop> {
+        let mut stop = None;
+        let mut active = false;
+        for (job, (outputs, result)) in jobs.into_iter().zip(results) {
+            let machine = job.machine;
+            active |= !job.idle || !outputs.is_empty();
+            if !self.route(machine, outputs, monitor.as_deref_mut()) {
+                stop.get_or_insert(NetStop::Stopped);
+            }
+            match result {
+                Ok(Terminus::NeedInput) => self.waiting[machine] = true,
+                Ok(Terminus::Halted) => {
+                    self.done[machine] = true;
+                    self.first_halt.get_or_insert(machine);
+                }
+                Ok(Terminus::OutOfFuel) => {
+                    self.waiting[machine] = false;
+                    stop.get_or_insert(NetStop::OutOfFuel(machine));
+                }
+                Ok(Terminus::HaveOutput(_)) => {
+                    unreachable!("network machine stopped for output")
+                }
+                Err(e) => {
+                    self.done[machine] = true;
+                    stop.get_or_insert(NetStop::Fault(machine, e));
+                }
+            }
+        }
+        if stop.is_some() {
+            return stop;
+        }
+        if self.done.iter().all(|&d| d) {
+            return Some(NetStop::Halted);
+        }
+        if active {
+            return None;
+        }
+        match monitor.and_then(|m| m.idle()) {
+            Some((dest, payload)) if dest < self.queues.len() => {
+                self.queues[dest].extend(payload);
+                None
+            }
+            _ => Some(NetStop::Idle),
+        }
+    }
+
+    // Route the outputs of the given machine. Return false
+    // if the monitor asked to stop.
+    fn route<'m>(
+        &mut self,
+        machine: usize,
+        outputs: Vec<i64>,
+        mut monitor: Option<&mut (dyn Monitor + 'm)>,
+    ) -> bool {
+        let n = self.queues.len();
+        let packet_size = match self.topology {
+            Topology::Pipeline | Topology::Ring if machine + 1 < n => {
+                self.queues[machine + 1].extend(outputs);
+                return true;
+            }
+            Topology::Pipeline => {
+                self.outputs.extend(outputs);
+                return true;
+            }
+            Topology::Ring => {
+                self.queues[0].extend(outputs.iter().cloned());
+                self.outputs.extend(outputs);
+                return true;
+            }
+            Topology::Addressed { packet_size, .. } => packet_size,
+        };
+        let partial = &mut self.partial[machine];
+        partial.extend(outputs);
+        let mut keep_going = true;
+        while partial.len() >= packet_size {
+            let mut payload: Vec<i64> =
+                partial.drain(..packet_size).collect();
+            let dest = payload.remove(0);
+            if dest >= 0 && (dest as usize) < n {
+                self.queues[dest as usize].extend(payload);
+                continue;
+            }
+            match monitor.as_deref_mut() {
+                Some(monitor) => {
+                    let packet = Packet {
+                        src: machine,
+                        dest,
+                        payload,
+                    };
+                    keep_going &= monitor.packet(&packet);
+                }
+                None => {
+                    self.outputs.push(dest);
+                    self.outputs.extend(payload);
+                }
+            }
+        }
+        keep_going
+    }
+}
+
+#[test]
+fn test_network() {
+    // Add one to each value received and pass it along
+    // the ring of three machines, sending values of 10 or
+    // more outside the network instead.
+    let prog = super::assemble(
+        "
+                in [addr]
+        loop:   in [v]
+                eq [v], #-1, [t]
+                jt [t], #loop
+                lt [v], #10, [t]
+                jf [t], #nat
+                add [v], #1, [v]
+                add [addr], #1, [dest]
+                eq [dest], #3, [t]
+                jf [t], #send
+                add #0, #0, [dest]
+        send:   out [dest]
+                out [v]
+                jt #1, #loop
+        nat:    out #255
+                out [v]
+                jt #1, #loop
+        addr:   .data 0
+        v:      .data 0
+        t:      .data 0
+        dest:   .data 0
+        ",
+    )
+    .unwrap();
+    let topology = Topology::Addressed {
+        packet_size: 2,
+        idle_input: -1,
+    };
+    let network = |schedule| {
+        let machines = vec![Intcode::new(prog.clone()); 3];
+        let mut net =
+            Network::new(machines, topology).with_schedule(schedule);
+        net.add_input(0, 0);
+        net
+    };
+
+    let mut reports = Vec::new();
+    for &schedule in &[Schedule::Cooperative, Schedule::Threaded] {
+        let report = network(schedule).run();
+        assert_eq!(report.stop, NetStop::Idle);
+        assert_eq!(report.outputs, vec![255, 10]);
+        assert_eq!(report.first_halt, None);
+
+        let mut nat = Nat::new();
+        let nat_report = network(schedule).run_monitored(&mut nat);
+        assert_eq!(nat_report.stop, NetStop::Idle);
+        assert_eq!(nat.sent(), &[vec![10], vec![10]]);
+        reports.push((report, nat_report));
+    }
+    assert_eq!(reports[0], reports[1]);
+
+    // A pipeline of adders, starved of input after the
+    // first value.
+    let adder = super::assemble(
+        "
+                in [p]
+        loop:   in [x]
+                add [x], [p], [x]
+                out [x]
+                jt #1, #loop
+        p:      .data 0
+        x:      .data 0
+        ",
+    )
+    .unwrap();
+    let machines = (1..=3)
+        .map(|p| Intcode::new(adder.clone()).with_inputs(vec![p]))
+        .collect();
+    let mut net = Network::new(machines, Topology::Pipeline)
+        .with_schedule(Schedule::Threaded);
+    net.add_input(0, 0);
+    let report = net.run();
+    assert_eq!(report.stop, NetStop::Idle);
+    assert_eq!(report.outputs, vec![6]);
+    net.add_input(0, 10);
+    assert_eq!(net.run().outputs, vec![16]);
+
+    // A fault stops the network.
+    let machines =
+        vec![Intcode::new(adder), Intcode::new(vec![3, 0, 42])];
+    let mut net = Network::new(machines, Topology::Ring);
+    net.add_input(1, 1);
+    match net.run().stop {
+        NetStop::Fault(1, e) => assert_eq!(e.ip, 2),
+        stop => panic!("unexpected stop {:?}", stop),
+    }
+}
@@ -0,0 +1,845 @@
+//! Peephole optimization of Intcode programs.
+//!
+//! `optimize()` rewrites instructions in place, without
+//! moving anything, so that addresses baked into the
+//! program stay valid. It threads jumps through chains of
+//! jumps and instructions that do nothing, replaces jumps
+//! to `halt` with `halt`, and replaces runs of instructions
+//! that do nothing, such as `add [x], #0, [x]`, `rbo #0` or
+//! a branch that is never taken, with a jump past them.
+//!
+//! Since Intcode programs may modify themselves, an
+//! instruction is only rewritten if it is proven never to
+//! be written, nor read as data, by the program. Stores
+//! and loads in position mode have fixed addresses. For
+//! relative mode, the analysis follows the relative base
+//! through the control-flow graph built by `analyze()`,
+//! which requires that the program keeps its stack frames
+//! in the disciplined way compiled code does: every
+//! subroutine moves the relative base up by constant
+//! amounts and back before returning, stores only within
+//! its own frame, and is called with its return address at
+//! `rb+0`. Programs that break these rules, or that jump to
+//! computed addresses other than returns, or that modify
+//! code in ways that change its control flow, are left
+//! alone, with the reason reported.
+//!
+//! Optimized programs have the same input and output
+//! behavior as the originals, but typically execute fewer
+//! instructions, and their memory may differ in the words
+//! of rewritten instructions. `validate()` checks this by
+//! running both on the same inputs, such as those recorded
+//! by a journaled run with `Journal::input_log()`.
+//!
+//! # Examples
+//!
+//! ```rust
+//! let prog = aoc::assemble("
+//!         in [x]
+//!         jt #1, #skip
+//! skip:   jt #1, #out
+//! out:    out [x]
+//!         halt
+//! x:      .data 0
+//! ").unwrap();
+//! let opt = aoc::optimize(&prog);
+//! assert_eq!(opt.refused, None);
+//! assert_eq!(opt.changes.len(), 1);
+//! assert_eq!(
+//!     opt.changes[0].to_string(),
+//!     "2: jt #1, #5 -> jt #1, #8 (threaded jump)",
+//! );
+//! let v = aoc::validate(&prog, &opt.prog, &[7], 1000).unwrap();
+//! assert_eq!(v.outputs, vec![7]);
+//! assert_eq!(v.executed, (4, 3));
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::{
+    analyze, decode, BlockEnd, Cfg, EdgeKind, Fault, Insn, Intcode,
+    Opcode, Operand, Terminus,
+};
+
+/// Kind of rewrite made by `optimize()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rewrite {
+    /// A jump or branch retargeted past jumps and
+    /// instructions that do nothing.
+    Thread,
+    /// A jump leading to `halt` replaced by `halt`.
+    JumpToHalt,
+    /// An instruction that does nothing replaced by a jump
+    /// past it and those after it, or by `halt`.
+    SkipNoOps,
+}
+
+impl fmt::Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = match self {
+            Rewrite::Thread => "threaded jump",
+            Rewrite::JumpToHalt => "jump to halt",
+            Rewrite::SkipNoOps => "skipped no-ops",
+        };
+        write!(f, "{}", desc)
+    }
+}
+
+/// An instruction rewritten by `optimize()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub addr: usize,
+    pub kind: Rewrite,
+    pub before: Insn,
+    pub after: Insn,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {} ({})",
+            self.addr, self.before, self.after, self.kind
+        )
+    }
+}
+
+/// Result of `optimize()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Optimized {
+    /// The optimized program.
+    pub prog: Vec<i64>,
+    /// The rewrites made, in address order.
+    pub changes: Vec<Change>,
+    /// Why the program could not be proven safe to
+    /// optimize, if it couldn't, in which case it is
+    /// unchanged.
+    pub refused: Option<String>,
+}
+
+// What the program may do to memory.
+#[derive(Debug, Default)]
+struct Facts {
+    // Addresses stored to or loaded from in position mode.
+    written: BTreeSet<usize>,
+    read: BTreeSet<usize>,
+    // Lowest addresses stored to or loaded from in
+    // relative mode, above which anything may be accessed.
+    write_floor: Option<i64>,
+    read_floor: Option<i64>,
+    // Lowest relative base with which each instruction can
+    // run.
+    rb_floor: BTreeMap<usize, i64>,
+}
+
+impl Facts {
+    fn written(&self, addr: usize) -> bool {
+        self.written.contains(&addr)
+            || self.write_floor.is_some_and(|f| addr as i64 >= f)
+    }
+
+    fn read(&self, addr: usize) -> bool {
+        self.read.contains(&addr)
+            || self.read_floor.is_some_and(|f| addr as i64 >= f)
+    }
+}
+
+// Offsets of the relative base from its value on entry to
+// a frame, before each instruction run in it, and the
+// calls made from it with their offsets.
+type FrameDeltas = (BTreeMap<usize, i64>, Vec<(usize, i64)>);
+
+// Follow the relative base through the frame entered at
+// the given address: the whole program for the entry at
+// 0, or a subroutine.
+fn frame_deltas(
+    cfg: &Cfg,
+    entry: usize,
+    main: bool,
+) -> Result<FrameDeltas, String> {
+    let mut at = BTreeMap::new();
+    let mut deltas = BTreeMap::new();
+    let mut calls = Vec::new();
+    at.insert(entry, 0i64);
+    let mut work = vec![entry];
+    while let Some(start) = work.pop() {
+        let block = match cfg.block(start) {
+            Some(block) => block,
+            None => continue,
+        };
+        let mut d = at[&start];
+        for (addr, insn) in &block.insns {
+            deltas.insert(*addr, d);
+            let store = insn.op.store_opnd().map(|i| insn.opnds[i]);
+            if let Some(Operand::Rel(off)) = store {
+                if !main && d.checked_add(off).is_none_or(|a| a < 1) {
+                    return Err(format!(
+                        "store at {} may clobber a return address",
+                        addr
+                    ));
+                }
+            }
+            if insn.op == Opcode::RBO {
+                d = match insn.opnds[0] {
+                    Operand::Imm(n) => {
+                        d.checked_add(n).ok_or_else(|| {
+                            format!(
+                                "relative base overflows at {}",
+                                addr
+                            )
+                        })?
+                    }
+                    _ => {
+                        return Err(format!(
+                            "relative base set from memory at {}",
+                            addr
+                        ))
+                    }
+                };
+            }
+        }
+        let last = block.insns.last().map(|(addr, insn)| (*addr, insn));
+        match (block.end, last) {
+            (BlockEnd::Indirect, Some((addr, _))) => {
+                return Err(format!("computed jump at {}", addr));
+            }
+            (BlockEnd::Return, Some((addr, _))) if main => {
+                return Err(format!(
+                    "return outside a subroutine at {}",
+                    addr
+                ));
+            }
+            (BlockEnd::Return, Some((addr, insn)))
+                if d != 0 || insn.opnds[1] != Operand::Rel(0) =>
+            {
+                return Err(format!(
+                    "return from an unbalanced frame at {}",
+                    addr
+                ));
+            }
+            (BlockEnd::Call { target, ret }, Some((addr, _))) => {
+                if d < 1 {
+                    return Err(format!(
+                        "call at {} overlaps the caller's frame",
+                        addr
+                    ));
+                }
+                if !stores_return(block.insns.iter(), &deltas, d, ret) {
+                    return Err(format!(
+                        "return address of call at {} not stored",
+                        addr
+                    ));
+                }
+                calls.push((target, d));
+            }
+            _ => (),
+        }
+        for edge in block.succs() {
+            if edge.kind == EdgeKind::Call {
+                continue;
+            }
+            match at.get(&edge.to) {
+                Some(&e) if e != d => {
+                    return Err(format!(
+                        "relative base differs on paths to {}",
+                        edge.to
+                    ));
+                }
+                Some(_) => (),
+                None => {
+                    at.insert(edge.to, d);
+                    work.push(edge.to);
+                }
+            }
+        }
+    }
+    Ok((deltas, calls))
+}
+
+// True if the last store among the given instructions to
+// the slot at the given offset from the frame entry stores
+// the given return address.
+fn stores_return<'a, I>(
+    insns: I,
+    deltas: &BTreeMap<usize, i64>,
+    slot: i64,
+    ret: usize,
+) -> bool
+where
+    I: DoubleEndedIterator<Item = &'a (usize, Insn)>,
+{
+    for (addr, insn) in insns.rev() {
+        let off = match insn.op.store_opnd().map(|i| insn.opnds[i]) {
+            Some(Operand::Rel(off)) => off,
+            _ => continue,
+        };
+        if deltas[addr].checked_add(off) != Some(slot) {
+            continue;
+        }
+        let val = match (insn.op, &insn.opnds[..]) {
+            (Opcode::Add, &[Operand::Imm(a), Operand::Imm(b), _]) => {
+                a.checked_add(b)
+            }
+            (Opcode::Mul, &[Operand::Imm(a), Operand::Imm(b), _]) => {
+                a.checked_mul(b)
+            }
+            _ => None,
+        };
+        return val == Some(ret as i64);
+    }
+    false
+}
+
+// Work out what the program may do to memory, or why that
+// can't be known.
+fn prove(prog: &[i64], cfg: &Cfg) -> Result<Facts, String> {
+    let mut entries = vec![(0, true)];
+    entries.extend(cfg.subroutines().iter().map(|s| (s.entry, false)));
+    let frames = entries
+        .iter()
+        .map(|&(entry, main)| frame_deltas(cfg, entry, main))
+        .collect::<Result<Vec<_>, _>>()?;
+    let index: BTreeMap<usize, usize> = entries
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, &(entry, _))| (entry, i))
+        .collect();
+
+    // Find the lowest relative base on entry to each frame.
+    // Calls always move it up, so this settles.
+    let mut lo: Vec<Option<i64>> = vec![None; entries.len()];
+    lo[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for f in 0..frames.len() {
+            let base = match lo[f] {
+                Some(base) => base,
+                None => continue,
+            };
+            for &(target, d) in &frames[f].1 {
+                let i = index[&target];
+                let rb = base
+                    .checked_add(d)
+                    .ok_or("relative base overflows")?;
+                if lo[i].is_none_or(|l| rb < l) {
+                    lo[i] = Some(rb);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    let mut facts = Facts::default();
+    let floor = |f: &mut Option<i64>, a: i64| {
+        *f = Some(f.map_or(a, |f| f.min(a)));
+    };
+    for (f, (deltas, _)) in frames.iter().enumerate() {
+        let base = match lo[f] {
+            Some(base) => base,
+            None => continue,
+        };
+        for (&addr, &d) in deltas {
+            let rb = base.saturating_add(d);
+            let rb_floor = facts.rb_floor.entry(addr).or_insert(rb);
+            *rb_floor = rb.min(*rb_floor);
+            let insn = decode(prog, addr).expect("code decodes");
+            let store = insn.op.store_opnd();
+            for (i, opnd) in insn.opnds.iter().enumerate() {
+                let is_store = store == Some(i);
+                match *opnd {
+                    Operand::Pos(a) if a >= 0 && is_store => {
+                        facts.written.insert(a as usize);
+                    }
+                    Operand::Pos(a) if a >= 0 => {
+                        facts.read.insert(a as usize);
+                    }
+                    Operand::Rel(off) if is_store => floor(
+                        &mut facts.write_floor,
+                        rb.saturating_add(off),
+                    ),
+                    Operand::Rel(off) => floor(
+                        &mut facts.read_floor,
+                        rb.saturating_add(off),
+                    ),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    // Position-mode stores must leave the stack alone.
+    let stack = lo[1..].iter().flatten().min();
+    if let Some(&stack) = stack {
+        if let Some(&a) =
+            facts.written.range(stack.max(0) as usize..).next()
+        {
+            return Err(format!(
+                "store to {} may clobber the stack",
+                a
+            ));
+        }
+    }
+
+    // Code may only be modified where that can't change
+    // what it does to control flow or memory. A load whose
+    // address is modified may read anything.
+    for block in cfg.blocks() {
+        let mut end = block.start;
+        for (addr, insn) in &block.insns {
+            end = addr + insn.size();
+            let fixed = matches!(
+                insn.op,
+                Opcode::JumpIfTrue | Opcode::JumpIfFalse | Opcode::RBO
+            );
+            for w in 0..insn.size() {
+                let structural = w == 0
+                    || fixed
+                    || insn.op.store_opnd() == Some(w - 1);
+                if !facts.written(addr + w) {
+                    continue;
+                }
+                if structural {
+                    return Err(format!(
+                        "instruction at {} may be modified",
+                        addr
+                    ));
+                }
+                if !matches!(insn.opnds[w - 1], Operand::Imm(_)) {
+                    facts.read_floor = Some(0);
+                }
+            }
+        }
+        if block.end == BlockEnd::Invalid && facts.written(end) {
+            return Err(format!("code at {} may be written", end));
+        }
+    }
+    Ok(facts)
+}
+
+// The rewriter, with what is known about the program.
+struct Peephole<'a> {
+    prog: &'a [i64],
+    cfg: &'a Cfg,
+    facts: Facts,
+    // Number of instructions covering each word of code.
+    cover: BTreeMap<usize, usize>,
+}
+
+impl Peephole<'_> {
+    // The instruction found by the analysis starting at
+    // the given address, if it is never modified.
+    fn insn(&self, addr: usize) -> Option<&Insn> {
+        let block = self.cfg.block_containing(addr)?;
+        let (_, insn) = block.insns.iter().find(|(a, _)| *a == addr)?;
+        if (addr..addr + insn.size()).any(|a| self.facts.written(a)) {
+            return None;
+        }
+        Some(insn)
+    }
+
+    // True if the instruction at the given address may be
+    // rewritten: it is never written or read as data, and
+    // shares no words with other instructions.
+    fn clean(&self, addr: usize, insn: &Insn) -> bool {
+        (addr..addr + insn.size()).all(|a| {
+            !self.facts.written(a)
+                && !self.facts.read(a)
+                && self.cover[&a] == 1
+        })
+    }
+
+    // If the instruction at the given address does nothing,
+    // the address after it.
+    fn noop(&self, addr: usize) -> Option<usize> {
+        use self::Operand::*;
+
+        let insn = self.insn(addr)?;
+        let next = addr + insn.size();
+        // Loading the operand can't fault.
+        let safe = |opnd: Operand| match opnd {
+            Imm(_) => true,
+            Pos(a) => a >= 0,
+            Rel(off) => self
+                .facts
+                .rb_floor
+                .get(&addr)
+                .is_some_and(|rb| rb.saturating_add(off) >= 0),
+        };
+        let noop = match (insn.op, &insn.opnds[..]) {
+            (Opcode::JumpIfTrue, &[c, t])
+            | (Opcode::JumpIfFalse, &[c, t]) => {
+                let never = match c {
+                    Imm(v) => {
+                        (v != 0) != (insn.op == Opcode::JumpIfTrue)
+                    }
+                    _ => false,
+                };
+                safe(c) && safe(t) && (never || t == Imm(next as i64))
+            }
+            (Opcode::Add, &[x, Imm(0), y])
+            | (Opcode::Add, &[Imm(0), x, y])
+            | (Opcode::Mul, &[x, Imm(1), y])
+            | (Opcode::Mul, &[Imm(1), x, y]) => {
+                x == y && x != Imm(0) && safe(x)
+            }
+            (Opcode::RBO, &[Imm(0)]) => true,
+            _ => false,
+        };
+        if noop {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    // If the instruction at the given address is an
+    // unconditional jump, its target.
+    fn jump(&self, addr: usize) -> Option<usize> {
+        use self::Operand::*;
+
+        let insn = self.insn(addr)?;
+        match (insn.op, &insn.opnds[..]) {
+            (Opcode::JumpIfTrue, &[Imm(c), Imm(t)])
+                if c != 0 && t >= 0 =>
+            {
+                Some(t as usize)
+            }
+            (Opcode::JumpIfFalse, &[Imm(0), Imm(t)]) if t >= 0 => {
+                Some(t as usize)
+            }
+            _ => None,
+        }
+    }
+
+    fn is_halt(&self, addr: usize) -> bool {
+        self.insn(addr).is_some_and(|insn| insn.op == Opcode::Halt)
+    }
+
+    // Follow jumps and instructions that do nothing from the
+    // given address, returning where they lead and how many
+    // were followed. Loops are left alone.
+    fn thread(&self, start: usize) -> (usize, usize) {
+        let mut seen = BTreeSet::new();
+        let mut addr = start;
+        let mut steps = 0;
+        loop {
+            if !seen.insert(addr) {
+                return (start, 0);
+            }
+            match self.noop(addr).or_else(|| self.jump(addr)) {
+                Some(next) => addr = next,
+                None => return (addr, steps),
+            }
+            steps += 1;
+        }
+    }
+
+    // Rewrite the given instruction if possible, returning
+    // the kind of rewrite and the new words.
+    fn rewrite(
+        &self,
+        addr: usize,
+        insn: &Insn,
+    ) -> Option<(Rewrite, Vec<i64>)> {
+        if !self.clean(addr, insn) {
+            return None;
+        }
+        let mut words = self.prog[addr..addr + insn.size()].to_vec();
+        if self.jump(addr).is_some() {
+            let (dest, steps) = self.thread(addr);
+            if self.is_halt(dest) {
+                words[0] = 99;
+                return Some((Rewrite::JumpToHalt, words));
+            }
+            if steps < 2 {
+                return None;
+            }
+            words[2] = dest as i64;
+            return Some((Rewrite::Thread, words));
+        }
+        if self.noop(addr).is_some() {
+            let (dest, steps) = self.thread(addr);
+            if steps >= 1 && self.is_halt(dest) {
+                words[0] = 99;
+            } else if steps >= 2 && words.len() >= 3 {
+                words[..3].copy_from_slice(&[1105, 1, dest as i64]);
+            } else {
+                return None;
+            }
+            return Some((Rewrite::SkipNoOps, words));
+        }
+        match (insn.op, insn.opnds.get(1)) {
+            (Opcode::JumpIfTrue, Some(&Operand::Imm(t)))
+            | (Opcode::JumpIfFalse, Some(&Operand::Imm(t)))
+                if t >= 0 =>
+            {
+                let (dest, steps) = self.thread(t as usize);
+                if steps < 1 {
+                    return None;
+                }
+                words[2] = dest as i64;
+                Some((Rewrite::Thread, words))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Optimize the given program. See the module
+/// documentation for what is rewritten and when.
+pub fn optimize(prog: &[i64]) -> Optimized {
+    let cfg = analyze(prog);
+    let facts = match prove(prog, &cfg) {
+        Ok(facts) => facts,
+        Err(reason) => {
+            return Optimized {
+                prog: prog.to_vec(),
+                changes: Vec::new(),
+                refused: Some(reason),
+            }
+        }
+    };
+    let mut cover = BTreeMap::new();
+    for block in cfg.blocks() {
+        for (addr, insn) in &block.insns {
+            for a in *addr..addr + insn.size() {
+                *cover.entry(a).or_insert(0) += 1;
+            }
+        }
+    }
+    let peephole = Peephole {
+        prog,
+        cfg: &cfg,
+        facts,
+        cover,
+    };
+
+    // Rewrites are all worked out from the original
+    // program, which they all leave behaving the same.
+    let mut result = prog.to_vec();
+    let mut changes = Vec::new();
+    for block in cfg.blocks() {
+        for (addr, insn) in &block.insns {
+            let (kind, words) = match peephole.rewrite(*addr, insn) {
+                Some(rewrite) => rewrite,
+                None => continue,
+            };
+            result[*addr..addr + words.len()].copy_from_slice(&words);
+            changes.push(Change {
+                addr: *addr,
+                kind,
+                before: insn.clone(),
+                after: decode(&words, 0).expect("rewrite decodes"),
+            });
+        }
+    }
+    Optimized {
+        prog: result,
+        changes,
+        refused: None,
+    }
+}
+
+/// Result of a successful `validate()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validation {
+    /// The outputs of both programs.
+    pub outputs: Vec<i64>,
+    /// How the original program stopped: `Ok` with a
+    /// `Terminus` other than `HaveOutput`, or `Err` with the
+    /// fault.
+    pub end: Result<Terminus, Fault>,
+    /// Instructions executed by the original and the
+    /// optimized program.
+    pub executed: (u64, u64),
+}
+
+// Run the given program until it stops for anything but
+// output.
+fn run_outputs(
+    prog: &[i64],
+    inputs: &[i64],
+    fuel: u64,
+) -> (Vec<i64>, Result<Terminus, Fault>, u64) {
+    let mut ic = Intcode::new(prog.to_vec())
+        .with_inputs(inputs.to_vec())
+        .with_fuel(fuel);
+    let mut outputs = Vec::new();
+    loop {
+        match ic.try_run() {
+            Ok(Terminus::HaveOutput(v)) => outputs.push(v),
+            Ok(t) => return (outputs, Ok(t), ic.executed()),
+            Err(e) => return (outputs, Err(e.fault), ic.executed()),
+        }
+    }
+}
+
+/// Check an optimized program against the original by
+/// running both with the given inputs, giving each at most
+/// the given number of instructions. They must produce the
+/// same outputs and stop the same way, except that if the
+/// original runs out of fuel, the optimized program need
+/// only have produced at least the same outputs. Returns a
+/// description of the first difference found otherwise.
+pub fn validate(
+    original: &[i64],
+    optimized: &[i64],
+    inputs: &[i64],
+    fuel: u64,
+) -> Result<Validation, String> {
+    let (outputs, end, executed) = run_outputs(original, inputs, fuel);
+    let (opt_outputs, opt_end, opt_executed) =
+        run_outputs(optimized, inputs, fuel);
+    let n = outputs.len().min(opt_outputs.len());
+    if let Some(i) = (0..n).find(|&i| outputs[i] != opt_outputs[i]) {
+        return Err(format!(
+            "output {} differs: {} originally, {} optimized",
+            i, outputs[i], opt_outputs[i]
+        ));
+    }
+    if end != Ok(Terminus::OutOfFuel) {
+        if outputs.len() != opt_outputs.len() {
+            return Err(format!(
+                "{} outputs originally, {} optimized",
+                outputs.len(),
+                opt_outputs.len()
+            ));
+        }
+        if end != opt_end {
+            return Err(format!(
+                "stopped with {:?} originally, {:?} optimized",
+                end, opt_end
+            ));
+        }
+    } else if opt_outputs.len() < outputs.len() {
+        return Err(format!(
+            "{} outputs originally, {} optimized",
+            outputs.len(),
+            opt_outputs.len()
+        ));
+    }
+    Ok(Validation {
+        outputs,
+        end,
+        executed: (executed, opt_executed),
+    })
+}
+
+#[test]
+fn test_optimize() {
+    use super::{assemble, compile};
+
+    let prog = assemble(
+        "
+                jt #1, #a
+        a:      jt #1, #b
+        b:      add [x], #0, [x]
+                rbo #0
+        loop:   in [x]
+                jf [x], #done
+                out [x]
+                jt #1, #b
+        done:   jt #1, #end
+        end:    halt
+        x:      .data 0
+        ",
+    )
+    .unwrap();
+    let opt = optimize(&prog);
+    assert_eq!(opt.refused, None);
+    let changes: Vec<String> =
+        opt.changes.iter().map(|c| c.to_string()).collect();
+    assert_eq!(
+        changes,
+        vec![
+            "0: jt #1, #3 -> jt #1, #12 (threaded jump)",
+            "3: jt #1, #6 -> jt #1, #12 (threaded jump)",
+            "6: add [26], #0, [26] -> jt #1, #12 (skipped no-ops)",
+            "14: jf [26], #22 -> jf [26], #25 (threaded jump)",
+            "19: jt #1, #6 -> jt #1, #12 (threaded jump)",
+            "22: jt #1, #25 -> halt (jump to halt)",
+        ]
+    );
+    let v = validate(&prog, &opt.prog, &[5, 3, 0], 1000).unwrap();
+    assert_eq!(v.outputs, vec![5, 3]);
+    assert_eq!(v.end, Ok(Terminus::Halted));
+    assert_eq!(v.executed, (19, 11));
+    let v = validate(&prog, &opt.prog, &[5], 1000).unwrap();
+    assert_eq!(v.end, Ok(Terminus::NeedInput));
+    let v = validate(&prog, &opt.prog, &[5, 5, 5, 5], 9).unwrap();
+    assert_eq!(v.end, Ok(Terminus::OutOfFuel));
+    assert_eq!(v.outputs, vec![5]);
+    let mut bad = opt.prog.clone();
+    bad[17] = 104;
+    let err = validate(&prog, &bad, &[5, 0], 100).unwrap_err();
+    assert_eq!(err, "output 0 differs: 5 originally, 26 optimized");
+
+    // Code modified only in its data operands is left
+    // alone, but the rest can still be optimized.
+    let prog = assemble(
+        "
+                in [k+1]
+                jt #1, #j
+        j:      jt #1, #k
+        k:      out #0
+                halt
+        ",
+    )
+    .unwrap();
+    let opt = optimize(&prog);
+    assert_eq!(opt.refused, None);
+    assert_eq!(opt.changes.len(), 1);
+    assert_eq!(opt.changes[0].addr, 2);
+    let v = validate(&prog, &opt.prog, &[42], 1000).unwrap();
+    assert_eq!(v.outputs, vec![42]);
+    let prog = assemble(
+        "
+                in [k+1]
+        k:      jt #1, #l
+        l:      jt #1, #m
+        m:      halt
+        ",
+    )
+    .unwrap();
+    let opt = optimize(&prog);
+    assert_eq!(
+        opt.refused,
+        Some("instruction at 2 may be modified".to_string())
+    );
+    assert_eq!(opt.prog, prog);
+
+    // Code read through a modified address is left alone.
+    let prog = assemble(
+        "
+                in [p+1]
+        p:      out [0]
+                jt #1, #a
+        a:      jt #1, #b
+        b:      halt
+        ",
+    )
+    .unwrap();
+    let opt = optimize(&prog);
+    assert_eq!(opt.refused, None);
+    assert!(opt.changes.is_empty());
+    let v = validate(&prog, &opt.prog, &[4], 1000).unwrap();
+    assert_eq!(v.outputs, vec![1105]);
+
+    // Computed jumps defeat the analysis.
+    let prog = assemble("in [t]\njt #1, [t]\nt: .data 0").unwrap();
+    let opt = optimize(&prog);
+    assert_eq!(opt.refused, Some("computed jump at 2".to_string()));
+
+    // Compiled programs keep their frames in order.
+    let src = include_str!("corpus/scopes.icl");
+    let prog = compile(src).unwrap();
+    let opt = optimize(&prog);
+    assert_eq!(opt.refused, None);
+    assert!(!opt.changes.is_empty());
+    let v = validate(&prog, &opt.prog, &[], 100_000).unwrap();
+    assert_eq!(v.outputs, super::interpret(src, &[]).unwrap());
+    assert!(v.executed.1 < v.executed.0);
+}
@@ -0,0 +1,419 @@
+//! Intcode execution profiling and coverage.
+//!
+//! A machine with profiling turned on by
+//! `Intcode::start_profile()` keeps a `Profile`: how often
+//! each address was executed as an instruction, how often
+//! each opcode was executed, and how often each memory
+//! address was read or written as data. After a run,
+//! `Profile::listing()` annotates a disassembly of the
+//! program with this information, showing the hot spots,
+//! the code that never ran and the code that was modified
+//! by the program itself.
+//!
+//! Only instructions that complete are counted: an input
+//! instruction that suspends for input, or an instruction
+//! that faults, is not.
+//!
+//! # Examples
+//!
+//! ```rust
+//! let prog = aoc::assemble("
+//! loop:   add [n], #-1, [n]
+//!         jt [n], #loop
+//!         halt
+//!         out [n]
+//! n:      .data 3
+//! ").unwrap();
+//! let mut ic = aoc::Intcode::new(prog).with_profile();
+//! ic.run();
+//! let profile = ic.profile().unwrap();
+//! assert_eq!(profile.executions(0), 3);
+//! assert_eq!(profile.writes(10), 3);
+//! assert_eq!(profile.hot_spots(1), vec![(0, 3)]);
+//! assert!(ic.profile_listing().unwrap().contains("never executed"));
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{decode, Insn, Intcode, Opcode, Operand, Terminus};
+
+// Addresses at or above this are left out of
+// `Intcode::profile_listing()`.
+const LISTING_LIMIT: usize = 1 << 20;
+
+/// Execution counts gathered while profiling a machine.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    // Executions and size of the instruction at each
+    // address executed.
+    insns: BTreeMap<usize, (u64, usize)>,
+    // Executions of each opcode, indexed by `Opcode`.
+    opcodes: [u64; 10],
+    reads: BTreeMap<usize, u64>,
+    writes: BTreeMap<usize, u64>,
+    total: u64,
+}
+
+impl Profile {
+    // Record the execution of the given instruction at the
+    // given address. `rel_base` is the relative base the
+    // instruction ran with, `jumped` is true if it
+    // transferred control, and `store` is the address it
+    // stored to, if any.
+    pub(super) fn record(
+        &mut self,
+        ip: usize,
+        insn: &Insn,
+        rel_base: i64,
+        jumped: bool,
+        store: Option<usize>,
+    ) {
+        self.total += 1;
+        self.opcodes[insn.op as usize] += 1;
+        self.insns.entry(ip).or_insert((0, insn.size())).0 += 1;
+        let nreads = match insn.op {
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse if !jumped => 1,
+            _ => insn.opnds.len(),
+        };
+        for (i, opnd) in insn.opnds.iter().take(nreads).enumerate() {
+            if Some(i) == insn.op.store_opnd() {
+                continue;
+            }
+            let addr = match *opnd {
+                Operand::Imm(_) => continue,
+                Operand::Pos(a) => a,
+                Operand::Rel(o) => o.wrapping_add(rel_base),
+            };
+            *self.reads.entry(addr as usize).or_insert(0) += 1;
+        }
+        if let Some(addr) = store {
+            *self.writes.entry(addr).or_insert(0) += 1;
+        }
+    }
+
+    /// Total number of instructions executed.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Number of times the instruction at the given
+    /// address was executed.
+    pub fn executions(&self, addr: usize) -> u64 {
+        self.insns.get(&addr).map_or(0, |&(n, _)| n)
+    }
+
+    /// Number of times the given address was read as
+    /// data.
+    pub fn reads(&self, addr: usize) -> u64 {
+        self.reads.get(&addr).cloned().unwrap_or(0)
+    }
+
+    /// Number of times the given address was written.
+    pub fn writes(&self, addr: usize) -> u64 {
+        self.writes.get(&addr).cloned().unwrap_or(0)
+    }
+
+    /// Addresses executed as instructions, with their
+    /// execution counts, in address order.
+    pub fn executed(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.insns.iter().map(|(&addr, &(n, _))| (addr, n))
+    }
+
+    /// Execution counts for each opcode executed, by
+    /// mnemonic, in opcode order.
+    pub fn opcode_counts(&self) -> Vec<(&'static str, u64)> {
+        Opcode::ALL
+            .iter()
+            .map(|&op| (op.mnemonic(), self.opcodes[op as usize]))
+            .filter(|&(_, n)| n > 0)
+            .collect()
+    }
+
+    /// The given number of most-executed instruction
+    /// addresses with their counts, most executed first.
+    pub fn hot_spots(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut hot: Vec<(usize, u64)> = self.executed().collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot.truncate(n);
+        hot
+    }
+
+    /// Addresses written by the program that are part of
+    /// an instruction it executed, in address order.
+    pub fn self_modified(&self) -> Vec<usize> {
+        self.writes
+            .keys()
+            .cloned()
+            .filter(|&addr| self.in_executed(addr))
+            .collect()
+    }
+
+    // True if the given address is part of an executed
+    // instruction.
+    fn in_executed(&self, addr: usize) -> bool {
+        self.insns
+            .range(..=addr)
+            .next_back()
+            .is_some_and(|(&ip, &(_, size))| addr < ip + size)
+    }
+
+    /// Produce a listing of the given program annotated
+    /// with this profile. It begins with a summary: the
+    /// opcode mix, the hot spots and the self-modified
+    /// cells. Then each line shows an execution count, the
+    /// address, the raw words and the disassembly, and
+    /// notes data reads and writes. Code that decodes but
+    /// never ran is marked, as is code that was modified.
+    ///
+    /// Disassembly follows the instructions actually
+    /// executed, and is linear elsewhere as with
+    /// `disassemble()`. Unexecuted words accessed as data
+    /// are always shown as data.
+    pub fn listing(&self, prog: &[i64]) -> String {
+        let mut result =
+            format!("; {} instructions executed\n", self.total);
+        let mix: Vec<String> = self
+            .opcode_counts()
+            .iter()
+            .map(|(m, n)| format!("{} {}", m, n))
+            .collect();
+        result += &format!("; opcodes: {}\n", mix.join(", "));
+        let hot: Vec<String> = self
+            .hot_spots(5)
+            .iter()
+            .map(|(a, n)| format!("{} ({})", a, n))
+            .collect();
+        result += &format!("; hot spots: {}\n", hot.join(", "));
+        let modified = self.self_modified();
+        if !modified.is_empty() {
+            let modified: Vec<String> =
+                modified.iter().map(|a| a.to_string()).collect();
+            result +=
+                &format!("; self-modified: {}\n", modified.join(", "));
+        }
+
+        let modified: BTreeSet<usize> = modified.into_iter().collect();
+        let mut addr = 0;
+        while addr < prog.len() {
+            let accessed = self.reads.contains_key(&addr)
+                || self.writes.contains_key(&addr);
+            // A linear decode must not run into executed
+            // code.
+            let insn = match self.insns.get(&addr) {
+                Some(_) => decode(prog, addr),
+                None if accessed => None,
+                None => decode(prog, addr).filter(|insn| {
+                    self.insns
+                        .range(addr + 1..addr + insn.size())
+                        .next()
+                        .is_none()
+                }),
+            };
+            // Executed code that no longer decodes keeps its
+            // old size.
+            let executed = self.insns.get(&addr);
+            let size = match (&insn, executed) {
+                (Some(insn), _) => insn.size(),
+                (None, Some(&(_, size))) => size.min(prog.len() - addr),
+                (None, None) => 1,
+            };
+            let words: Vec<String> = prog[addr..addr + size]
+                .iter()
+                .map(|w| w.to_string())
+                .collect();
+            let (count, text, mut notes) = match (insn, executed) {
+                (Some(insn), Some(&(n, _))) => {
+                    (n.to_string(), insn.to_string(), Vec::new())
+                }
+                (None, Some(&(n, _))) => {
+                    (n.to_string(), "?".to_string(), Vec::new())
+                }
+                (Some(insn), None) => (
+                    "-".to_string(),
+                    insn.to_string(),
+                    vec!["never executed".to_string()],
+                ),
+                (None, None) => (
+                    String::new(),
+                    format!(".data {}", prog[addr]),
+                    Vec::new(),
+                ),
+            };
+            if (addr..addr + size).any(|a| modified.contains(&a)) {
+                notes.push("self-modified".to_string());
+            }
+            if size == 1 {
+                if let Some(n) = self.reads.get(&addr) {
+                    notes.push(format!("read {}", n));
+                }
+                if let Some(n) = self.writes.get(&addr) {
+                    notes.push(format!("written {}", n));
+                }
+            }
+            let line = format!(
+                "{:>10} {:6}  {:<28} {:<28} {}",
+                count,
+                addr,
+                words.join(" "),
+                text,
+                notes.join(", "),
+            );
+            result += line.trim_end();
+            result.push('\n');
+            addr += size;
+        }
+        result
+    }
+}
+
+impl Intcode {
+    /// Builder for turning on profiling.
+    pub fn with_profile(mut self) -> Self {
+        self.start_profile();
+        self
+    }
+
+    /// Start profiling from scratch, discarding any profile
+    /// gathered so far.
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    /// The profile gathered so far, if profiling.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Stop profiling, returning the profile gathered.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Produce an annotated listing of this machine's
+    /// memory from the profile gathered so far, as with
+    /// `Profile::listing()`, or `None` if not profiling.
+    /// The listing runs up to the last address executed or
+    /// accessed, leaving out addresses in the millions and
+    /// beyond.
+    pub fn profile_listing(&self) -> Option<String> {
+        let profile = self.profile.as_ref()?;
+        let end = profile
+            .insns
+            .iter()
+            .map(|(&addr, &(_, size))| addr + size)
+            .chain(profile.reads.keys().map(|&addr| addr + 1))
+            .chain(profile.writes.keys().map(|&addr| addr + 1))
+            .filter(|&end| end <= LISTING_LIMIT)
+            .max()
+            .unwrap_or(0)
+            .min(self.prog.len());
+        let prog: Vec<i64> = self.prog.iter().take(end).collect();
+        Some(profile.listing(&prog))
+    }
+
+    // Update the profile after executing the given
+    // instruction from the given address with the given
+    // relative base.
+    pub(super) fn update_profile(
+        &mut self,
+        ip: usize,
+        insn: Option<Insn>,
+        rel_base: i64,
+        result: &Result<Option<Terminus>, super::IntcodeError>,
+    ) {
+        let profile = match self.profile {
+            Some(ref mut profile) => profile,
+            None => return,
+        };
+        let insn = match (insn, result) {
+            (Some(insn), Ok(None))
+            | (Some(insn), Ok(Some(Terminus::HaveOutput(_))))
+            | (Some(insn), Ok(Some(Terminus::Halted))) => insn,
+            _ => return,
+        };
+        let jumped = self.ip != ip + insn.size();
+        profile.record(ip, &insn, rel_base, jumped, self.last_store);
+    }
+}
+
+#[test]
+fn test_profile() {
+    // Sum 0..5 by bumping an immediate operand.
+    let prog = super::assemble(
+        "
+        loop:   add [x], #0, [x]
+                add [loop+2], #1, [loop+2]
+                lt [loop+2], #5, [t]
+                jt [t], #loop
+                out [x]
+                halt
+                jf #0, #loop
+        x:      .data 0
+        t:      .data 0
+        ",
+    )
+    .unwrap();
+    let mut ic = Intcode::new(prog).with_profile();
+    assert_eq!(ic.run(), Terminus::HaveOutput(10));
+    assert_eq!(ic.run(), Terminus::Halted);
+    let profile = ic.take_profile().unwrap();
+    assert!(ic.profile().is_none());
+
+    assert_eq!(profile.total(), 5 * 4 + 2);
+    assert_eq!(profile.executions(0), 5);
+    assert_eq!(profile.executions(17), 1);
+    assert_eq!(profile.executions(1), 0);
+    assert_eq!(
+        profile.opcode_counts(),
+        vec![
+            ("add", 10),
+            ("out", 1),
+            ("jt", 5),
+            ("lt", 5),
+            ("halt", 1)
+        ]
+    );
+    assert_eq!(profile.hot_spots(2), vec![(0, 5), (4, 5)]);
+    assert_eq!(profile.self_modified(), vec![2]);
+    // The jump target is immediate, so is never read.
+    assert_eq!((profile.reads(21), profile.writes(21)), (6, 5));
+    assert_eq!((profile.reads(22), profile.writes(22)), (5, 5));
+    assert_eq!((profile.reads(2), profile.writes(2)), (10, 5));
+
+    let listing = profile.listing(&ic.memory().to_vec());
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines[0], "; 22 instructions executed");
+    assert_eq!(
+        lines[2],
+        "; hot spots: 0 (5), 4 (5), 8 (5), 12 (5), 15 (1)"
+    );
+    assert_eq!(lines[3], "; self-modified: 2");
+    assert!(lines[4].starts_with("         5      0  1001 21 5 21"));
+    assert!(lines[4].contains("add [21], #5, [21]"));
+    assert!(lines[4].ends_with("self-modified"));
+    assert!(lines[10].contains("jf #0, #0"));
+    assert!(lines[10].ends_with("never executed"));
+    assert!(lines[11].contains(".data 10"));
+    assert!(lines[11].ends_with("read 6, written 5"));
+
+    // Suspending for input is not an execution.
+    let mut ic = Intcode::new(vec![3, 3, 99, 0]).with_profile();
+    assert_eq!(ic.run(), Terminus::NeedInput);
+    assert_eq!(ic.profile().unwrap().total(), 0);
+    ic.add_input(1);
+    ic.run();
+    assert_eq!(ic.profile().unwrap().total(), 2);
+    let listing = ic.profile_listing().unwrap();
+    assert!(listing.ends_with("written 1\n"));
+
+    // Overwritten code keeps its count.
+    let mut ic = Intcode::new(vec![3, 0, 4, 0, 99]).with_profile();
+    ic.add_input(42);
+    ic.collect_outputs();
+    let listing = ic.profile_listing().unwrap();
+    let line = listing.lines().nth(4).unwrap();
+    assert!(line.starts_with("         1      0  42 0"));
+    assert!(
+        line.ends_with("?                            self-modified")
+    );
+}
@@ -0,0 +1,329 @@
+//! Saved Intcode machine states.
+//!
+//! A `Snapshot` holds everything needed to resume a
+//! machine: memory, instruction pointer, relative base and
+//! pending inputs. Taking a snapshot copies memory, unless
+//! the machine has paged memory, whose pages are shared
+//! until written. Snapshots can be saved to and loaded
+//! from a compact binary format.
+//!
+//! The format is the magic bytes `ICS2` followed by a
+//! sequence of LEB128 varints (zigzag-encoded where signed):
+//! the `ip`, the `rel_base`, the number of inputs and the
+//! inputs, the memory length, and then memory as runs of
+//! zeros alternating with runs of literal words. Each run
+//! is a zero count, a literal count and the literals.
+//! Memory is followed by the values too large for an
+//! `i64`: a count, then for each an address, its number of
+//! base 2^32 digits shifted left one with the sign in the
+//! low bit, and the digits from least significant up.
+//!
+//! # Examples
+//!
+//! ```rust
+//! let mut ic = aoc::Intcode::new(vec![3, 0, 4, 0, 99]);
+//! ic.add_input(7);
+//! let snap = ic.snapshot();
+//! let mut bytes = Vec::new();
+//! snap.save(&mut bytes).unwrap();
+//! let snap = aoc::Snapshot::load(bytes.as_slice()).unwrap();
+//! let mut ic = aoc::Intcode::from_snapshot(&snap);
+//! assert_eq!(ic.collect_outputs(), vec![7]);
+//! ```
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use super::{BigInt, DecodeCache, Intcode, Memory};
+
+const MAGIC: &[u8; 4] = b"ICS2";
+
+/// A saved Intcode machine state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    memory: Memory,
+    inputs: Vec<i64>,
+    ip: usize,
+    rel_base: i64,
+}
+
+impl Snapshot {
+    /// Memory at the time of the snapshot.
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// Inputs pending at the time of the snapshot, in the
+    /// order they will be consumed.
+    pub fn pending_inputs(&self) -> &[i64] {
+        &self.inputs
+    }
+
+    /// Instruction pointer at the time of the snapshot.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// Relative base at the time of the snapshot.
+    pub fn rel_base(&self) -> i64 {
+        self.rel_base
+    }
+
+    /// Write this snapshot in binary format.
+    pub fn save<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        write_varint(&mut w, self.ip as u64)?;
+        write_signed(&mut w, self.rel_base)?;
+        write_varint(&mut w, self.inputs.len() as u64)?;
+        for &input in &self.inputs {
+            write_signed(&mut w, input)?;
+        }
+        write_memory(&mut w, &self.memory)
+    }
+
+    /// Read a snapshot in binary format.
+    pub fn load<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(bad_data("bad snapshot magic"));
+        }
+        let ip = read_varint(&mut r)? as usize;
+        let rel_base = read_signed(&mut r)?;
+        let ninputs = read_varint(&mut r)?;
+        let inputs = (0..ninputs)
+            .map(|_| read_signed(&mut r))
+            .collect::<io::Result<Vec<i64>>>()?;
+        let memory = read_memory(&mut r)?;
+        Ok(Self {
+            memory,
+            inputs,
+            ip,
+            rel_base,
+        })
+    }
+}
+
+impl Intcode {
+    /// Take a snapshot of this machine's state.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.prog.clone(),
+            inputs: self.pending_inputs(),
+            ip: self.ip,
+            rel_base: self.rel_base,
+        }
+    }
+
+    /// Return this machine to the state in the given
+    /// snapshot. Tracing, fuel, the execution engine and
+    /// the executed-instruction count are not affected. Any
+    /// journal is restarted.
+    pub fn restore(&mut self, snap: &Snapshot) {
+        self.prog = snap.memory.clone();
+        self.inputs = snap.inputs.iter().cloned().collect();
+        self.ip = snap.ip;
+        self.rel_base = snap.rel_base;
+        self.last_store = None;
+        if self.cache.is_some() {
+            self.cache = Some(DecodeCache::default());
+        }
+        self.restart_journal();
+    }
+
+    /// Make a new machine in the state of the given
+    /// snapshot.
+    pub fn from_snapshot(snap: &Snapshot) -> Self {
+        let mut ic = Intcode::new(Vec::new());
+        ic.restore(snap);
+        ic
+    }
+}
+
+// Write memory as its length, the runs of zeros and
+// literals, and the values too large for an `i64`.
+pub(super) fn write_memory<W: Write>(
+    w: &mut W,
+    memory: &Memory,
+) -> io::Result<()> {
+    let len = memory.len();
+    write_varint(w, len as u64)?;
+    // Gather the runs of nonzero words, skipping
+    // unallocated memory.
+    let mut runs: Vec<(usize, Vec<i64>)> = Vec::new();
+    for (base, words) in memory.chunks() {
+        for (addr, v) in (base..).zip(words) {
+            if v == 0 {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start, lits)) if *start + lits.len() == addr => {
+                    lits.push(v)
+                }
+                _ => runs.push((addr, vec![v])),
+            }
+        }
+    }
+    let mut pos = 0;
+    for (start, lits) in runs {
+        write_varint(w, (start - pos) as u64)?;
+        write_varint(w, lits.len() as u64)?;
+        for v in &lits {
+            write_signed(w, *v)?;
+        }
+        pos = start + lits.len();
+    }
+    if pos < len {
+        write_varint(w, (len - pos) as u64)?;
+        write_varint(w, 0)?;
+    }
+    let big: Vec<(usize, &BigInt)> = memory.big_values().collect();
+    write_varint(w, big.len() as u64)?;
+    for (addr, val) in big {
+        let (negative, digits) = val.parts();
+        write_varint(w, addr as u64)?;
+        write_varint(w, (digits.len() as u64) << 1 | negative as u64)?;
+        for &d in digits {
+            write_varint(w, u64::from(d))?;
+        }
+    }
+    Ok(())
+}
+
+// Read memory written by `write_memory()`.
+pub(super) fn read_memory<R: Read>(r: &mut R) -> io::Result<Memory> {
+    let len = read_varint(r)? as usize;
+    let mut memory = Memory::from(Vec::new());
+    memory.resize(len);
+    let mut pos = 0;
+    while pos < len {
+        let zeros = read_varint(r)? as usize;
+        let lits = read_varint(r)? as usize;
+        let run = zeros
+            .checked_add(lits)
+            .ok_or_else(|| bad_data("memory overrun"))?;
+        if run == 0 {
+            return Err(bad_data("empty memory run"));
+        }
+        if run > len - pos {
+            return Err(bad_data("memory overrun"));
+        }
+        pos += zeros;
+        for _ in 0..lits {
+            memory.set(pos, read_signed(r)?);
+            pos += 1;
+        }
+    }
+    let nbig = read_varint(r)?;
+    for _ in 0..nbig {
+        let addr = read_varint(r)? as usize;
+        if addr >= len {
+            return Err(bad_data("big value out of range"));
+        }
+        let header = read_varint(r)?;
+        let digits = (0..header >> 1)
+            .map(|_| {
+                let d = read_varint(r)?;
+                u32::try_from(d).map_err(|_| bad_data("bad big digit"))
+            })
+            .collect::<io::Result<Vec<u32>>>()?;
+        let val = BigInt::from_parts(header & 1 == 1, digits);
+        memory.set_big(addr, val);
+    }
+    Ok(memory)
+}
+
+pub(super) fn bad_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_varint<W: Write>(w: &mut W, mut v: u64) -> io::Result<()> {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn write_signed<W: Write>(w: &mut W, v: i64) -> io::Result<()> {
+    write_varint(w, ((v << 1) ^ (v >> 63)) as u64)
+}
+
+fn read_varint<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut v = 0;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0];
+        r.read_exact(&mut byte)?;
+        // Bits shifted past the top of a `u64` would be
+        // lost.
+        let bits = u64::from(byte[0] & 0x7f);
+        if shift >= 64 || bits << shift >> shift != bits {
+            return Err(bad_data("varint too long"));
+        }
+        v |= bits << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(v);
+        }
+        shift += 7;
+    }
+}
+
+fn read_signed<R: Read>(r: &mut R) -> io::Result<i64> {
+    let v = read_varint(r)?;
+    Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+}
+
+#[test]
+fn test_snapshot() {
+    let mut prog = vec![
+        109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006,
+        101, 0, 99,
+    ];
+    prog.resize(2000, 0);
+    prog[1999] = i64::MIN;
+    let mut ic = Intcode::new(prog).with_inputs(vec![i64::MAX, -2]);
+    for _ in 0..5 {
+        ic.run();
+    }
+    let snap = ic.snapshot();
+    let mut bytes = Vec::new();
+    snap.save(&mut bytes).unwrap();
+    assert!(bytes.len() < 100);
+    let loaded = Snapshot::load(bytes.as_slice()).unwrap();
+    assert_eq!(loaded, snap);
+
+    // The restored machine carries on where the original
+    // left off.
+    let rest = ic.collect_outputs();
+    let mut ic = Intcode::from_snapshot(&loaded);
+    assert_eq!(ic.collect_outputs(), rest);
+    assert_eq!(ic.pending_inputs(), vec![i64::MAX, -2]);
+
+    assert!(Snapshot::load(&bytes[..bytes.len() - 1]).is_err());
+
+    // Memory at far-flung addresses stays cheap.
+    let far = 1_000_000_000_000;
+    let mut ic = Intcode::new(vec![1101, 1, 2, far, 99]);
+    ic.run();
+    let mut bytes = Vec::new();
+    ic.snapshot().save(&mut bytes).unwrap();
+    assert!(bytes.len() < 100);
+    let loaded = Snapshot::load(bytes.as_slice()).unwrap();
+    assert_eq!(loaded.memory().get(far as usize), Some(3));
+    assert!(Snapshot::load(&b"ICS0"[..]).is_err());
+
+    // Big values survive the round trip.
+    let mut ic =
+        Intcode::new(vec![1002, 7, 1 << 40, 7, 4, 7, 99, 1 << 40])
+            .with_arith(super::Arith::Big);
+    ic.run();
+    let mut bytes = Vec::new();
+    ic.snapshot().save(&mut bytes).unwrap();
+    let loaded = Snapshot::load(bytes.as_slice()).unwrap();
+    assert_eq!(loaded, ic.snapshot());
+    assert!(loaded.memory().is_big(7));
+}
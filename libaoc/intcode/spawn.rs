@@ -0,0 +1,312 @@
+//! Running Intcode machines on threads of their own.
+//!
+//! `Intcode::spawn()` moves a machine onto a new thread,
+//! with its input and output wired to `std::sync::mpsc`
+//! channels, and returns the caller's ends of the channels
+//! along with a `Worker` for joining the thread.
+//! `Intcode::spawn_with()` takes the channel ends to use
+//! instead, so that machines can be chained: the output
+//! sender of one can feed the input of the next, and a ring
+//! of them runs an amplifier feedback loop in parallel.
+//!
+//! The thread runs until the program halts, needs input
+//! after its input channel has hung up, runs out of fuel,
+//! or faults. `Worker::join()` gives back the machine and
+//! how it stopped, or the fault. Outputs sent after the
+//! output channel has hung up are dropped. A `Cancel`
+//! shared with the thread stops it from outside: it is
+//! checked every `CANCEL_INTERVAL` instructions, and
+//! regularly while waiting for input.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::{Exit, Intcode};
+//!
+//! // Double each input.
+//! let prog = aoc::assemble("
+//! loop:   in [x]
+//!         mul [x], #2, [x]
+//!         out [x]
+//!         jt #1, #loop
+//! x:      .data 0
+//! ").unwrap();
+//! let spawned = Intcode::new(prog).spawn();
+//! for i in 1..=3 {
+//!     spawned.input.send(i).unwrap();
+//! }
+//! drop(spawned.input);
+//! let outputs: Vec<i64> = spawned.output.iter().collect();
+//! assert_eq!(outputs, vec![2, 4, 6]);
+//! let (exit, _) = spawned.worker.join().unwrap();
+//! assert_eq!(exit, Exit::InputClosed);
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{Intcode, IntcodeError, Terminus};
+
+/// A spawned machine checks for cancellation at least once
+/// per this many instructions.
+pub const CANCEL_INTERVAL: u64 = 1024;
+
+// How long a spawned machine waits for input between
+// checks for cancellation.
+const CANCEL_POLL: Duration = Duration::from_millis(10);
+
+/// Shared flag for cancelling spawned machines. Clones
+/// share the flag, so one `Cancel` may stop many machines.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// Make a new flag, not yet cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the machines sharing this flag to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// True once `cancel()` has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// How a spawned machine stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// Program executed a `Halt` instruction.
+    Halted,
+    /// Program needed input after the input channel hung
+    /// up.
+    InputClosed,
+    /// Program used up its fuel or passed its deadline.
+    OutOfFuel,
+    /// The run was cancelled.
+    Cancelled,
+}
+
+/// Handle for a machine running on its own thread.
+#[derive(Debug)]
+pub struct Worker {
+    thread: JoinHandle<Result<(Exit, Intcode), IntcodeError>>,
+    cancel: Cancel,
+}
+
+impl Worker {
+    /// Ask the machine to stop. Returns at once: use
+    /// `join()` to wait for it.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// The cancellation flag shared with the machine.
+    pub fn canceller(&self) -> Cancel {
+        self.cancel.clone()
+    }
+
+    /// True once the machine has stopped.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Wait for the machine to stop, and return how it
+    /// stopped and the machine itself, or its execution
+    /// fault.
+    ///
+    /// # Panics
+    /// Will panic if the machine's thread panicked.
+    pub fn join(self) -> Result<(Exit, Intcode), IntcodeError> {
+        self.thread
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    }
+}
+
+/// A machine spawned by `Intcode::spawn()`, with the
+/// caller's ends of its channels.
+#[derive(Debug)]
+pub struct Spawned {
+    /// Sends inputs to the machine. Dropping this hangs up
+    /// the input channel.
+    pub input: Sender<i64>,
+    /// Receives outputs from the machine. This hangs up
+    /// once the machine stops.
+    pub output: Receiver<i64>,
+    /// The machine's thread.
+    pub worker: Worker,
+}
+
+impl Intcode {
+    /// Move this machine onto a new thread, with new
+    /// channels for its input and output.
+    pub fn spawn(self) -> Spawned {
+        let (input, rx) = channel();
+        let (tx, output) = channel();
+        let worker = self.spawn_with(rx, tx, Cancel::new());
+        Spawned {
+            input,
+            output,
+            worker,
+        }
+    }
+
+    /// Move this machine onto a new thread, taking inputs
+    /// from the given receiver and sending outputs to the
+    /// given sender, and stopping when the given flag is
+    /// cancelled.
+    pub fn spawn_with(
+        self,
+        input: Receiver<i64>,
+        output: Sender<i64>,
+        cancel: Cancel,
+    ) -> Worker {
+        let flag = cancel.clone();
+        let thread =
+            thread::spawn(move || self.work(&input, &output, &flag));
+        Worker { thread, cancel }
+    }
+
+    // Run on a spawned machine's thread until the machine
+    // stops.
+    fn work(
+        mut self,
+        input: &Receiver<i64>,
+        output: &Sender<i64>,
+        cancel: &Cancel,
+    ) -> Result<(Exit, Intcode), IntcodeError> {
+        let exit = loop {
+            if cancel.is_cancelled() {
+                break Exit::Cancelled;
+            }
+            // Run a slice of at most `CANCEL_INTERVAL`
+            // instructions, charged to the machine's own
+            // fuel.
+            let fuel = self.fuel;
+            let slice = fuel
+                .map_or(CANCEL_INTERVAL, |f| f.min(CANCEL_INTERVAL));
+            self.fuel = Some(slice);
+            let result = self.try_run();
+            let used = slice - self.fuel.unwrap_or(0);
+            self.fuel = fuel.map(|f| f - used);
+            match result? {
+                Terminus::Halted => break Exit::Halted,
+                Terminus::HaveOutput(val) => {
+                    let _ = output.send(val);
+                }
+                Terminus::NeedInput => match wait(input, cancel) {
+                    Ok(val) => self.add_input(val),
+                    Err(exit) => break exit,
+                },
+                Terminus::OutOfFuel => {
+                    let late = self
+                        .deadline
+                        .is_some_and(|d| Instant::now() >= d);
+                    if self.fuel == Some(0) || late {
+                        break Exit::OutOfFuel;
+                    }
+                }
+            }
+        };
+        Ok((exit, self))
+    }
+}
+
+// Wait for an input, or for the input channel to hang up
+// or the run to be cancelled.
+fn wait(input: &Receiver<i64>, cancel: &Cancel) -> Result<i64, Exit> {
+    loop {
+        match input.recv_timeout(CANCEL_POLL) {
+            Ok(val) => return Ok(val),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(Exit::InputClosed)
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if cancel.is_cancelled() {
+                    return Err(Exit::Cancelled);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_spawn_ring() {
+    // Day 7 Part 2 example: five amplifiers in a feedback
+    // loop, each on its own thread.
+    let prog = vec![
+        3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27,
+        4, 27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5,
+    ];
+    let phases = [9, 8, 7, 6, 5];
+    let (first_tx, first_rx) = channel();
+    first_tx.send(phases[0]).unwrap();
+    first_tx.send(0).unwrap();
+    let mut rx = first_rx;
+    let mut workers = Vec::new();
+    let cancel = Cancel::new();
+    for &phase in &phases[1..] {
+        let (tx, next_rx) = channel();
+        tx.send(phase).unwrap();
+        let ic = Intcode::new(prog.clone());
+        workers.push(ic.spawn_with(rx, tx, cancel.clone()));
+        rx = next_rx;
+    }
+    // The last amplifier feeds the first through here, so
+    // that its final output can be seen.
+    let (tx, tap) = channel();
+    let ic = Intcode::new(prog);
+    workers.push(ic.spawn_with(rx, tx, cancel));
+    let mut last = None;
+    for val in tap {
+        let _ = first_tx.send(val);
+        last = Some(val);
+    }
+    assert_eq!(last, Some(139_629_729));
+    for worker in workers {
+        let (exit, mut ic) = worker.join().unwrap();
+        assert_eq!(exit, Exit::Halted);
+        assert_eq!(ic.peek(28), 0);
+    }
+}
+
+#[test]
+fn test_spawn_stop() {
+    use super::Fault;
+
+    // Faults come back through `join()`.
+    let spawned = Intcode::new(vec![104, 7, 98]).spawn();
+    assert_eq!(spawned.output.recv(), Ok(7));
+    let err = spawned.worker.join().unwrap_err();
+    assert_eq!((err.ip, err.fault), (2, Fault::IllegalOpcode(98)));
+
+    // Cancelling a machine stuck in a loop.
+    let spawned = Intcode::new(vec![1105, 1, 0]).spawn();
+    spawned.worker.cancel();
+    let (exit, _) = spawned.worker.join().unwrap();
+    assert_eq!(exit, Exit::Cancelled);
+
+    // Cancelling a machine waiting for input.
+    let spawned = Intcode::new(vec![3, 0, 4, 0, 1105, 1, 0]).spawn();
+    spawned.input.send(5).unwrap();
+    assert_eq!(spawned.output.recv(), Ok(5));
+    spawned.worker.canceller().cancel();
+    let (exit, _) = spawned.worker.join().unwrap();
+    assert_eq!(exit, Exit::Cancelled);
+    assert!(spawned.output.recv().is_err());
+
+    // Fuel still limits the run, slice by slice.
+    let ic = Intcode::new(vec![1105, 1, 0]).with_fuel(5000);
+    let spawned = ic.spawn();
+    let (exit, ic) = spawned.worker.join().unwrap();
+    assert_eq!(exit, Exit::OutOfFuel);
+    assert_eq!((ic.executed(), ic.fuel()), (5000, Some(0)));
+}
@@ -0,0 +1,1089 @@
+//! Symbolic execution of Intcode programs.
+//!
+//! A `Symbolic` machine runs a program in which some memory
+//! cells or inputs stand for unknown values, named by
+//! `Sym`s. Values are `Expr`s: `add` and `mul` combine
+//! polynomials in the symbols, and `lt` and `eq` on values
+//! that are not both constant give comparison expressions.
+//!
+//! Control flow and store addresses must stay concrete: a
+//! jump on a symbolic condition, or a store to a symbolic
+//! address, stops the machine with an error. A load from a
+//! symbolic address gives `Expr::Unknown`, which does no
+//! harm if it is overwritten before it matters. Day 2
+//! programs do just this: the noun and verb are first used
+//! as the addresses of a sum that is immediately replaced.
+//!
+//! Once the machine stops, `solve()` finds values for the
+//! symbols, within given ranges, that make an output or a
+//! memory cell come out to a target value. For a
+//! polynomial that is at most quadratic in some symbol it
+//! tries each combination of the other symbols and solves
+//! for that one directly; anything else is solved by trying
+//! every combination.
+//!
+//! Symbolic arithmetic is exact, so it can miss overflow
+//! faults that a concrete run would hit. Check a solution
+//! by running the program on it.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use aoc::{solve, SymTerminus, Symbolic};
+//!
+//! let prog = aoc::assemble("
+//!         in [x]
+//!         mul [x], [x], [y]
+//!         mul [x], #3, [x]
+//!         add [x], [y], [y]
+//!         out [y]
+//!         halt
+//! x:      .data 0
+//! y:      .data 0
+//! ").unwrap();
+//! let mut sym = Symbolic::new(prog);
+//! let x = sym.symbolic_input("x");
+//! let out = match sym.run().unwrap() {
+//!     SymTerminus::HaveOutput(out) => out,
+//!     t => panic!("unexpected {:?}", t),
+//! };
+//! assert_eq!(out.to_string(), "x^2 + 3*x");
+//! let soln = solve(&out, 130, &[(x, -100..=100)]);
+//! assert_eq!(soln, Some(vec![-13]));
+//! ```
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::{Fault, Intcode, Memory, Opcode, OpndMode};
+
+// Polynomials with more terms than this become
+// `Expr::Unknown`, to keep runaway products in check.
+const MAX_TERMS: usize = 256;
+
+/// A symbol standing for an unknown value. Symbols with the
+/// same name are the same symbol.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Sym(String);
+
+impl Sym {
+    /// Make a symbol with the given name.
+    pub fn new(name: &str) -> Self {
+        Sym(name.to_string())
+    }
+
+    /// Name of this symbol.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Sym {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A polynomial in symbols with integer coefficients.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Poly {
+    // Nonzero coefficients, keyed by the sorted symbols of
+    // their monomials.
+    terms: BTreeMap<Vec<Sym>, i64>,
+}
+
+impl Poly {
+    /// The constant polynomial with the given value.
+    pub fn constant(c: i64) -> Self {
+        let mut poly = Self::default();
+        if c != 0 {
+            poly.terms.insert(Vec::new(), c);
+        }
+        poly
+    }
+
+    /// The polynomial consisting of just the given symbol.
+    pub fn symbol(sym: Sym) -> Self {
+        let mut poly = Self::default();
+        poly.terms.insert(vec![sym], 1);
+        poly
+    }
+
+    /// Value of the polynomial, if it is constant.
+    pub fn as_const(&self) -> Option<i64> {
+        match self.terms.iter().next() {
+            None => Some(0),
+            Some((mono, &c))
+                if mono.is_empty() && self.terms.len() == 1 =>
+            {
+                Some(c)
+            }
+            _ => None,
+        }
+    }
+
+    /// Iterate over the terms, as pairs of the sorted
+    /// symbols of a monomial and its nonzero coefficient.
+    pub fn terms(&self) -> impl Iterator<Item = (&[Sym], i64)> {
+        self.terms.iter().map(|(mono, &c)| (mono.as_slice(), c))
+    }
+
+    /// Highest power of the given symbol in any term.
+    pub fn degree_in(&self, sym: &Sym) -> usize {
+        self.terms
+            .keys()
+            .map(|mono| mono.iter().filter(|&s| s == sym).count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The symbols appearing in the polynomial.
+    pub fn symbols(&self) -> BTreeSet<Sym> {
+        self.terms.keys().flatten().cloned().collect()
+    }
+
+    /// Value of the polynomial with the given values for
+    /// its symbols, or `None` if a symbol has no value or
+    /// the arithmetic overflows.
+    pub fn eval(&self, values: &BTreeMap<Sym, i64>) -> Option<i64> {
+        let mut sum = 0i64;
+        for (mono, &c) in &self.terms {
+            let mut term = c;
+            for sym in mono {
+                term = term.checked_mul(*values.get(sym)?)?;
+            }
+            sum = sum.checked_add(term)?;
+        }
+        Some(sum)
+    }
+
+    // Sum of two polynomials, or `None` on coefficient
+    // overflow.
+    fn checked_add(&self, other: &Poly) -> Option<Poly> {
+        let mut sum = self.clone();
+        for (mono, &c) in &other.terms {
+            sum.add_term(mono.clone(), c)?;
+        }
+        Some(sum)
+    }
+
+    // Product of two polynomials, or `None` on coefficient
+    // overflow.
+    fn checked_mul(&self, other: &Poly) -> Option<Poly> {
+        let mut product = Poly::default();
+        for (m1, &c1) in &self.terms {
+            for (m2, &c2) in &other.terms {
+                let mut mono: Vec<Sym> =
+                    m1.iter().chain(m2).cloned().collect();
+                mono.sort();
+                product.add_term(mono, c1.checked_mul(c2)?)?;
+            }
+        }
+        Some(product)
+    }
+
+    // Add a term in place, or return `None` on coefficient
+    // overflow.
+    fn add_term(&mut self, mono: Vec<Sym>, c: i64) -> Option<()> {
+        let sum = self.terms.get(&mono).unwrap_or(&0).checked_add(c)?;
+        if sum == 0 {
+            self.terms.remove(&mono);
+        } else {
+            self.terms.insert(mono, sum);
+        }
+        Some(())
+    }
+}
+
+impl fmt::Display for Poly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+        // Highest degree first.
+        let mut terms: Vec<(&Vec<Sym>, i64)> =
+            self.terms.iter().map(|(mono, &c)| (mono, c)).collect();
+        terms.sort_by_key(|&(mono, _)| (Reverse(mono.len()), mono));
+        for (i, (mono, c)) in terms.into_iter().enumerate() {
+            match (i, c < 0) {
+                (0, true) => write!(f, "-")?,
+                (0, false) => (),
+                (_, true) => write!(f, " - ")?,
+                (_, false) => write!(f, " + ")?,
+            }
+            let c = c.unsigned_abs();
+            if mono.is_empty() {
+                write!(f, "{}", c)?;
+                continue;
+            }
+            if c != 1 {
+                write!(f, "{}*", c)?;
+            }
+            let mut powers: Vec<(&Sym, usize)> = Vec::new();
+            for sym in mono {
+                match powers.last_mut() {
+                    Some((s, n)) if *s == sym => *n += 1,
+                    _ => powers.push((sym, 1)),
+                }
+            }
+            for (j, (sym, n)) in powers.into_iter().enumerate() {
+                if j > 0 {
+                    write!(f, "*")?;
+                }
+                write!(f, "{}", sym)?;
+                if n > 1 {
+                    write!(f, "^{}", n)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A value computed by a symbolic run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// A polynomial in the symbols. Constants are
+    /// polynomials too.
+    Poly(Poly),
+    /// `1` if the first value is less than the second, else
+    /// `0`.
+    Lt(Box<Expr>, Box<Expr>),
+    /// `1` if the values are equal, else `0`.
+    Eq(Box<Expr>, Box<Expr>),
+    /// Sum of values that are not both polynomials.
+    Add(Box<Expr>, Box<Expr>),
+    /// Product of values that are not both polynomials.
+    Mul(Box<Expr>, Box<Expr>),
+    /// A value that depends on the symbols in a way that is
+    /// not tracked, such as one loaded from a symbolic
+    /// address.
+    Unknown,
+}
+
+impl Expr {
+    /// The constant expression with the given value.
+    pub fn constant(c: i64) -> Self {
+        Expr::Poly(Poly::constant(c))
+    }
+
+    /// The expression consisting of just the given symbol.
+    pub fn symbol(sym: Sym) -> Self {
+        Expr::Poly(Poly::symbol(sym))
+    }
+
+    /// Value of the expression, if it is constant.
+    pub fn as_const(&self) -> Option<i64> {
+        self.as_poly().and_then(Poly::as_const)
+    }
+
+    /// The expression as a polynomial, if it is one.
+    pub fn as_poly(&self) -> Option<&Poly> {
+        match self {
+            Expr::Poly(poly) => Some(poly),
+            _ => None,
+        }
+    }
+
+    /// True if no part of the expression is `Unknown`.
+    pub fn is_known(&self) -> bool {
+        match self {
+            Expr::Poly(_) => true,
+            Expr::Lt(a, b)
+            | Expr::Eq(a, b)
+            | Expr::Add(a, b)
+            | Expr::Mul(a, b) => a.is_known() && b.is_known(),
+            Expr::Unknown => false,
+        }
+    }
+
+    /// The symbols appearing in the expression.
+    pub fn symbols(&self) -> BTreeSet<Sym> {
+        match self {
+            Expr::Poly(poly) => poly.symbols(),
+            Expr::Lt(a, b)
+            | Expr::Eq(a, b)
+            | Expr::Add(a, b)
+            | Expr::Mul(a, b) => {
+                let mut syms = a.symbols();
+                syms.extend(b.symbols());
+                syms
+            }
+            Expr::Unknown => BTreeSet::new(),
+        }
+    }
+
+    /// Value of the expression with the given values for
+    /// its symbols, or `None` if it is not known, a symbol
+    /// has no value, or the arithmetic overflows.
+    pub fn eval(&self, values: &BTreeMap<Sym, i64>) -> Option<i64> {
+        match self {
+            Expr::Poly(poly) => poly.eval(values),
+            Expr::Lt(a, b) => {
+                Some((a.eval(values)? < b.eval(values)?) as i64)
+            }
+            Expr::Eq(a, b) => {
+                Some((a.eval(values)? == b.eval(values)?) as i64)
+            }
+            Expr::Add(a, b) => {
+                a.eval(values)?.checked_add(b.eval(values)?)
+            }
+            Expr::Mul(a, b) => {
+                a.eval(values)?.checked_mul(b.eval(values)?)
+            }
+            Expr::Unknown => None,
+        }
+    }
+
+    // Result of an ALU instruction on the given values, or
+    // `None` on overflow.
+    fn apply(op: Opcode, a: Expr, b: Expr) -> Option<Expr> {
+        use Opcode::*;
+        if a == Expr::Unknown || b == Expr::Unknown {
+            return Some(Expr::Unknown);
+        }
+        if let (Some(x), Some(y)) = (a.as_const(), b.as_const()) {
+            let val = match op {
+                Add => x.checked_add(y)?,
+                Mul => x.checked_mul(y)?,
+                LessThan => (x < y) as i64,
+                Equals => (x == y) as i64,
+                _ => unreachable!("wrong insn for ALU"),
+            };
+            return Some(Expr::constant(val));
+        }
+        let poly = match (op, a.as_poly(), b.as_poly()) {
+            (Add, Some(p), Some(q)) => Some(p.checked_add(q)?),
+            (Mul, Some(p), Some(q)) => Some(p.checked_mul(q)?),
+            _ => None,
+        };
+        if let Some(poly) = poly {
+            if poly.terms.len() > MAX_TERMS {
+                return Some(Expr::Unknown);
+            }
+            return Some(Expr::Poly(poly));
+        }
+        let expr = match op {
+            Add if a.as_const() == Some(0) => b,
+            Add if b.as_const() == Some(0) => a,
+            Mul if a.as_const() == Some(0)
+                || b.as_const() == Some(0) =>
+            {
+                Expr::constant(0)
+            }
+            Mul if a.as_const() == Some(1) => b,
+            Mul if b.as_const() == Some(1) => a,
+            Equals if a == b => Expr::constant(1),
+            LessThan if a == b => Expr::constant(0),
+            Add => Expr::Add(Box::new(a), Box::new(b)),
+            Mul => Expr::Mul(Box::new(a), Box::new(b)),
+            LessThan => Expr::Lt(Box::new(a), Box::new(b)),
+            Equals => Expr::Eq(Box::new(a), Box::new(b)),
+            _ => unreachable!("wrong insn for ALU"),
+        };
+        Some(expr)
+    }
+
+    // Show the expression as an operand of a larger one.
+    fn fmt_operand(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Poly(poly) if poly.terms.len() > 1 => {
+                write!(f, "({})", poly)
+            }
+            e => write!(f, "{}", e),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (a, op, b) = match self {
+            Expr::Poly(poly) => return write!(f, "{}", poly),
+            Expr::Unknown => return write!(f, "?"),
+            Expr::Lt(a, b) => (a, "<", b),
+            Expr::Eq(a, b) => (a, "==", b),
+            Expr::Add(a, b) => (a, "+", b),
+            Expr::Mul(a, b) => (a, "*", b),
+        };
+        write!(f, "(")?;
+        a.fmt_operand(f)?;
+        write!(f, " {} ", op)?;
+        b.fmt_operand(f)?;
+        write!(f, ")")
+    }
+}
+
+/// This is returned by `Symbolic::run()` to indicate why it
+/// stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymTerminus {
+    /// Program executed a `Halt` instruction.
+    Halted,
+    /// Program executed an `Input` instruction with no
+    /// inputs buffered.
+    NeedInput,
+    /// Program executed an `Output` instruction with the
+    /// given value.
+    HaveOutput(Expr),
+    /// Program used up its fuel before suspending.
+    OutOfFuel,
+}
+
+/// Reason a symbolic run could not continue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymFault {
+    /// The program faulted as a concrete run would.
+    Fault(Fault),
+    /// A value that is not constant was needed as the given
+    /// kind of thing, such as a jump condition.
+    Symbolic(&'static str),
+}
+
+impl From<Fault> for SymFault {
+    fn from(fault: Fault) -> Self {
+        SymFault::Fault(fault)
+    }
+}
+
+/// Error stopping a symbolic run, with the address of the
+/// instruction at fault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymError {
+    pub ip: usize,
+    pub fault: SymFault,
+}
+
+impl fmt::Display for SymError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.fault {
+            SymFault::Fault(fault) => {
+                write!(f, "ip {}: {}", self.ip, fault)
+            }
+            SymFault::Symbolic(what) => {
+                write!(f, "ip {}: symbolic {}", self.ip, what)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SymError {}
+
+/// An Intcode machine running on symbolic values.
+#[derive(Debug, Clone)]
+pub struct Symbolic {
+    mem: Memory,
+    // Cells holding values that are not constant. Their
+    // words in `mem` are zero.
+    exprs: BTreeMap<usize, Expr>,
+    inputs: VecDeque<Expr>,
+    ip: usize,
+    rel_base: i64,
+    fuel: Option<u64>,
+}
+
+impl Symbolic {
+    /// Make a symbolic machine for the given program, with
+    /// every value concrete to begin with.
+    pub fn new(prog: Vec<i64>) -> Self {
+        Self {
+            mem: Memory::from(prog),
+            exprs: BTreeMap::new(),
+            inputs: VecDeque::new(),
+            ip: 0,
+            rel_base: 0,
+            fuel: None,
+        }
+    }
+
+    /// Make a symbolic machine in the state of the given
+    /// machine. Values too large for an `i64` become
+    /// `Expr::Unknown`.
+    pub fn from_intcode(machine: &Intcode) -> Self {
+        let mut sym = Self {
+            mem: machine.prog.clone(),
+            exprs: BTreeMap::new(),
+            inputs: machine
+                .inputs
+                .iter()
+                .cloned()
+                .map(Expr::constant)
+                .collect(),
+            ip: machine.ip,
+            rel_base: machine.rel_base,
+            fuel: None,
+        };
+        let big: Vec<usize> =
+            machine.prog.big_values().map(|(addr, _)| addr).collect();
+        for addr in big {
+            sym.set(addr, Expr::Unknown);
+        }
+        sym
+    }
+
+    /// Builder limiting the run to the given number of
+    /// instructions.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Make the memory cell at the given address a new
+    /// symbol with the given name, and return the symbol.
+    pub fn symbol_at(&mut self, addr: usize, name: &str) -> Sym {
+        let sym = Sym::new(name);
+        self.set(addr, Expr::symbol(sym.clone()));
+        sym
+    }
+
+    /// Add an input that is a new symbol with the given
+    /// name, and return the symbol.
+    pub fn symbolic_input(&mut self, name: &str) -> Sym {
+        let sym = Sym::new(name);
+        self.inputs.push_back(Expr::symbol(sym.clone()));
+        sym
+    }
+
+    /// Add a concrete input.
+    pub fn add_input(&mut self, input: i64) {
+        self.inputs.push_back(Expr::constant(input));
+    }
+
+    /// Value at the given address. Memory past the end is
+    /// zero.
+    pub fn get(&self, addr: usize) -> Expr {
+        match self.exprs.get(&addr) {
+            Some(expr) => expr.clone(),
+            None => Expr::constant(self.mem.get(addr).unwrap_or(0)),
+        }
+    }
+
+    /// Store the given value at the given address,
+    /// extending memory if needed.
+    pub fn set(&mut self, addr: usize, val: Expr) {
+        self.mem.resize(addr + 1);
+        match val.as_const() {
+            Some(c) => {
+                self.mem.set(addr, c);
+                self.exprs.remove(&addr);
+            }
+            None => {
+                self.mem.set(addr, 0);
+                self.exprs.insert(addr, val);
+            }
+        }
+    }
+
+    /// Address of the next instruction to be executed.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// Current relative base.
+    pub fn rel_base(&self) -> i64 {
+        self.rel_base
+    }
+
+    /// Run until the program halts, needs input, produces
+    /// output or runs out of fuel.
+    pub fn run(&mut self) -> Result<SymTerminus, SymError> {
+        loop {
+            if let Some(t) = self.step()? {
+                return Ok(t);
+            }
+        }
+    }
+
+    /// Execute a single instruction. Returns a terminus if
+    /// the instruction suspended the run.
+    pub fn step(&mut self) -> Result<Option<SymTerminus>, SymError> {
+        match self.fuel {
+            Some(0) => return Ok(Some(SymTerminus::OutOfFuel)),
+            Some(ref mut fuel) => *fuel -= 1,
+            None => (),
+        }
+        let ip = self.ip;
+        self.execute().map_err(|fault| SymError { ip, fault })
+    }
+
+    // Execute a single instruction, following the checks
+    // of `Intcode::execute()`.
+    fn execute(&mut self) -> Result<Option<SymTerminus>, SymFault> {
+        let ip = self.ip;
+        if ip >= self.mem.len() {
+            return Err(Fault::RanOffEnd.into());
+        }
+        let word = self.concrete(self.get(ip), "instruction")?;
+        if word < 0 {
+            return Err(Fault::IllegalOpcode(word).into());
+        }
+        let op = Opcode::try_new(word as usize % 100)
+            .ok_or(Fault::IllegalOpcode(word % 100))?;
+        let modes = word as usize / 100;
+        use Opcode::*;
+        self.ip = match op {
+            Halt => return Ok(Some(SymTerminus::Halted)),
+            Add | Mul | LessThan | Equals => {
+                let a = self.load(modes, 0)?;
+                let b = self.load(modes, 1)?;
+                let val =
+                    Expr::apply(op, a, b).ok_or(Fault::Overflow)?;
+                self.store(modes, 2, val)?;
+                self.finish(modes, 3)?
+            }
+            Input => {
+                let input = match self.inputs.front() {
+                    Some(input) => input.clone(),
+                    None => return Ok(Some(SymTerminus::NeedInput)),
+                };
+                self.store(modes, 0, input)?;
+                let next = self.finish(modes, 1)?;
+                self.inputs.pop_front();
+                next
+            }
+            Output => {
+                let output = self.load(modes, 0)?;
+                self.ip = self.finish(modes, 1)?;
+                return Ok(Some(SymTerminus::HaveOutput(output)));
+            }
+            JumpIfTrue | JumpIfFalse => {
+                let test = self.load(modes, 0)?;
+                let test = self.concrete(test, "jump condition")? != 0;
+                if test == (op == JumpIfTrue) {
+                    let target = self.load(modes, 1)?;
+                    let target =
+                        self.concrete(target, "jump target")?;
+                    if target < 0 {
+                        return Err(Fault::NegativeJump(target).into());
+                    }
+                    target as usize
+                } else {
+                    self.finish(modes, 2)?
+                }
+            }
+            RBO => {
+                let offset = self.load(modes, 0)?;
+                let offset =
+                    self.concrete(offset, "relative base offset")?;
+                self.rel_base = self
+                    .rel_base
+                    .checked_add(offset)
+                    .ok_or(Fault::Overflow)?;
+                self.finish(modes, 1)?
+            }
+        };
+        Ok(None)
+    }
+
+    // The constant value of the given expression, or a
+    // fault naming what it was needed for.
+    fn concrete(
+        &self,
+        val: Expr,
+        what: &'static str,
+    ) -> Result<i64, SymFault> {
+        val.as_const().ok_or(SymFault::Symbolic(what))
+    }
+
+    // Mode and raw word of the given operand of the current
+    // instruction, extending memory if needed.
+    fn operand(
+        &mut self,
+        modes: usize,
+        index: usize,
+    ) -> Result<(OpndMode, Expr), SymFault> {
+        let addr = self.ip + 1 + index;
+        self.mem.resize(addr + 1);
+        let mode = modes / 10usize.pow(index as u32) % 10;
+        let mode =
+            OpndMode::try_new(mode).ok_or(Fault::IllegalMode(mode))?;
+        Ok((mode, self.get(addr)))
+    }
+
+    // Address given by a positional or relative operand, or
+    // `None` if it is symbolic. Extends memory if needed.
+    fn address(
+        &mut self,
+        mode: OpndMode,
+        opnd: &Expr,
+    ) -> Result<Option<usize>, SymFault> {
+        let mut opnd = match opnd.as_const() {
+            Some(opnd) => opnd,
+            None => return Ok(None),
+        };
+        if mode == OpndMode::Rel {
+            opnd = opnd
+                .checked_add(self.rel_base)
+                .ok_or(Fault::Overflow)?;
+        }
+        if opnd < 0 {
+            return Err(Fault::NegativeAddress(opnd).into());
+        }
+        let addr = opnd as usize;
+        self.mem.resize(addr + 1);
+        Ok(Some(addr))
+    }
+
+    // Value of the given operand of the current instruction.
+    fn load(
+        &mut self,
+        modes: usize,
+        index: usize,
+    ) -> Result<Expr, SymFault> {
+        let (mode, opnd) = self.operand(modes, index)?;
+        if mode == OpndMode::Imm {
+            return Ok(opnd);
+        }
+        Ok(match self.address(mode, &opnd)? {
+            Some(addr) => self.get(addr),
+            None => Expr::Unknown,
+        })
+    }
+
+    // Store to the given operand of the current instruction.
+    fn store(
+        &mut self,
+        modes: usize,
+        index: usize,
+        val: Expr,
+    ) -> Result<(), SymFault> {
+        let (mode, opnd) = self.operand(modes, index)?;
+        if mode == OpndMode::Imm {
+            return Err(Fault::StoreToImmediate.into());
+        }
+        match self.address(mode, &opnd)? {
+            Some(addr) => self.set(addr, val),
+            None => return Err(SymFault::Symbolic("store address")),
+        }
+        Ok(())
+    }
+
+    // Check that the modes of an instruction with the given
+    // number of operands are all used, and return the
+    // address of the next instruction.
+    fn finish(
+        &self,
+        modes: usize,
+        nopnds: usize,
+    ) -> Result<usize, SymFault> {
+        if modes / 10usize.pow(nopnds as u32) != 0 {
+            return Err(Fault::UnusedModeBits.into());
+        }
+        Ok(self.ip + 1 + nopnds)
+    }
+}
+
+/// Find values for the given symbols, each within its
+/// range, for which the expression equals the target.
+/// Returns the values in the order the symbols are given,
+/// or `None` if there are none, if the expression is not
+/// known, or if it has symbols not given. When there are
+/// several solutions, this is the first in order of the
+/// values of the symbols other than the one solved for
+/// directly, then smallest.
+pub fn solve(
+    expr: &Expr,
+    target: i64,
+    domains: &[(Sym, RangeInclusive<i64>)],
+) -> Option<Vec<i64>> {
+    let given = |sym: &Sym| domains.iter().any(|(s, _)| s == sym);
+    if !expr.is_known() || !expr.symbols().iter().all(given) {
+        return None;
+    }
+    if domains.iter().any(|(_, range)| range.is_empty()) {
+        return None;
+    }
+    let residual = match (expr.as_poly(), target.checked_neg()) {
+        (Some(poly), Some(neg)) => {
+            poly.checked_add(&Poly::constant(neg))
+        }
+        _ => None,
+    };
+    if let Some(residual) = residual {
+        if let Some(pivot) = pivot(&residual, domains) {
+            return search(domains, Some(pivot), |values| {
+                let x = root(&residual, domains, values, pivot)?;
+                values[pivot] = x;
+                Some(())
+            });
+        }
+    }
+    let mut assignment: BTreeMap<Sym, i64> = BTreeMap::new();
+    search(domains, None, |values| {
+        for ((sym, _), &val) in domains.iter().zip(values.iter()) {
+            assignment.insert(sym.clone(), val);
+        }
+        if expr.eval(&assignment)? == target {
+            Some(())
+        } else {
+            None
+        }
+    })
+}
+
+// Index of the symbol to solve for directly: one of the
+// lowest degree, at most quadratic, with the largest range.
+// Symbols not in the polynomial can take any value, and
+// are solved for as degree zero. Returns `None` if every
+// symbol that appears has a higher degree.
+fn pivot(
+    poly: &Poly,
+    domains: &[(Sym, RangeInclusive<i64>)],
+) -> Option<usize> {
+    let size = |range: &RangeInclusive<i64>| {
+        *range.end() as i128 - *range.start() as i128
+    };
+    let candidates = domains
+        .iter()
+        .enumerate()
+        .map(|(i, (sym, range))| (poly.degree_in(sym), i, size(range)))
+        .filter(|&(degree, _, _)| degree <= 2);
+    let best = candidates
+        .filter(|&(degree, _, _)| degree > 0)
+        .min_by_key(|&(degree, i, size)| (degree, Reverse(size), i));
+    match best {
+        Some((_, i, _)) => Some(i),
+        None if poly.symbols().is_empty() => {
+            if domains.is_empty() {
+                None
+            } else {
+                Some(0)
+            }
+        }
+        None => None,
+    }
+}
+
+// Try each combination of values of the symbols other than
+// `fixed`, in order, until `try_values` succeeds. Values are
+// in the order of `domains`; `try_values` may set the value
+// of the `fixed` symbol.
+fn search<F>(
+    domains: &[(Sym, RangeInclusive<i64>)],
+    fixed: Option<usize>,
+    mut try_values: F,
+) -> Option<Vec<i64>>
+where
+    F: FnMut(&mut Vec<i64>) -> Option<()>,
+{
+    let mut values: Vec<i64> =
+        domains.iter().map(|(_, range)| *range.start()).collect();
+    loop {
+        let mut trial = values.clone();
+        if try_values(&mut trial).is_some() {
+            return Some(trial);
+        }
+        // Step to the next combination, last symbol
+        // fastest.
+        let mut next = false;
+        for i in (0..values.len()).rev() {
+            if Some(i) == fixed {
+                continue;
+            }
+            let range = &domains[i].1;
+            if values[i] < *range.end() {
+                values[i] += 1;
+                next = true;
+                break;
+            }
+            values[i] = *range.start();
+        }
+        if !next {
+            return None;
+        }
+    }
+}
+
+// Smallest value in range of the symbol with index `pivot`
+// at which the polynomial is zero, given values for the
+// other symbols. The polynomial is at most quadratic in
+// the pivot.
+fn root(
+    poly: &Poly,
+    domains: &[(Sym, RangeInclusive<i64>)],
+    values: &[i64],
+    pivot: usize,
+) -> Option<i64> {
+    let (x, range) = &domains[pivot];
+    let mut coeffs = [0i128; 3];
+    for (mono, c) in poly.terms() {
+        let mut term = c as i128;
+        let mut power = 0;
+        for sym in mono {
+            if sym == x {
+                power += 1;
+                continue;
+            }
+            let i = domains.iter().position(|(s, _)| s == sym)?;
+            term = term.checked_mul(values[i] as i128)?;
+        }
+        coeffs[power] = coeffs[power].checked_add(term)?;
+    }
+    let in_range = |r: i128| {
+        if r >= *range.start() as i128 && r <= *range.end() as i128 {
+            Some(r as i64)
+        } else {
+            None
+        }
+    };
+    // Root of `a*x + b`, if integral.
+    let linear = |a: i128, b: i128| {
+        if b.checked_rem(a)? == 0 {
+            in_range(b.checked_neg()?.checked_div(a)?)
+        } else {
+            None
+        }
+    };
+    match coeffs {
+        [0, 0, 0] => Some(*range.start()),
+        [_, 0, 0] => None,
+        [c, b, 0] => linear(b, c),
+        [c, b, a] => {
+            let disc = b
+                .checked_mul(b)?
+                .checked_sub(a.checked_mul(c)?.checked_mul(4)?)?;
+            let s = isqrt(disc)?;
+            if s.checked_mul(s)? != disc {
+                return None;
+            }
+            let a2 = a.checked_mul(2)?;
+            let r1 = b.checked_sub(s).and_then(|b| linear(a2, b));
+            let r2 = b.checked_add(s).and_then(|b| linear(a2, b));
+            match (r1, r2) {
+                (Some(r1), Some(r2)) => Some(r1.min(r2)),
+                (r1, r2) => r1.or(r2),
+            }
+        }
+    }
+}
+
+// Integer square root, or `None` if negative.
+fn isqrt(n: i128) -> Option<i128> {
+    if n < 0 {
+        return None;
+    }
+    let mut s = (n as f64).sqrt() as i128;
+    while s.checked_mul(s).is_none_or(|sq| sq > n) {
+        s -= 1;
+    }
+    while (s + 1).checked_mul(s + 1).is_some_and(|sq| sq <= n) {
+        s += 1;
+    }
+    Some(s)
+}
+
+#[cfg(test)]
+fn run_to_output(sym: &mut Symbolic) -> Expr {
+    match sym.run() {
+        Ok(SymTerminus::HaveOutput(out)) => out,
+        r => panic!("expected output, got {:?}", r),
+    }
+}
+
+#[test]
+fn test_symbolic_day02() {
+    // Shaped like a Day 2 program: the noun and verb are
+    // first used as addresses for a sum that is then
+    // overwritten. Part 2 asks for the noun and verb giving
+    // 19690720, which one symbolic run finds instead of a
+    // search over all 10,000 pairs.
+    let prog = vec![
+        1, 0, 0, 3, // [3] = [noun] + [verb]
+        1, 1, 2, 3, // [3] = noun + verb
+        2, 1, 21, 0, // [0] = noun * [21]
+        1, 0, 2, 0, // [0] = [0] + verb
+        1, 0, 22, 0, // [0] = [0] + [22]
+        99, 230_400, 337_061,
+    ];
+    let ic = Intcode::new(prog);
+    let mut sym = Symbolic::from_intcode(&ic);
+    let noun = sym.symbol_at(1, "noun");
+    let verb = sym.symbol_at(2, "verb");
+    assert_eq!(sym.run(), Ok(SymTerminus::Halted));
+    let result = sym.get(0);
+    assert_eq!(result.to_string(), "230400*noun + verb + 337061");
+    assert_eq!(sym.get(3).to_string(), "noun + verb");
+    let domains = [(noun, 0..=99), (verb, 0..=99)];
+    let soln = solve(&result, 19_690_720, &domains).unwrap();
+    assert_eq!(soln, vec![84, 59]);
+
+    // Symbolic arithmetic does not fault on overflow, so
+    // check the answer for real.
+    let mut ic = ic.clone();
+    ic.poke(1, soln[0]);
+    ic.poke(2, soln[1]);
+    ic.run();
+    assert_eq!(ic.peek(0), 19_690_720);
+
+    assert_eq!(solve(&result, 1, &domains), None);
+    assert_eq!(solve(&Expr::Unknown, 1, &domains), None);
+    assert_eq!(solve(&result, 337_061, &domains[..1]), None);
+}
+
+#[test]
+fn test_symbolic_compiled() {
+    let prog = super::compile(
+        "fn main() {
+             var x = input();
+             var y = input();
+             output(3 * x * x - 2 * x + 7);
+             output((x < 5) + y);
+             output(x * y);
+         }",
+    )
+    .unwrap();
+    let mut sym = Symbolic::new(prog);
+    let x = sym.symbolic_input("x");
+    let y = sym.symbolic_input("y");
+
+    let quad = run_to_output(&mut sym);
+    assert_eq!(quad.to_string(), "3*x^2 - 2*x + 7");
+    let domains = [(x.clone(), -1_000_000..=1_000_000)];
+    assert_eq!(solve(&quad, 4_565_807, &domains), Some(vec![1234]));
+    assert_eq!(solve(&quad, 4_565_808, &domains), None);
+
+    let mixed = run_to_output(&mut sym);
+    assert_eq!(mixed.to_string(), "((x < 5) + y)");
+    let domains = [(x.clone(), 0..=9), (y.clone(), 0..=9)];
+    assert_eq!(solve(&mixed, 0, &domains), Some(vec![5, 0]));
+    assert_eq!(solve(&mixed, 10, &domains), Some(vec![0, 9]));
+
+    let product = run_to_output(&mut sym);
+    let domains = [(x, 2..=100), (y, 2..=100)];
+    assert_eq!(solve(&product, 91, &domains), Some(vec![13, 7]));
+    assert_eq!(sym.run(), Ok(SymTerminus::Halted));
+}
+
+#[test]
+fn test_symbolic_stuck() {
+    let prog = super::compile(
+        "fn main() {
+             var x = input();
+             if x < 5 {
+                 output(1);
+             }
+         }",
+    )
+    .unwrap();
+    let mut sym = Symbolic::new(prog);
+    sym.symbolic_input("x");
+    let err = sym.run().unwrap_err();
+    assert_eq!(err.fault, SymFault::Symbolic("jump condition"));
+
+    // Store through a symbolic address.
+    let mut sym = Symbolic::new(vec![1101, 1, 1, 0, 99]);
+    sym.symbol_at(3, "p");
+    let err = sym.run().unwrap_err();
+    assert_eq!(err.to_string(), "ip 0: symbolic store address");
+
+    let mut sym = Symbolic::new(vec![1, 0, 0, 0, 98]);
+    sym.symbol_at(0, "a");
+    let err = sym.run().unwrap_err();
+    assert_eq!(err.fault, SymFault::Symbolic("instruction"));
+
+    // Concrete faults still fault.
+    let mut sym = Symbolic::new(vec![11101, 1, 1, 0, 99]);
+    let err = sym.run().unwrap_err();
+    assert_eq!(err.fault, SymFault::Fault(Fault::StoreToImmediate));
+}
@@ -0,0 +1,323 @@
+//! Intcode execution tracing.
+//!
+//! When tracing is turned on with `Intcode::set_trace()`,
+//! every instruction executed produces a `TraceRecord`
+//! written to the given sink. The `Human` format is meant
+//! for reading; the `Json` format has one JSON object per
+//! line, suitable for diffing the executions of two builds
+//! or two inputs.
+//!
+//! Tracing is independent of the compile-time `trace!`
+//! macro. Clones of a traced `Intcode` share the sink.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::io::Write;
+//! use std::sync::{Arc, Mutex};
+//!
+//! // A sink we can look at after tracing.
+//! #[derive(Clone, Default)]
+//! struct Buf(Arc<Mutex<Vec<u8>>>);
+//! impl Write for Buf {
+//!     fn write(&mut self, b: &[u8]) -> std::io::Result<usize> {
+//!         self.0.lock().unwrap().write(b)
+//!     }
+//!     fn flush(&mut self) -> std::io::Result<()> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let buf = Buf::default();
+//! let mut ic = aoc::Intcode::new(vec![1002, 4, 3, 4, 33]);
+//! ic.set_trace(buf.clone(), aoc::TraceFormat::Json);
+//! ic.run();
+//! let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+//! assert_eq!(
+//!     text.lines().next().unwrap(),
+//!     r#"{"n":0,"ip":0,"insn":"mul [4], #3, [4]","reads":[33,3],"write":[4,99],"rel_base":0}"#,
+//! );
+//! ```
+
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use super::{Fault, Intcode, IntcodeError, Operand, Terminus};
+
+/// Output format for execution traces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One aligned line of text per instruction.
+    Human,
+    /// One JSON object per line per instruction.
+    Json,
+}
+
+/// Record of the execution of a single instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Sequence number of this record in the trace.
+    pub n: u64,
+    /// Address of the instruction.
+    pub ip: usize,
+    /// Disassembly of the instruction, or `None` if it did
+    /// not decode.
+    pub insn: Option<String>,
+    /// Values of the operands read by the instruction.
+    pub reads: Vec<i64>,
+    /// Address and value stored by the instruction, if
+    /// any.
+    pub write: Option<(usize, i64)>,
+    /// Relative base after the instruction.
+    pub rel_base: i64,
+    /// Suspension caused by the instruction, if any.
+    pub stop: Option<Terminus>,
+    /// Fault caused by the instruction, if any.
+    pub fault: Option<Fault>,
+}
+
+impl TraceRecord {
+    /// Render this record as a single-line JSON object.
+    /// Absent fields are omitted.
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\"n\":{},\"ip\":{}", self.n, self.ip);
+        if let Some(ref insn) = self.insn {
+            // Disassembly never contains characters that
+            // need escaping.
+            json += &format!(",\"insn\":\"{}\"", insn);
+        }
+        let reads: Vec<String> =
+            self.reads.iter().map(|r| r.to_string()).collect();
+        json += &format!(",\"reads\":[{}]", reads.join(","));
+        if let Some((addr, val)) = self.write {
+            json += &format!(",\"write\":[{},{}]", addr, val);
+        }
+        json += &format!(",\"rel_base\":{}", self.rel_base);
+        match self.stop {
+            Some(Terminus::Halted) => json += ",\"stop\":\"halted\"",
+            Some(Terminus::NeedInput) => json += ",\"stop\":\"input\"",
+            Some(Terminus::HaveOutput(v)) => {
+                json += &format!(",\"output\":{}", v)
+            }
+            Some(Terminus::OutOfFuel) => json += ",\"stop\":\"fuel\"",
+            None => (),
+        }
+        if let Some(ref fault) = self.fault {
+            let fault = json_escape(&fault.to_string());
+            json += &format!(",\"fault\":\"{}\"", fault);
+        }
+        json.push('}');
+        json
+    }
+}
+
+// Escape a string for use inside a JSON string literal.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            c if (c as u32) < 0x20 => {
+                escaped += &format!("\\u{:04x}", c as u32)
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let insn = self.insn.as_deref().unwrap_or("?");
+        write!(f, "{:8} {:6}  {:<28}", self.n, self.ip, insn)?;
+        if !self.reads.is_empty() {
+            write!(f, " reads={:?}", self.reads)?;
+        }
+        if let Some((addr, val)) = self.write {
+            write!(f, " [{}]<-{}", addr, val)?;
+        }
+        write!(f, " rb={}", self.rel_base)?;
+        match self.stop {
+            Some(Terminus::Halted) => write!(f, " halted")?,
+            Some(Terminus::NeedInput) => write!(f, " need input")?,
+            Some(Terminus::HaveOutput(v)) => {
+                write!(f, " output {}", v)?
+            }
+            Some(Terminus::OutOfFuel) => write!(f, " out of fuel")?,
+            None => (),
+        }
+        if let Some(ref fault) = self.fault {
+            write!(f, " fault: {}", fault)?;
+        }
+        Ok(())
+    }
+}
+
+/// Trace destination attached to an `Intcode`.
+#[derive(Clone)]
+pub(super) struct Tracer {
+    sink: Arc<Mutex<dyn Write + Send>>,
+    format: TraceFormat,
+    count: u64,
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tracer({:?}, {})", self.format, self.count)
+    }
+}
+
+impl Intcode {
+    /// Trace every instruction subsequently executed to the
+    /// given sink in the given format. Write errors on the
+    /// sink are ignored.
+    pub fn set_trace<W>(&mut self, sink: W, format: TraceFormat)
+    where
+        W: Write + Send + 'static,
+    {
+        self.tracer = Some(Tracer {
+            sink: Arc::new(Mutex::new(sink)),
+            format,
+            count: 0,
+        });
+    }
+
+    /// Stop tracing.
+    pub fn clear_trace(&mut self) {
+        self.tracer = None;
+    }
+
+    // Step with tracing: look at the instruction before
+    // executing it, then write a record of what it did.
+    pub(super) fn traced_step(
+        &mut self,
+    ) -> Result<Option<Terminus>, IntcodeError> {
+        let ip = self.ip;
+        let rel_base = self.rel_base;
+        let insn = self.decode(ip);
+        let reads = match insn {
+            Some(ref insn) => {
+                let store = insn.op.store_opnd();
+                let mem = &self.prog;
+                let value = |addr: i64| {
+                    if addr < 0 {
+                        0
+                    } else {
+                        mem.get(addr as usize).unwrap_or(0)
+                    }
+                };
+                insn.opnds
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, _)| Some(i) != store)
+                    .map(|(_, opnd)| match *opnd {
+                        Operand::Imm(v) => v,
+                        Operand::Pos(a) => value(a),
+                        // Overflow faults when executed; there is
+                        // no value read.
+                        Operand::Rel(o) => {
+                            o.checked_add(rel_base).map_or(0, value)
+                        }
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        let result = self.untraced_step();
+        let write =
+            self.last_store.map(|a| (a, self.prog.get(a).unwrap()));
+
+        let tracer =
+            self.tracer.as_mut().expect("traced step without tracer");
+        let record = TraceRecord {
+            n: tracer.count,
+            ip,
+            insn: insn.map(|insn| insn.to_string()),
+            reads,
+            write,
+            rel_base: self.rel_base,
+            stop: result.as_ref().ok().cloned().and_then(|t| t),
+            fault: result.as_ref().err().map(|e| e.fault.clone()),
+        };
+        tracer.count += 1;
+        let mut sink = tracer.sink.lock().expect("trace sink poisoned");
+        let _ = match tracer.format {
+            TraceFormat::Human => writeln!(sink, "{}", record),
+            TraceFormat::Json => writeln!(sink, "{}", record.to_json()),
+        };
+        result
+    }
+}
+
+// A sink the tests can look at after tracing.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(super) struct Buf(pub(super) Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for Buf {
+    fn write(&mut self, b: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(b)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_trace_human() {
+    let buf = Buf::default();
+    let prog = vec![109, 7, 203, 0, 204, 0, 99];
+    let mut ic = Intcode::new(prog);
+    ic.set_trace(buf.clone(), TraceFormat::Human);
+    assert_eq!(ic.run(), Terminus::NeedInput);
+    ic.add_input(5);
+    assert_eq!(ic.run(), Terminus::HaveOutput(5));
+    ic.clear_trace();
+    assert_eq!(ic.run(), Terminus::Halted);
+
+    let text =
+        String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = text.lines().map(|l| l.trim_end()).collect();
+    #[rustfmt::skip]
+    let expected = vec![
+        "       0      0  rbo #7                       reads=[7] rb=7",
+        "       1      2  in rb+0                      rb=7 need input",
+        "       2      2  in rb+0                      [7]<-5 rb=7",
+        "       3      4  out rb+0                     reads=[5] rb=7 output 5",
+    ];
+    assert_eq!(lines, expected);
+}
+
+#[test]
+fn test_trace_json() {
+    let buf = Buf::default();
+    let prog = vec![109, 1, 204, i64::MAX, 99];
+    let mut ic = Intcode::new(prog);
+    ic.set_trace(buf.clone(), TraceFormat::Json);
+    let err = ic.try_run().unwrap_err();
+
+    let text =
+        String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    #[rustfmt::skip]
+    let expected = vec![
+        r#"{"n":0,"ip":0,"insn":"rbo #1","reads":[1],"rel_base":1}"#.to_string(),
+        format!(
+            r#"{{"n":1,"ip":2,"insn":"out rb+{}","reads":[0],"rel_base":1,"fault":"{}"}}"#,
+            i64::MAX,
+            err.fault,
+        ),
+    ];
+    assert_eq!(lines, expected);
+
+    // Fault messages are escaped.
+    assert_eq!(
+        json_escape("said \"no\" \\ twice\n\u{1}"),
+        r#"said \"no\" \\ twice\n\u0001"#,
+    );
+}